@@ -0,0 +1,254 @@
+// cargo bench --bench bvh
+//
+// Builds BVHs over the triangles of scenes/david/david.stl and traces a grid
+// of rays at it from a few sides, with the binned SAH builder of `bvh` and
+// with the median-split builder it replaced (kept below as the baseline).
+// Both have to find the same hits.
+
+use std::fs::File;
+use std::time::{Duration, Instant};
+
+use glam::Vec3;
+
+use cosmo::aabb::AABB;
+use cosmo::bvh::Bvh;
+use cosmo::engine::{Thing, Triangle};
+use cosmo::util::Ray;
+
+const RAYS: usize = 300;
+const RUNS: usize = 5;
+
+// The builder before the SAH one: median split along the longest axis of the
+// centroids, boxed nodes, both children always visited.
+mod median {
+    use std::cmp::Ordering;
+
+    use glam::Vec3;
+
+    use cosmo::aabb::AABB;
+    use cosmo::engine::Thing;
+    use cosmo::util::{Color, Ray};
+
+    const LEAF_MAX: usize = 4;
+
+    pub enum Bvh {
+        Internal {
+            aabb: AABB,
+            left: Box<Bvh>,
+            right: Box<Bvh>,
+        },
+        Leaf {
+            aabb: AABB,
+            indices: Vec<usize>,
+        },
+    }
+
+    impl Bvh {
+        pub fn build(children: &[Box<dyn Thing>]) -> Self {
+            let child_aabbs: Vec<AABB> = children
+                .iter()
+                .map(|c| {
+                    let mut a = AABB::new();
+                    c.update_aabb(&mut a);
+                    a
+                })
+                .collect();
+            build_recursive((0..children.len()).collect(), &child_aabbs)
+        }
+
+        pub fn intersect(
+            &self,
+            ray: &Ray,
+            children: &[Box<dyn Thing>],
+        ) -> Option<(Vec3, Vec3, Color)> {
+            intersect_inner(self, ray, children).map(|(p, n, c, _)| (p, n, c))
+        }
+    }
+
+    fn build_recursive(mut indices: Vec<usize>, child_aabbs: &[AABB]) -> Bvh {
+        let mut aabb = AABB::new();
+        for &i in &indices {
+            aabb.merge(&child_aabbs[i]);
+        }
+        if indices.len() <= LEAF_MAX {
+            return Bvh::Leaf { aabb, indices };
+        }
+        let mut centroids = AABB::new();
+        for &i in &indices {
+            if !child_aabbs[i].is_empty() {
+                centroids.update(&child_aabbs[i].centroid());
+            }
+        }
+        let extent = centroids.max() - centroids.min();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        indices.sort_by(|&a, &b| {
+            let ca = child_aabbs[a].centroid()[axis];
+            let cb = child_aabbs[b].centroid()[axis];
+            ca.partial_cmp(&cb).unwrap_or(Ordering::Equal)
+        });
+        let right = indices.split_off(indices.len() / 2);
+        Bvh::Internal {
+            aabb,
+            left: Box::new(build_recursive(indices, child_aabbs)),
+            right: Box::new(build_recursive(right, child_aabbs)),
+        }
+    }
+
+    fn intersect_inner(
+        node: &Bvh,
+        ray: &Ray,
+        children: &[Box<dyn Thing>],
+    ) -> Option<(Vec3, Vec3, Color, f32)> {
+        match node {
+            Bvh::Internal { aabb, left, right } => {
+                if !aabb.intersect(ray) {
+                    return None;
+                }
+                let l = intersect_inner(left, ray, children);
+                let r = intersect_inner(right, ray, children);
+                match (l, r) {
+                    (Some(a), Some(b)) => Some(if a.3 <= b.3 { a } else { b }),
+                    (a, b) => a.or(b),
+                }
+            }
+            Bvh::Leaf { aabb, indices } => {
+                if !aabb.intersect(ray) {
+                    return None;
+                }
+                let mut best: Option<(Vec3, Vec3, Color, f32)> = None;
+                for &i in indices {
+                    if let Some((p, n, c)) = children[i].intersect(ray) {
+                        let t = (p - ray.p).dot(ray.d);
+                        if best.is_none_or(|b| t < b.3) {
+                            best = Some((p, n, c, t));
+                        }
+                    }
+                }
+                best
+            }
+        }
+    }
+}
+
+fn load_david() -> Vec<Box<dyn Thing>> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/david/david.stl");
+    let mut file = File::open(path).expect("cannot open david.stl");
+    let stl = stl_io::read_stl(&mut file).expect("cannot read david.stl");
+    stl.faces
+        .iter()
+        .map(|face| {
+            let v = face
+                .vertices
+                .map(|i| Vec3::from_array(stl.vertices[i].into()));
+            Box::new(Triangle::new(v[0], v[1], v[2], '.')) as Box<dyn Thing>
+        })
+        .collect()
+}
+
+// A RAYS by RAYS grid of rays at the model from each of a few sides, each
+// grid just covering it.
+fn rays(bounds: &AABB) -> Vec<Ray> {
+    let center = bounds.centroid();
+    let radius = (bounds.max() - bounds.min()).length() * 0.5;
+    let sides = [
+        Vec3::new(1., 0.2, 0.1),
+        Vec3::new(-0.3, 1., 0.2),
+        Vec3::new(-1., -0.5, -0.3),
+        Vec3::new(0.2, -0.4, 1.),
+    ];
+    let mut rays = vec![];
+    for side in sides {
+        let back = side.normalize();
+        let right = back.cross(Vec3::Z).normalize();
+        let up = right.cross(back);
+        let eye = center + back * radius * 3.;
+        for i in 0..RAYS {
+            for j in 0..RAYS {
+                let u = (i as f32 / (RAYS - 1) as f32 * 2. - 1.) * radius;
+                let v = (j as f32 / (RAYS - 1) as f32 * 2. - 1.) * radius;
+                let aim = center + right * u + up * v;
+                rays.push(Ray {
+                    p: eye,
+                    d: (aim - eye).normalize(),
+                });
+            }
+        }
+    }
+    rays
+}
+
+// The fastest of RUNS runs of `f`, and what it returned.
+fn time<T>(mut f: impl FnMut() -> T) -> (Duration, T) {
+    let mut best = Duration::MAX;
+    let mut out = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let r = f();
+        best = best.min(start.elapsed());
+        out = Some(r);
+    }
+    (best, out.unwrap())
+}
+
+fn report(name: &str, build: Duration, trace: Duration, rays: usize, hits: usize) {
+    println!(
+        "{:<8} build {:>8.2}ms  trace {:>9.2}ms  {:>6.2} Mrays/s  {} hits",
+        name,
+        build.as_secs_f64() * 1e3,
+        trace.as_secs_f64() * 1e3,
+        rays as f64 / trace.as_secs_f64() / 1e6,
+        hits
+    );
+}
+
+fn main() {
+    let children = load_david();
+    let mut bounds = AABB::new();
+    for c in &children {
+        c.update_aabb(&mut bounds);
+    }
+    let rays = rays(&bounds);
+    println!("{} triangles, {} rays", children.len(), rays.len());
+
+    let (build, old) = time(|| median::Bvh::build(&children));
+    let (trace, old_hits) = time(|| {
+        rays.iter()
+            .map(|r| old.intersect(r, &children).map(|h| h.0))
+            .collect::<Vec<_>>()
+    });
+    report(
+        "median",
+        build,
+        trace,
+        rays.len(),
+        old_hits.iter().flatten().count(),
+    );
+
+    let (build, new) = time(|| Bvh::build(&children));
+    let (trace, new_hits) = time(|| {
+        rays.iter()
+            .map(|r| new.intersect(r, &children).map(|h| h.0))
+            .collect::<Vec<_>>()
+    });
+    report(
+        "sah",
+        build,
+        trace,
+        rays.len(),
+        new_hits.iter().flatten().count(),
+    );
+
+    for (a, b) in old_hits.iter().zip(&new_hits) {
+        match (a, b) {
+            (Some(p), Some(q)) => assert!(p.distance(*q) < 1e-3, "hits differ: {} and {}", p, q),
+            (None, None) => {}
+            _ => panic!("the builders disagree on a hit"),
+        }
+    }
+}
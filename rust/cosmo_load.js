@@ -23,14 +23,42 @@ async function readSTL(name) {
         const arrayBuffer = await response.arrayBuffer();
         return new Uint8Array(arrayBuffer);
     } catch (error) {
-        console.error('Error fetching or reading the STL file:', error);
+        console.error('Error fetching or reading the STL file:', name, error);
+        return null;
     }
 }
 
+// Null if any of them cannot be read, as for readFiles below.
 async function readSTLs(names) {
     var result = [];
     for (const name of names) {
-        result.push(await readSTL(name));
+        const data = await readSTL(name);
+        if (!data) {
+            return null;
+        }
+        result.push(data);
+    }
+    return result;
+}
+
+// Other files the scene refers to by name (INCLUDE, OBJ_FILE, PLY, GLTF and the
+// `.bin` buffers of a glTF file) are looked up by the exact name used in the
+// scene, so they are fetched as-is (extension included). Null if any of them
+// cannot be read: the data is matched to the names by position, so leaving one
+// out would hand every file after it the wrong name.
+async function readFiles(names) {
+    var result = [];
+    for (const name of names) {
+        try {
+            const response = await fetch('/static/cosmo_scenes/' + name);
+            if (!response.ok) {
+                throw new Error(`HTTP error! status: ${response.status}`);
+            }
+            result.push(new Uint8Array(await response.arrayBuffer()));
+        } catch (error) {
+            console.error('Error fetching or reading the file:', name, error);
+            return null;
+        }
     }
     return result;
 }
@@ -47,6 +75,8 @@ async function prepareCosmo(displayEle) {
     const sceneName = displayEle.getAttribute('scene');
     const STLNames = displayEle.hasAttribute('stl-names') ?
         displayEle.getAttribute('stl-names').split(',') : [];
+    const fileNames = displayEle.hasAttribute('file-names') ?
+        displayEle.getAttribute('file-names').split(',') : [];
     const [w, h] = displayEle.getAttribute('dimension').split(',');
     const fr = displayEle.getAttribute('framerate');
     const enableAABB = displayEle.getAttribute('enable-aabb') === 'true';
@@ -60,7 +90,19 @@ async function prepareCosmo(displayEle) {
         return;
     }
     const STLData = await readSTLs(STLNames);
-    const player = PlayerWASM.new(scene, parseInt(fr), parseInt(w), parseInt(h), enableAABB, disableShade, raster, sharpen, STLNames, STLData);
+    const fileData = STLData && await readFiles(fileNames);
+    if (!fileData) {
+        console.error('Failed to load scene:', sceneName);
+        return;
+    }
+    let player;
+    try {
+        player = PlayerWASM.new(scene, parseInt(fr), parseInt(w), parseInt(h), enableAABB, disableShade, raster, sharpen,
+            STLNames.concat(fileNames), STLData.concat(fileData));
+    } catch (error) {
+        console.error('Failed to load scene:', sceneName, error);
+        return;
+    }
     startCosmo(displayEle, player);
     displayEle.addEventListener('click', () => {
         if (displayEle.hasAttribute('intId')) {
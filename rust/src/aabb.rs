@@ -2,54 +2,78 @@ use glam::Vec3;
 
 use crate::util::Ray;
 
-// Axis-aligned Bounding Box
-#[derive(Default)]
+// Axis-aligned Bounding Box, as its min and max corners. A new box is empty:
+// min above max, so the first `update` sets both.
+#[derive(Clone, Copy, Debug)]
 pub struct AABB {
-    x: Vec<f32>,
-    y: Vec<f32>,
-    z: Vec<f32>,
+    bounds: [Vec3; 2],
+}
+
+impl Default for AABB {
+    fn default() -> Self {
+        AABB::new()
+    }
 }
 
 impl AABB {
     pub fn new() -> Self {
         AABB {
-            x: vec![f32::MAX, f32::MIN],
-            y: vec![f32::MAX, f32::MIN],
-            z: vec![f32::MAX, f32::MIN],
+            bounds: [Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)],
         }
     }
 
+    // The box with these corners, as `min` and `max` give them back.
+    pub fn from_corners(min: Vec3, max: Vec3) -> Self {
+        AABB { bounds: [min, max] }
+    }
+
     pub fn clear(&mut self) {
-        self.x = vec![f32::MAX, f32::MIN];
-        self.y = vec![f32::MAX, f32::MIN];
-        self.z = vec![f32::MAX, f32::MIN];
+        *self = AABB::new();
     }
 
     pub fn update(&mut self, p: &Vec3) {
-        self.x[0] = f32::min(p.x, self.x[0]);
-        self.x[1] = f32::max(p.x, self.x[1]);
-        self.y[0] = f32::min(p.y, self.y[0]);
-        self.y[1] = f32::max(p.y, self.y[1]);
-        self.z[0] = f32::min(p.z, self.z[0]);
-        self.z[1] = f32::max(p.z, self.z[1]);
+        self.bounds[0] = self.bounds[0].min(*p);
+        self.bounds[1] = self.bounds[1].max(*p);
     }
 
     pub fn is_empty(&self) -> bool {
-        self.x[0] > self.x[1] || self.y[0] > self.y[1] || self.z[0] > self.z[1]
+        self.bounds[0].cmpgt(self.bounds[1]).any()
     }
 
     pub fn min(&self) -> Vec3 {
-        Vec3::new(self.x[0], self.y[0], self.z[0])
+        self.bounds[0]
     }
 
     pub fn max(&self) -> Vec3 {
-        Vec3::new(self.x[1], self.y[1], self.z[1])
+        self.bounds[1]
     }
 
     pub fn centroid(&self) -> Vec3 {
         (self.min() + self.max()) * 0.5
     }
 
+    // Half the surface area, which is all the SAH needs: the chance of a ray
+    // that hits a box also hitting a box inside it is the ratio of their
+    // areas. Zero for an empty box.
+    pub fn area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let e = self.max() - self.min();
+        e.x * e.y + e.y * e.z + e.z * e.x
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (lo, hi) = (self.min(), self.max());
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            Vec3::new(
+                if i & 1 == 0 { lo.x } else { hi.x },
+                if i & 2 == 0 { lo.y } else { hi.y },
+                if i & 4 == 0 { lo.z } else { hi.z },
+            )
+        })
+    }
+
     // Expand to include `other`. No-op if `other` is empty so that callers
     // can fold a mix of populated and never-touched AABBs without inflating
     // the result.
@@ -57,39 +81,33 @@ impl AABB {
         if other.is_empty() {
             return;
         }
-        self.x[0] = f32::min(other.x[0], self.x[0]);
-        self.x[1] = f32::max(other.x[1], self.x[1]);
-        self.y[0] = f32::min(other.y[0], self.y[0]);
-        self.y[1] = f32::max(other.y[1], self.y[1]);
-        self.z[0] = f32::min(other.z[0], self.z[0]);
-        self.z[1] = f32::max(other.z[1], self.z[1]);
+        self.bounds[0] = self.bounds[0].min(other.bounds[0]);
+        self.bounds[1] = self.bounds[1].max(other.bounds[1]);
     }
 
-    // Standard slab method: clip the ray against three pairs of parallel
-    // planes and keep the overlapping t-interval. The branchless form below
-    // handles negative ray-direction components without an explicit swap,
-    // and infinities arising from axis-aligned rays fall out correctly via
-    // IEEE arithmetic.
     pub fn intersect(&self, ray: &Ray) -> bool {
+        self.entry(ray, ray.d.recip()).is_some()
+    }
+
+    // Standard slab method: clip the ray against three pairs of parallel
+    // planes and keep the overlapping t-interval. Returns where the ray
+    // enters the box (0 if it starts inside), or None if it misses or the
+    // box is behind it. `inv_d` is `1 / ray.d`, worked out once per ray by
+    // callers testing many boxes. The branchless form handles negative
+    // direction components without an explicit swap, and infinities arising
+    // from axis-aligned rays fall out correctly via IEEE arithmetic.
+    pub fn entry(&self, ray: &Ray, inv_d: Vec3) -> Option<f32> {
         let mut tmin = f32::NEG_INFINITY;
         let mut tmax = f32::INFINITY;
-
-        let axes = [
-            (ray.p.x, ray.d.x, self.x[0], self.x[1]),
-            (ray.p.y, ray.d.y, self.y[0], self.y[1]),
-            (ray.p.z, ray.d.z, self.z[0], self.z[1]),
-        ];
-
-        for (o, d, bmin, bmax) in axes {
-            let inv_d = 1.0 / d;
-            let t1 = (bmin - o) * inv_d;
-            let t2 = (bmax - o) * inv_d;
+        for axis in 0..3 {
+            let t1 = (self.bounds[0][axis] - ray.p[axis]) * inv_d[axis];
+            let t2 = (self.bounds[1][axis] - ray.p[axis]) * inv_d[axis];
             tmin = tmin.max(t1.min(t2));
             tmax = tmax.min(t1.max(t2));
-            if tmax < tmin {
-                return false;
-            }
         }
-        tmax >= 0.0
+        if tmax < tmin || tmax < 0.0 {
+            return None;
+        }
+        Some(tmin.max(0.0))
     }
 }
@@ -1,93 +1,373 @@
-use std::cmp::Ordering;
-
 use glam::Vec3;
+use rayon::prelude::*;
 
 use crate::aabb::AABB;
 use crate::engine::Thing;
 use crate::util::{Color, Ray};
 
+// Nodes with at most this many children may become leaves, when the SAH
+// finds splitting them no cheaper.
 const LEAF_MAX: usize = 4;
+// Buckets along each axis that candidate split planes fall between.
+const BINS: usize = 12;
+// Cost of stepping through a node, against 1 for intersecting a child.
+const TRAVERSAL_COST: f32 = 1.;
+// Subtrees over fewer children than this are built on one thread.
+const PARALLEL_MIN: usize = 4096;
+
+// A node of the flattened tree. A leaf (`count` > 0) holds `indices[first..
+// first + count]`; an inner node has its left child right after it and its
+// right child at `first`. Children always come after their parent.
+#[derive(Clone, Copy)]
+struct Node {
+    aabb: AABB,
+    first: u32,
+    count: u32,
+}
 
-pub enum Bvh {
-    Internal {
-        aabb: AABB,
-        left: Box<Bvh>,
-        right: Box<Bvh>,
-    },
-    Leaf {
-        aabb: AABB,
-        indices: Vec<usize>,
-    },
+// Bounding volume hierarchy over a list of children, built with a binned
+// surface area heuristic and stored depth-first in one array.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    // Child indices, grouped so that each leaf's are contiguous.
+    indices: Vec<usize>,
 }
 
 impl Bvh {
     pub fn build(children: &[Box<dyn Thing>]) -> Self {
-        if children.is_empty() {
-            return Bvh::Leaf {
-                aabb: AABB::new(),
-                indices: vec![],
+        let child_aabbs: Vec<AABB> = children.par_iter().map(|c| bounds(c.as_ref())).collect();
+        Bvh::from_bounds(&child_aabbs)
+    }
+
+    // The tree over children with these boxes, built in parallel. The
+    // children are referred to by their index in `child_aabbs`.
+    pub fn from_bounds(child_aabbs: &[AABB]) -> Self {
+        let mut indices: Vec<usize> = (0..child_aabbs.len()).collect();
+        let nodes = if indices.is_empty() {
+            vec![]
+        } else {
+            build_subtree(&mut indices, 0, child_aabbs)
+        };
+        Bvh { nodes, indices }
+    }
+
+    pub fn intersect(&self, ray: &Ray, children: &[Box<dyn Thing>]) -> Option<(Vec3, Vec3, Color)> {
+        self.intersect_with(ray, |i| children[i].intersect(ray))
+    }
+
+    // The nearest of the hits `hit` gives for the children `ray` reaches,
+    // visiting the nearer child of each node first and skipping any box that
+    // starts beyond the nearest hit so far.
+    pub fn intersect_with(
+        &self,
+        ray: &Ray,
+        hit: impl Fn(usize) -> Option<(Vec3, Vec3, Color)>,
+    ) -> Option<(Vec3, Vec3, Color)> {
+        let inv_d = ray.d.recip();
+        let mut best: Option<(Vec3, Vec3, Color)> = None;
+        let mut best_t = f32::INFINITY;
+        let mut stack: Vec<(usize, f32)> = Vec::with_capacity(64);
+        if let Some(t) = self.nodes.first().and_then(|n| n.aabb.entry(ray, inv_d)) {
+            stack.push((0, t));
+        }
+        while let Some((i, entry)) = stack.pop() {
+            if entry > best_t {
+                continue;
+            }
+            let node = &self.nodes[i];
+            if node.count > 0 {
+                for &c in self.leaf(node) {
+                    if let Some((p, n, color)) = hit(c) {
+                        let t = (p - ray.p).dot(ray.d);
+                        if t < best_t {
+                            best_t = t;
+                            best = Some((p, n, color));
+                        }
+                    }
+                }
+                continue;
+            }
+            let (l, r) = (i + 1, node.first as usize);
+            let tl = self.nodes[l].aabb.entry(ray, inv_d);
+            let tr = self.nodes[r].aabb.entry(ray, inv_d);
+            // Pushed far then near, so the near one is popped first.
+            match (tl, tr) {
+                (Some(tl), Some(tr)) if tl <= tr => stack.extend([(r, tr), (l, tl)]),
+                (Some(tl), Some(tr)) => stack.extend([(l, tl), (r, tr)]),
+                (Some(tl), None) => stack.push((l, tl)),
+                (None, Some(tr)) => stack.push((r, tr)),
+                (None, None) => {}
+            }
+        }
+        best
+    }
+
+    pub fn occluded(&self, ray: &Ray, children: &[Box<dyn Thing>]) -> bool {
+        self.occluded_with(ray, |i| children[i].intersect(ray).is_some())
+    }
+
+    // Whether `hit` holds for any child `ray` reaches, stopping at the first
+    // one found. Enough for a shadow ray, which needs no nearest hit.
+    pub fn occluded_with(&self, ray: &Ray, hit: impl Fn(usize) -> bool) -> bool {
+        let inv_d = ray.d.recip();
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if node.aabb.entry(ray, inv_d).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                if self.leaf(node).iter().any(|&c| hit(c)) {
+                    return true;
+                }
+            } else {
+                stack.extend([node.first as usize, i + 1]);
+            }
+        }
+        false
+    }
+
+    // Recompute the boxes for children that have moved, keeping the tree as
+    // it was built. The tree gets looser as things drift from where they
+    // were, but stays correct.
+    pub fn refit(&mut self, children: &[Box<dyn Thing>]) {
+        let child_aabbs: Vec<AABB> = children.par_iter().map(|c| bounds(c.as_ref())).collect();
+        self.refit_bounds(&child_aabbs);
+    }
+
+    // `refit` for children that now have these boxes.
+    pub fn refit_bounds(&mut self, child_aabbs: &[AABB]) {
+        // Children come after their parents, so going backwards refits
+        // every node after the ones below it.
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            self.nodes[i].aabb = if node.count > 0 {
+                union_of(self.leaf(&node), child_aabbs)
+            } else {
+                let mut aabb = self.nodes[i + 1].aabb;
+                aabb.merge(&self.nodes[node.first as usize].aabb);
+                aabb
             };
         }
-        let child_aabbs: Vec<AABB> = children
-            .iter()
-            .map(|c| {
-                let mut a = AABB::new();
-                c.update_aabb(&mut a);
-                a
-            })
-            .collect();
-        let indices: Vec<usize> = (0..children.len()).collect();
-        build_recursive(indices, &child_aabbs)
     }
 
-    pub fn intersect(&self, ray: &Ray, children: &[Box<dyn Thing>]) -> Option<(Vec3, Vec3, Color)> {
-        intersect_inner(self, ray, children).map(|(p, n, c, _)| (p, n, c))
+    // Box around all the children.
+    pub fn aabb(&self) -> AABB {
+        self.nodes.first().map_or_else(AABB::new, |n| n.aabb)
+    }
+
+    fn leaf(&self, node: &Node) -> &[usize] {
+        &self.indices[node.first as usize..(node.first + node.count) as usize]
+    }
+
+    // The tree as little-endian bytes: the node and index counts, each node
+    // as its box, `first` and `count`, then the indices.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out =
+            Vec::with_capacity(8 + self.nodes.len() * NODE_BYTES + self.indices.len() * 4);
+        out.extend((self.nodes.len() as u32).to_le_bytes());
+        out.extend((self.indices.len() as u32).to_le_bytes());
+        for node in &self.nodes {
+            for x in node
+                .aabb
+                .min()
+                .to_array()
+                .into_iter()
+                .chain(node.aabb.max().to_array())
+            {
+                out.extend(x.to_le_bytes());
+            }
+            out.extend(node.first.to_le_bytes());
+            out.extend(node.count.to_le_bytes());
+        }
+        for &i in &self.indices {
+            out.extend((i as u32).to_le_bytes());
+        }
+        out
+    }
+
+    // A tree written by `to_bytes` for `n` children. None unless it is one:
+    // every child in exactly one leaf and every inner node pointing forward
+    // within the array, so that tracing through it cannot go out of bounds.
+    pub fn from_bytes(data: &[u8], n: usize) -> Option<Self> {
+        let mut words = data
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()));
+        let n_nodes = words.next()? as usize;
+        let n_indices = words.next()? as usize;
+        if n_indices != n
+            || data.len() as u64 != 8 + n_nodes as u64 * NODE_BYTES as u64 + n_indices as u64 * 4
+        {
+            return None;
+        }
+        let mut nodes = Vec::with_capacity(n_nodes);
+        for _ in 0..n_nodes {
+            let mut corner = || {
+                let [x, y, z] = [0; 3].map(|_| f32::from_bits(words.next().unwrap()));
+                Vec3::new(x, y, z)
+            };
+            let (min, max) = (corner(), corner());
+            nodes.push(Node {
+                aabb: AABB::from_corners(min, max),
+                first: words.next().unwrap(),
+                count: words.next().unwrap(),
+            });
+        }
+        let indices: Vec<usize> = words.map(|i| i as usize).collect();
+
+        let mut seen = vec![false; n];
+        for (i, node) in nodes.iter().enumerate() {
+            let (first, count) = (node.first as usize, node.count as usize);
+            if count > 0 {
+                for &c in indices.get(first..first + count)? {
+                    if c >= n || std::mem::replace(&mut seen[c], true) {
+                        return None;
+                    }
+                }
+            } else if first <= i + 1 || first >= n_nodes {
+                return None;
+            }
+        }
+        if seen.contains(&false) {
+            return None;
+        }
+        Some(Bvh { nodes, indices })
     }
 }
 
-fn build_recursive(mut indices: Vec<usize>, child_aabbs: &[AABB]) -> Bvh {
-    let aabb = union_of(&indices, child_aabbs);
+// Bytes a node takes in `Bvh::to_bytes`.
+const NODE_BYTES: usize = 32;
+
+// The top level of a two-level structure: a BVH over the scene's objects by
+// their world boxes, each of which (an Object) keeps its own BVH over its
+// mesh in object space and moves the ray into it. Without --aabb it is a
+// plain list, scanned in full.
+pub struct Tlas {
+    objects: Vec<Box<dyn Thing>>,
+    bvh: Option<Bvh>,
+}
 
-    if indices.len() <= LEAF_MAX {
-        return Bvh::Leaf { aabb, indices };
+impl Tlas {
+    pub fn new(objects: Vec<Box<dyn Thing>>, enable_aabb: bool) -> Self {
+        let bvh = if enable_aabb {
+            Some(Bvh::build(&objects))
+        } else {
+            None
+        };
+        Tlas { objects, bvh }
     }
 
-    // Pick split axis as the longest extent of the centroid bounds.
-    let mut cmin = Vec3::splat(f32::MAX);
-    let mut cmax = Vec3::splat(f32::MIN);
-    for &i in &indices {
-        if child_aabbs[i].is_empty() {
-            continue;
+    pub fn objects(&self) -> &[Box<dyn Thing>] {
+        &self.objects
+    }
+
+    // Call `refit` once done moving them.
+    pub fn objects_mut(&mut self) -> &mut [Box<dyn Thing>] {
+        &mut self.objects
+    }
+
+    pub fn refit(&mut self) {
+        if let Some(bvh) = &mut self.bvh {
+            bvh.refit(&self.objects);
         }
-        let c = child_aabbs[i].centroid();
-        cmin = cmin.min(c);
-        cmax = cmax.max(c);
-    }
-    let extent = cmax - cmin;
-    let axis: usize = if extent.x >= extent.y && extent.x >= extent.z {
-        0
-    } else if extent.y >= extent.z {
-        1
-    } else {
-        2
-    };
+    }
+
+    // The nearest hit.
+    pub fn intersect(&self, ray: &Ray) -> Option<(Vec3, Vec3, Color)> {
+        if let Some(bvh) = &self.bvh {
+            return bvh.intersect(ray, &self.objects);
+        }
+        self.objects
+            .iter()
+            .filter_map(|obj| obj.intersect(ray))
+            .min_by(|a, b| {
+                (a.0 - ray.p)
+                    .dot(ray.d)
+                    .total_cmp(&(b.0 - ray.p).dot(ray.d))
+            })
+    }
 
-    indices.sort_by(|&a, &b| {
-        let ca = axis_value(&child_aabbs[a].centroid(), axis);
-        let cb = axis_value(&child_aabbs[b].centroid(), axis);
-        ca.partial_cmp(&cb).unwrap_or(Ordering::Equal)
+    pub fn occluded(&self, ray: &Ray) -> bool {
+        match &self.bvh {
+            Some(bvh) => bvh.occluded(ray, &self.objects),
+            None => self.objects.iter().any(|obj| obj.intersect(ray).is_some()),
+        }
+    }
+}
+
+// The nodes of the tree over `indices`, which start at `offset` in the
+// whole list, with the root first. Inner nodes point to their right child by
+// its place in the returned list. Big subtrees build their halves in
+// parallel and join them; small ones build in place.
+fn build_subtree(indices: &mut [usize], offset: usize, child_aabbs: &[AABB]) -> Vec<Node> {
+    let n = indices.len();
+    if n < PARALLEL_MIN {
+        let mut nodes = Vec::with_capacity(2 * n);
+        build_into(&mut nodes, indices, offset, child_aabbs);
+        return nodes;
+    }
+    let aabb = union_of(indices, child_aabbs);
+    let mid = match split(indices, &aabb, child_aabbs) {
+        Some(mid) => mid,
+        None => return vec![leaf(aabb, offset, n)],
+    };
+    let (l, r) = indices.split_at_mut(mid);
+    let (left, right) = rayon::join(
+        || build_subtree(l, offset, child_aabbs),
+        || build_subtree(r, offset + mid, child_aabbs),
+    );
+    let mut nodes = Vec::with_capacity(1 + left.len() + right.len());
+    nodes.push(Node {
+        aabb,
+        first: (1 + left.len()) as u32,
+        count: 0,
     });
+    for sub in [left, right] {
+        let base = nodes.len() as u32;
+        nodes.extend(sub.into_iter().map(|mut node| {
+            if node.count == 0 {
+                node.first += base;
+            }
+            node
+        }));
+    }
+    nodes
+}
 
-    let mid = indices.len() / 2;
-    let right_indices = indices.split_off(mid);
+// `build_subtree` on one thread, adding the nodes to `nodes`.
+fn build_into(nodes: &mut Vec<Node>, indices: &mut [usize], offset: usize, child_aabbs: &[AABB]) {
+    let here = nodes.len();
+    let aabb = union_of(indices, child_aabbs);
+    nodes.push(leaf(aabb, offset, indices.len()));
+    let mid = match split(indices, &aabb, child_aabbs) {
+        Some(mid) => mid,
+        None => return,
+    };
+    let (l, r) = indices.split_at_mut(mid);
+    build_into(nodes, l, offset, child_aabbs);
+    let right = nodes.len() as u32;
+    build_into(nodes, r, offset + mid, child_aabbs);
+    nodes[here].first = right;
+    nodes[here].count = 0;
+}
 
-    Bvh::Internal {
+fn leaf(aabb: AABB, first: usize, count: usize) -> Node {
+    Node {
         aabb,
-        left: Box::new(build_recursive(indices, child_aabbs)),
-        right: Box::new(build_recursive(right_indices, child_aabbs)),
+        first: first as u32,
+        count: count as u32,
     }
 }
 
+fn bounds(child: &dyn Thing) -> AABB {
+    let mut a = AABB::new();
+    child.update_aabb(&mut a);
+    a
+}
+
 fn union_of(indices: &[usize], child_aabbs: &[AABB]) -> AABB {
     let mut a = AABB::new();
     for &i in indices {
@@ -96,51 +376,90 @@ fn union_of(indices: &[usize], child_aabbs: &[AABB]) -> AABB {
     a
 }
 
-fn axis_value(v: &Vec3, axis: usize) -> f32 {
-    match axis {
-        0 => v.x,
-        1 => v.y,
-        _ => v.z,
+// Reorder `indices` (the children of a node with box `aabb`) into the two
+// halves that split it most cheaply, and return where the second starts;
+// None to keep them together in a leaf. Candidate planes lie between BINS
+// equal buckets of the children's centroids along each axis, and the cost of
+// a split is the SAH estimate of what tracing through it would take.
+fn split(indices: &mut [usize], aabb: &AABB, child_aabbs: &[AABB]) -> Option<usize> {
+    let n = indices.len();
+    if n <= 1 {
+        return None;
     }
-}
+    // Children without a box have no centroid to sort by; they stay in the
+    // first bucket, where they cost nothing.
+    let mut centroids = AABB::new();
+    for &i in indices.iter() {
+        if !child_aabbs[i].is_empty() {
+            centroids.update(&child_aabbs[i].centroid());
+        }
+    }
+    let bin_of = |i: usize, axis: usize| {
+        let a = &child_aabbs[i];
+        if a.is_empty() {
+            return 0;
+        }
+        let (lo, hi) = (centroids.min()[axis], centroids.max()[axis]);
+        let b = (a.centroid()[axis] - lo) / (hi - lo) * BINS as f32;
+        (b as usize).min(BINS - 1)
+    };
 
-// Returns (point, normal, color, t) where t is the ray parameter at the hit.
-// Keeping t around lets the traversal pick the nearest child without
-// recomputing it, and would let callers compare across multiple BVHs later.
-fn intersect_inner(
-    node: &Bvh,
-    ray: &Ray,
-    children: &[Box<dyn Thing>],
-) -> Option<(Vec3, Vec3, Color, f32)> {
-    match node {
-        Bvh::Internal { aabb, left, right } => {
-            if !aabb.intersect(ray) {
-                return None;
-            }
-            let l = intersect_inner(left, ray, children);
-            let r = intersect_inner(right, ray, children);
-            match (l, r) {
-                (Some(a), Some(b)) => Some(if a.3 <= b.3 { a } else { b }),
-                (Some(a), None) => Some(a),
-                (None, Some(b)) => Some(b),
-                (None, None) => None,
-            }
+    // (cost, axis, first bucket on the right)
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroids.is_empty() || centroids.max()[axis] <= centroids.min()[axis] {
+            continue;
         }
-        Bvh::Leaf { aabb, indices } => {
-            if indices.is_empty() || !aabb.intersect(ray) {
-                return None;
+        let mut bins = [(AABB::new(), 0_usize); BINS];
+        for &i in indices.iter() {
+            let bin = &mut bins[bin_of(i, axis)];
+            bin.0.merge(&child_aabbs[i]);
+            bin.1 += 1;
+        }
+        // Area times count of everything right of each plane, swept from the
+        // right, then the same from the left.
+        let mut right_cost = [0.; BINS];
+        let mut acc = (AABB::new(), 0);
+        for b in (1..BINS).rev() {
+            acc.0.merge(&bins[b].0);
+            acc.1 += bins[b].1;
+            right_cost[b] = acc.0.area() * acc.1 as f32;
+        }
+        let mut acc = (AABB::new(), 0);
+        for b in 1..BINS {
+            acc.0.merge(&bins[b - 1].0);
+            acc.1 += bins[b - 1].1;
+            if acc.1 == 0 || acc.1 == n {
+                continue;
             }
-            let mut best: Option<(Vec3, Vec3, Color, f32)> = None;
-            for &i in indices {
-                if let Some((p, n, c)) = children[i].intersect(ray) {
-                    let t = (p - ray.p).dot(ray.d);
-                    match best {
-                        Some((_, _, _, bt)) if bt <= t => {}
-                        _ => best = Some((p, n, c, t)),
-                    }
-                }
+            let cost = TRAVERSAL_COST
+                + (acc.0.area() * acc.1 as f32 + right_cost[b])
+                    / aabb.area().max(f32::MIN_POSITIVE);
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, b));
             }
-            best
         }
     }
+
+    let mid = match best {
+        Some((cost, _, _)) if n <= LEAF_MAX && cost >= n as f32 => return None,
+        Some((_, axis, b)) => partition(indices, |i| bin_of(i, axis) < b),
+        None if n <= LEAF_MAX => return None,
+        // All the centroids in one place; any split is as good as another.
+        None => n / 2,
+    };
+    Some(mid)
+}
+
+// Move the indices for which `left` holds to the front, returning how many
+// there are.
+fn partition(indices: &mut [usize], left: impl Fn(usize) -> bool) -> usize {
+    let mut mid = 0;
+    for k in 0..indices.len() {
+        if left(indices[k]) {
+            indices.swap(mid, k);
+            mid += 1;
+        }
+    }
+    mid
 }
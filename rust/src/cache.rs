@@ -0,0 +1,68 @@
+use crate::bvh::Bvh;
+
+// Cache of the BVH built for a mesh file, kept next to it as
+// `<file>.cosmo-bvh` so the next run can read the tree back instead of
+// building it again. A cache holds:
+//
+// - MAGIC,
+// - the builder version (VERSION), bumped whenever `Bvh::build` would
+//   give a different tree for the same mesh,
+// - the content hash of the mesh file it was built from,
+// - the tree, as `Bvh::to_bytes` writes it.
+//
+// A cache whose version or hash does not match is stale and gets rebuilt,
+// so editing the mesh file is all it takes to invalidate it.
+
+const MAGIC: &[u8; 8] = b"COSMOBVH";
+pub const VERSION: u32 = 1;
+const HEADER_BYTES: usize = 20;
+
+// Name of the cache for mesh file `name`.
+pub fn cache_name(name: &str) -> String {
+    format!("{}.cosmo-bvh", name)
+}
+
+// FNV-1a over the data a word at a time, then the bytes left over. Not
+// cryptographic, just stable across runs and platforms, unlike the std
+// hashers.
+pub fn content_hash(data: &[u8]) -> u64 {
+    const PRIME: u64 = 0x100000001b3;
+    let mut h: u64 = 0xcbf29ce484222325;
+    let words = data.chunks_exact(8);
+    let rest = words.remainder();
+    for w in words {
+        h = (h ^ u64::from_le_bytes(w.try_into().unwrap())).wrapping_mul(PRIME);
+    }
+    for &b in rest {
+        h = (h ^ b as u64).wrapping_mul(PRIME);
+    }
+    h ^ data.len() as u64
+}
+
+pub fn write_cache(bvh: &Bvh, hash: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_BYTES);
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+    out.extend(hash.to_le_bytes());
+    out.extend(bvh.to_bytes());
+    out
+}
+
+// The tree in cache `data` for a mesh with content hash `hash` and `n`
+// primitives, or why it cannot be used.
+pub fn read_cache(data: &[u8], hash: u64, n: usize) -> Result<Bvh, String> {
+    if data.len() < HEADER_BYTES || &data[..8] != MAGIC {
+        return Err("not a BVH cache".to_string());
+    }
+    let version = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(format!(
+            "built by version {} of the builder, not {}",
+            version, VERSION
+        ));
+    }
+    if u64::from_le_bytes(data[12..20].try_into().unwrap()) != hash {
+        return Err("built from a different mesh file".to_string());
+    }
+    Bvh::from_bytes(&data[HEADER_BYTES..], n).ok_or_else(|| "damaged".to_string())
+}
@@ -1,93 +1,258 @@
 use glam::Vec3;
 
+use crate::aabb::AABB;
+
+use crate::engine::{find_object, Thing};
 use crate::movement::Movement;
 use crate::movement::Rotate;
-use crate::util::Ray;
+use crate::track::Track;
+use crate::util::{fmt_num, fmt_vec3, to_rad, Ray};
 
 pub trait CameraInt {
-    fn get_ray(&self, i: usize, j: usize) -> &Ray;
-    // Build a ray for fractional screen coordinates. Used by the sharpen path
-    // to sub-cell-sample at positions like (i + 0.166, j + 0.333). The integer
-    // arms (i, j) -> (i + 0.5, j + 0.5) reproduce the precomputed ray grid.
+    // The ray through screen cell (i, j), or through any point of the screen
+    // for fractional coordinates, as the sharpen path uses to sub-cell-sample
+    // at positions like (i + 0.166, j + 0.333). Worked out from the current
+    // basis on each call, so a moving camera has no ray grid to rebuild.
     fn ray_at(&self, i_f: f32, j_f: f32) -> Ray;
     // Forward projection: world point -> (screen_j, screen_i, depth). Returns
     // None if the point is at or behind the near plane (depth <= 0).
     fn project(&self, p_world: Vec3) -> Option<(f32, f32, f32)>;
     fn eye(&self) -> Vec3;
     fn forward(&self) -> Vec3;
+    // The camera as a `C` line, followed by the `NAME`, `LOOK` and `KEY`
+    // lines of its rig.
+    fn to_cos(&self) -> String;
+    // Point the camera where its rig has it at time `t + dt`. `objects` are
+    // the scene's, already posed at that time, for a look-at target.
+    fn update(&mut self, t: f32, dt: f32, objects: &[Box<dyn Thing>]);
+    fn rig(&self) -> &CameraRig;
+    fn rig_mut(&mut self) -> &mut CameraRig;
+    // Move the camera back along its view direction, and for an ortho
+    // camera zoom it, until `bounds` fill the screen but for `margin` (a
+    // fraction of their size) on every side. Its rest pose becomes the new
+    // one.
+    fn frame(&mut self, bounds: &AABB, margin: f32);
+
+    // The camera's keyframe track, started empty if it has none yet.
+    fn track_mut(&mut self) -> &mut Track {
+        self.rig_mut().track.get_or_insert_with(Track::default)
+    }
+}
+
+// What a camera looks at, whichever way it moves.
+#[derive(Clone, Debug)]
+pub enum Look {
+    Point(Vec3),
+    // The origin of the object with this name.
+    Object(String),
+}
+
+// How a camera moves: its pose at time 0, moved like a point light by `m`
+// and offset by `track`, then turned to face `look`. `up` turns along with
+// the view direction; without one the camera keeps the roll `Rotate::get`
+// gives it.
+pub struct CameraRig {
+    pub eye: Vec3,
+    pub forward: Vec3,
+    pub up: Option<Vec3>,
+    pub m: Option<Box<dyn Movement>>,
+    pub track: Option<Track>,
+    pub look: Option<Look>,
+    // Margin for `frame` to fit the camera to the scene with once it is
+    // loaded, for a `C AUTO` line.
+    pub fit: Option<f32>,
+    // Given with `NAME`, for `CUT` lines to switch to.
+    pub name: Option<String>,
+}
+
+impl CameraRig {
+    pub fn new(eye: Vec3, forward: Vec3) -> Self {
+        CameraRig {
+            eye,
+            forward,
+            up: None,
+            m: None,
+            track: None,
+            look: None,
+            fit: None,
+            name: None,
+        }
+    }
+
+    // Nothing moves or turns the camera.
+    pub fn is_fixed(&self) -> bool {
+        self.m.is_none() && self.track.is_none() && self.look.is_none()
+    }
+
+    // Eye, view direction and up vector at time `t`.
+    pub fn pose(&self, t: f32, objects: &[Box<dyn Thing>]) -> (Vec3, Vec3, Option<Vec3>) {
+        let (mut eye, mut forward, mut up) = (self.eye, self.forward, self.up);
+        if let Some(m) = &self.m {
+            eye = m.point_at(t, eye);
+            forward = m.direction_at(t, forward);
+            up = up.map(|up| m.direction_at(t, up));
+        }
+        if let Some(track) = &self.track {
+            eye = track.point_at(t, eye);
+            forward = track.direction_at(t, forward);
+            up = up.map(|up| track.direction_at(t, up));
+        }
+        let target = match &self.look {
+            Some(Look::Point(p)) => Some(*p),
+            Some(Look::Object(name)) => {
+                find_object(objects, name).map(|o| o.transform().translation)
+            }
+            None => None,
+        };
+        if let Some(target) = target {
+            // Looking at a target the eye is on leaves the direction as it
+            // was.
+            forward = (target - eye).try_normalize().unwrap_or(forward);
+        }
+        (eye, forward, up)
+    }
+
+    // `C AUTO` lines are written as they were given, not as they were framed.
+    fn auto_to_cos(&self, kind: char) -> Option<String> {
+        self.fit.map(|margin| {
+            let margin = if margin == AUTO_MARGIN {
+                String::new()
+            } else {
+                format!(" {}", fmt_num(margin))
+            };
+            format!("C AUTO {} {}{}", kind, fmt_vec3(self.forward), margin)
+        })
+    }
+
+    // The rest of a `C` line after the pose and lens (the movement), and the
+    // `NAME`, `LOOK` and `KEY` lines after it.
+    fn to_cos(&self) -> (String, String) {
+        let m = match &self.m {
+            Some(m) => format!(" {}", m.to_cos()),
+            None => String::new(),
+        };
+        let mut lines = String::new();
+        if let Some(name) = &self.name {
+            lines.push_str(&format!("\nNAME {}", name));
+        }
+        match &self.look {
+            Some(Look::Point(p)) => lines.push_str(&format!("\nLOOK {}", fmt_vec3(*p))),
+            Some(Look::Object(name)) => lines.push_str(&format!("\nLOOK OBJ {}", name)),
+            None => {}
+        }
+        for line in self.track.iter().flat_map(|t| t.to_cos()) {
+            lines.push('\n');
+            lines.push_str(&line);
+        }
+        (m, lines)
+    }
 }
 
 pub trait Camera: CameraInt + Sync {}
 
-// Apply the same Rotate the ray-grid constructor uses to a local basis vector
-// so the rasterizer's world-axis basis is consistent with the ray grid.
-fn rotated_dir(rot: &Rotate, mut v: Vec3) -> Vec3 {
-    rot.update_direction(1.0, &mut v);
-    v
+// Height over width of a character cell, which is what stretches rows apart
+// on screen unless a `C FOV` line says otherwise.
+pub const CELL_ASPECT: f32 = 2.;
+
+// Part of a `C AUTO` camera's view left free on each side, unless given.
+pub const AUTO_MARGIN: f32 = 0.1;
+
+// Vertical field of view of a `C AUTO P` camera, in degrees.
+pub const AUTO_FOV: f32 = 40.;
+
+// The `scale` that shows `fov` degrees over `h` rows with the screen one
+// unit ahead of the eye.
+pub fn fov_scale(fov: f32, aspect: f32, h: usize) -> f32 {
+    h as f32 * aspect / (2. * (to_rad(fov) / 2.).tan())
+}
+
+// The orthographic `scale` that shows `ymag` units above and below the
+// middle of `h` rows.
+pub fn ortho_scale(ymag: f32, aspect: f32, h: usize) -> f32 {
+    h as f32 * aspect / (2. * ymag)
+}
+
+// A camera looking along `d` that `frame` puts in place once the scene it
+// is to show is known, as for a `C AUTO` line.
+pub fn auto_camera(ortho: bool, d: Vec3, margin: f32, w: usize, h: usize) -> Box<dyn Camera> {
+    let mut camera: Box<dyn Camera> = if ortho {
+        Box::new(OrthoCamera::new(d, -d, 1., w, h))
+    } else {
+        let scale = fov_scale(AUTO_FOV, CELL_ASPECT, h);
+        Box::new(PerspectiveCamera::new(d, -d, scale, 1., w, h))
+    };
+    camera.rig_mut().fit = Some(margin);
+    camera
+}
+
+// The corners of `bounds` seen looking along `d`: how far each is ahead of
+// the box's center, right of it and above it. Also returns the center.
+fn view_of(bounds: &AABB, d: Vec3, right: Vec3, up: Vec3) -> (Vec3, [Vec3; 8]) {
+    let center = bounds.centroid();
+    let corners = bounds.corners().map(|c| {
+        let v = c - center;
+        Vec3::new(v.dot(d), v.dot(right), v.dot(up))
+    });
+    (center, corners)
+}
+
+// Right and up directions of a camera looking along `d`, square to it and to
+// each other. Up is as close to `up` as that allows. Without `up`, or with
+// one along `d`, the local frame (looking down -x, +y right, +z up) is turned
+// onto `d` by `Rotate::get`, as cameras always were.
+fn basis(d: Vec3, up: Option<Vec3>) -> (Vec3, Vec3) {
+    if let Some(right) = up.and_then(|up| d.cross(up).try_normalize()) {
+        return (right, right.cross(d));
+    }
+    let rot = Rotate::get(Vec3::new(-1., 0., 0.), d, Vec3::ZERO);
+    (
+        rot.direction_at(1., Vec3::new(0., 1., 0.)),
+        rot.direction_at(1., Vec3::new(0., 0., 1.)),
+    )
 }
 
 pub struct OrthoCamera {
-    rays: Vec<Vec<Ray>>,
     eye: Vec3,
     forward: Vec3,
     right_world: Vec3,
     up_world: Vec3,
     scale: f32,
+    aspect: f32,
     w: usize,
     h: usize,
+    rig: CameraRig,
 }
 
 impl OrthoCamera {
     pub fn new(d: Vec3, p: Vec3, scale: f32, w: usize, h: usize) -> Self {
-        // Compute rotate axis and degree.
-        let rot = Rotate::get(Vec3::new(-1., 0., 0.), d, Vec3::ZERO);
-
-        // Pre-compute all rays
-        let mut rays: Vec<Vec<Ray>> = vec![];
-        for i in 0..h {
-            rays.push(vec![]);
-            let z = ((h as f32) / 2. - (i as f32)) * 2. / scale;
-            for j in 0..w {
-                let y = (-(w as f32) / 2. + (j as f32)) / scale;
-                let mut p0 = Vec3::new(0., y, z);
-                rot.update_point(1., &mut p0); // Rotate
-                p0 += p; // Translate
-                rays[i].push(Ray { p: p0, d: d });
-            }
-        }
-        let right_world = rotated_dir(&rot, Vec3::new(0., 1., 0.));
-        let up_world = rotated_dir(&rot, Vec3::new(0., 0., 1.));
-        OrthoCamera {
-            rays,
+        let mut camera = OrthoCamera {
             eye: p,
             forward: d,
-            right_world,
-            up_world,
+            right_world: Vec3::ZERO,
+            up_world: Vec3::ZERO,
             scale,
+            aspect: CELL_ASPECT,
             w,
             h,
-        }
+            rig: CameraRig::new(p, d),
+        };
+        camera.aim(p, d, None);
+        camera
     }
-}
 
-impl CameraInt for OrthoCamera {
-    fn get_ray(&self, i: usize, j: usize) -> &Ray {
-        &self.rays[i][j]
+    fn aim(&mut self, p: Vec3, d: Vec3, up: Option<Vec3>) {
+        (self.right_world, self.up_world) = basis(d, up);
+        self.eye = p;
+        self.forward = d;
     }
+}
 
+impl CameraInt for OrthoCamera {
     fn ray_at(&self, i_f: f32, j_f: f32) -> Ray {
-        // Same local->world transform as the constructor, but with fractional
-        // i/j so callers can sample at arbitrary sub-cell positions.
-        let z_local = ((self.h as f32) / 2. - i_f) * 2. / self.scale;
+        let z_local = ((self.h as f32) / 2. - i_f) * self.aspect / self.scale;
         let y_local = (-(self.w as f32) / 2. + j_f) / self.scale;
-        let p_local = Vec3::new(0., y_local, z_local);
-        let mut p_world = p_local;
-        // Build the rotation around the +x axis (matches the constructor).
-        let rot = Rotate::get(Vec3::new(-1., 0., 0.), self.forward, Vec3::ZERO);
-        rot.update_point(1., &mut p_world);
-        p_world += self.eye;
         Ray {
-            p: p_world,
+            p: self.eye + self.right_world * y_local + self.up_world * z_local,
             d: self.forward,
         }
     }
@@ -100,10 +265,10 @@ impl CameraInt for OrthoCamera {
         }
         let y_eye = v.dot(self.right_world);
         let z_eye = v.dot(self.up_world);
-        // Inverse of the ray-grid mapping: j = y_local * scale + w/2;
-        //                                  i = h/2 - z_local * scale / 2.
+        // Inverse of the `ray_at` mapping: j = y_local * scale + w/2;
+        //                                  i = h/2 - z_local * scale / aspect.
         let j = y_eye * self.scale + (self.w as f32) / 2.0;
-        let i = (self.h as f32) / 2.0 - z_eye * self.scale / 2.0;
+        let i = (self.h as f32) / 2.0 - z_eye * self.scale / self.aspect;
         Some((j, i, z))
     }
 
@@ -114,75 +279,151 @@ impl CameraInt for OrthoCamera {
     fn forward(&self) -> Vec3 {
         self.forward
     }
-}
 
-unsafe impl Sync for OrthoCamera {}
+    fn to_cos(&self) -> String {
+        let (m, lines) = self.rig.to_cos();
+        let lens = match self.rig.auto_to_cos('O') {
+            Some(auto) => auto,
+            None => format!(
+                "C O {} {} {}",
+                fmt_vec3(self.rig.forward),
+                fmt_vec3(self.rig.eye),
+                fmt_num(self.scale)
+            ),
+        };
+        format!("{}{}{}", lens, m, lines)
+    }
+
+    fn update(&mut self, t: f32, dt: f32, objects: &[Box<dyn Thing>]) {
+        if self.rig.is_fixed() {
+            return;
+        }
+        let (p, d, up) = self.rig.pose(t + dt, objects);
+        self.aim(p, d, up);
+    }
+
+    fn rig(&self) -> &CameraRig {
+        &self.rig
+    }
+
+    fn rig_mut(&mut self) -> &mut CameraRig {
+        &mut self.rig
+    }
+
+    fn frame(&mut self, bounds: &AABB, margin: f32) {
+        if bounds.is_empty() {
+            return;
+        }
+        let d = self.rig.forward;
+        let (right, up) = basis(d, self.rig.up);
+        let (center, corners) = view_of(bounds, d, right, up);
+        let (mut ahead, mut wide, mut high) = (0f32, 0f32, 0f32);
+        for c in corners {
+            ahead = ahead.max(c.x);
+            wide = wide.max(c.y.abs());
+            high = high.max(c.z.abs());
+        }
+        // Half the screen is w / 2 / scale wide and h * aspect / 2 / scale
+        // high. A box with no width or height puts no limit on the zoom.
+        let grow = 1. + margin;
+        let scale = f32::min(
+            self.w as f32 / (2. * wide * grow),
+            self.h as f32 * self.aspect / (2. * high * grow),
+        );
+        if scale.is_finite() {
+            self.scale = scale;
+        }
+        // Rays start on the eye's plane, so it has to be clear of the box.
+        let eye = center - d * (ahead + 1.);
+        self.rig.eye = eye;
+        self.aim(eye, d, self.rig.up);
+    }
+}
 
 impl Camera for OrthoCamera {}
 
+// A perspective camera as given by a `C FOV` line: what it was pointed at
+// and its vertical field of view in degrees. Only kept to write the line
+// back out.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub target: Vec3,
+    pub fov: f32,
+}
+
 pub struct PerspectiveCamera {
-    rays: Vec<Vec<Ray>>,
     eye: Vec3,
     forward: Vec3,
     right_world: Vec3,
     up_world: Vec3,
     scale: f32,
     focal: f32,
+    aspect: f32,
     w: usize,
     h: usize,
+    rig: CameraRig,
+    view: Option<View>,
 }
 
 impl PerspectiveCamera {
     pub fn new(d: Vec3, p: Vec3, scale: f32, f: f32, w: usize, h: usize) -> Self {
-        let o = Vec3::new(f, 0., 0.);
-        let rot = Rotate::get(Vec3::new(-1., 0., 0.), d, o);
-        let mut rays: Vec<Vec<Ray>> = vec![];
-        for i in 0..h {
-            rays.push(vec![]);
-            let z = ((h as f32) / 2. - (i as f32)) * 2. / scale;
-            for j in 0..w {
-                let y = (-(w as f32) / 2. + (j as f32)) / scale;
-                let mut p0 = Vec3::new(0., y, z);
-                rot.update_point(1., &mut p0); // Rotate
-                p0 += p - o; // Translate
-                rays[i].push(Ray {
-                    p: p,
-                    d: (p0 - p).normalize(),
-                })
-            }
-        }
-        let right_world = rotated_dir(&rot, Vec3::new(0., 1., 0.));
-        let up_world = rotated_dir(&rot, Vec3::new(0., 0., 1.));
-        PerspectiveCamera {
-            rays,
+        let mut camera = PerspectiveCamera {
             eye: p,
             forward: d,
-            right_world,
-            up_world,
+            right_world: Vec3::ZERO,
+            up_world: Vec3::ZERO,
             scale,
             focal: f,
+            aspect: CELL_ASPECT,
             w,
             h,
-        }
+            rig: CameraRig::new(p, d),
+            view: None,
+        };
+        camera.aim(p, d, None);
+        camera
     }
-}
 
-impl CameraInt for PerspectiveCamera {
-    fn get_ray(&self, i: usize, j: usize) -> &Ray {
-        &self.rays[i][j]
+    // A camera at `eye` looking at `target`, rolled so `up` points up on
+    // screen, that shows `fov` degrees from the top of the screen to the
+    // bottom. The field of view, not the cell count, sets the zoom, so a
+    // scene frames the same at any size with the same shape. `aspect` is
+    // the height over width of a cell.
+    pub fn look_at(
+        eye: Vec3,
+        target: Vec3,
+        up: Vec3,
+        fov: f32,
+        aspect: f32,
+        w: usize,
+        h: usize,
+    ) -> Self {
+        let d = (target - eye).normalize();
+        let scale = fov_scale(fov, aspect, h);
+        let mut camera = PerspectiveCamera::new(d, eye, scale, 1., w, h);
+        camera.aspect = aspect;
+        camera.view = Some(View { target, fov });
+        camera.rig.up = Some(up);
+        camera.aim(eye, d, Some(up));
+        camera
+    }
+
+    fn aim(&mut self, p: Vec3, d: Vec3, up: Option<Vec3>) {
+        (self.right_world, self.up_world) = basis(d, up);
+        self.eye = p;
+        self.forward = d;
     }
+}
 
+impl CameraInt for PerspectiveCamera {
     fn ray_at(&self, i_f: f32, j_f: f32) -> Ray {
-        let z_local = ((self.h as f32) / 2. - i_f) * 2. / self.scale;
+        let z_local = ((self.h as f32) / 2. - i_f) * self.aspect / self.scale;
         let y_local = (-(self.w as f32) / 2. + j_f) / self.scale;
-        let o = Vec3::new(self.focal, 0., 0.);
-        let mut p0 = Vec3::new(0., y_local, z_local);
-        let rot = Rotate::get(Vec3::new(-1., 0., 0.), self.forward, o);
-        rot.update_point(1., &mut p0);
-        p0 += self.eye - o;
+        // Through the point of the screen `focal` ahead of the eye.
+        let d = self.forward * self.focal + self.right_world * y_local + self.up_world * z_local;
         Ray {
             p: self.eye,
-            d: (p0 - self.eye).normalize(),
+            d: d.normalize(),
         }
     }
 
@@ -198,7 +439,7 @@ impl CameraInt for PerspectiveCamera {
         let y_img = y_eye * self.focal / z;
         let z_img = z_eye * self.focal / z;
         let j = y_img * self.scale + (self.w as f32) / 2.0;
-        let i = (self.h as f32) / 2.0 - z_img * self.scale / 2.0;
+        let i = (self.h as f32) / 2.0 - z_img * self.scale / self.aspect;
         Some((j, i, z))
     }
 
@@ -209,8 +450,80 @@ impl CameraInt for PerspectiveCamera {
     fn forward(&self) -> Vec3 {
         self.forward
     }
-}
 
-unsafe impl Sync for PerspectiveCamera {}
+    fn to_cos(&self) -> String {
+        let (m, lines) = self.rig.to_cos();
+        if let Some(auto) = self.rig.auto_to_cos('P') {
+            return format!("{}{}{}", auto, m, lines);
+        }
+        let lens = match (self.view, self.rig.up) {
+            (Some(view), Some(up)) => {
+                let aspect = if self.aspect != CELL_ASPECT {
+                    format!(" ASPECT {}", fmt_num(self.aspect))
+                } else {
+                    String::new()
+                };
+                format!(
+                    "C FOV {} {} {} {}{}",
+                    fmt_vec3(self.rig.eye),
+                    fmt_vec3(view.target),
+                    fmt_vec3(up),
+                    fmt_num(view.fov),
+                    aspect
+                )
+            }
+            _ => format!(
+                "C P {} {} {} {}",
+                fmt_vec3(self.rig.forward),
+                fmt_vec3(self.rig.eye),
+                fmt_num(self.scale),
+                fmt_num(self.focal)
+            ),
+        };
+        format!("{}{}{}", lens, m, lines)
+    }
+
+    fn update(&mut self, t: f32, dt: f32, objects: &[Box<dyn Thing>]) {
+        if self.rig.is_fixed() {
+            return;
+        }
+        let (p, d, up) = self.rig.pose(t + dt, objects);
+        self.aim(p, d, up);
+    }
+
+    fn rig(&self) -> &CameraRig {
+        &self.rig
+    }
+
+    fn rig_mut(&mut self) -> &mut CameraRig {
+        &mut self.rig
+    }
+
+    fn frame(&mut self, bounds: &AABB, margin: f32) {
+        if bounds.is_empty() {
+            return;
+        }
+        let d = self.rig.forward;
+        let (right, up) = basis(d, self.rig.up);
+        let (center, corners) = view_of(bounds, d, right, up);
+        // Tangents of half the field of view across and up the screen.
+        let tan_w = self.w as f32 / (2. * self.scale * self.focal);
+        let tan_h = self.h as f32 * self.aspect / (2. * self.scale * self.focal);
+        let grow = 1. + margin;
+        // Far enough back that every corner is inside the view, and in front
+        // of the eye.
+        let mut dist = 0f32;
+        for c in corners {
+            let need = f32::max(c.y.abs() * grow / tan_w, c.z.abs() * grow / tan_h);
+            dist = dist.max(need - c.x).max(0.1 - c.x);
+        }
+        let eye = center - d * dist;
+        self.rig.eye = eye;
+        if let Some(view) = &mut self.view {
+            view.target = center;
+        }
+        self.aim(eye, d, self.rig.up);
+    }
+}
 
 impl Camera for PerspectiveCamera {}
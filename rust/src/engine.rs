@@ -1,8 +1,12 @@
+use std::sync::Arc;
+
 use glam::f32::Vec3;
+use rayon::prelude::*;
 
 use crate::aabb::AABB;
 use crate::bvh::Bvh;
 use crate::movement::{Movement, Rotate};
+use crate::track::Track;
 use crate::util::{Color, Ray, Transform};
 
 const NEWTON_MAX_ITER: usize = 20;
@@ -28,9 +32,23 @@ pub trait Visible {
     fn as_object(&self) -> Option<&Object> {
         None
     }
+    fn as_object_mut(&mut self) -> Option<&mut Object> {
+        None
+    }
+    // Same for the primitives, used by the scene writer to read their
+    // parameters back.
+    fn as_triangle(&self) -> Option<&Triangle> {
+        None
+    }
+    fn as_sphere(&self) -> Option<&Sphere> {
+        None
+    }
+    fn as_torus(&self) -> Option<&Torus> {
+        None
+    }
 }
 
-pub trait Thing: Updatable + Visible + Sync {}
+pub trait Thing: Updatable + Visible + Send + Sync {}
 
 #[derive(Default)]
 pub struct Triangle {
@@ -70,6 +88,20 @@ impl Triangle {
         self.n = self.v1.cross(self.v0).normalize();
     }
 
+    pub fn vertices(&self) -> (Vec3, Vec3, Vec3) {
+        (self.a, self.b, self.c)
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn aabb(&self) -> AABB {
+        let mut aabb = AABB::new();
+        self.update_aabb(&mut aabb);
+        aabb
+    }
+
     fn contains_point(&self, p: Vec3) -> bool {
         let v2 = p - self.a;
         let dot02 = self.v0.dot(v2);
@@ -107,15 +139,19 @@ impl Visible for Triangle {
     fn raster_tri(&self) -> Option<(Vec3, Vec3, Vec3, Color, Vec3)> {
         Some((self.a, self.b, self.c, self.color, self.n))
     }
+
+    fn as_triangle(&self) -> Option<&Triangle> {
+        Some(self)
+    }
 }
 
 impl Updatable for Triangle {
-    fn update(&mut self, _t: f32, dt: f32, m: Option<&Box<dyn Movement>>) {
+    fn update(&mut self, t: f32, dt: f32, m: Option<&Box<dyn Movement>>) {
         match m {
             Some(mv) => {
-                mv.update_point(dt, &mut self.a);
-                mv.update_point(dt, &mut self.b);
-                mv.update_point(dt, &mut self.c);
+                mv.update_point(t, dt, &mut self.a);
+                mv.update_point(t, dt, &mut self.b);
+                mv.update_point(t, dt, &mut self.c);
                 self.process();
             }
             None => {}
@@ -123,8 +159,6 @@ impl Updatable for Triangle {
     }
 }
 
-unsafe impl Sync for Triangle {}
-
 impl Thing for Triangle {}
 
 pub struct Sphere {
@@ -158,24 +192,64 @@ impl Visible for Sphere {
         }
     }
 
-    fn update_aabb(&self, _aabb: &mut AABB) {}
+    fn update_aabb(&self, aabb: &mut AABB) {
+        aabb.update(&(self.o - Vec3::splat(self.r)));
+        aabb.update(&(self.o + Vec3::splat(self.r)));
+    }
+
+    fn as_sphere(&self) -> Option<&Sphere> {
+        Some(self)
+    }
 }
 
 impl Updatable for Sphere {
-    fn update(&mut self, _t: f32, dt: f32, m: Option<&Box<dyn Movement>>) {
+    fn update(&mut self, t: f32, dt: f32, m: Option<&Box<dyn Movement>>) {
         match m {
             Some(mv) => {
-                mv.update_point(dt, &mut self.o);
+                mv.update_point(t, dt, &mut self.o);
             }
             None => {}
         };
     }
 }
 
-unsafe impl Sync for Sphere {}
-
 impl Thing for Sphere {}
 
+// One sample of a point cloud, drawn as a small sphere of radius `r`. Unlike
+// Sphere it has a bounding box, so large clouds can sit behind a BVH. Not
+// rasterized; point clouds only show up with the ray tracer.
+pub struct Point {
+    pub p: Vec3,
+    pub r: f32,
+    pub color: Color,
+}
+
+impl Visible for Point {
+    fn intersect(&self, ray: &Ray) -> Option<(Vec3, Vec3, Color)> {
+        Sphere {
+            o: self.p,
+            r: self.r,
+            color: self.color,
+        }
+        .intersect(ray)
+    }
+
+    fn update_aabb(&self, aabb: &mut AABB) {
+        aabb.update(&(self.p - Vec3::splat(self.r)));
+        aabb.update(&(self.p + Vec3::splat(self.r)));
+    }
+}
+
+impl Updatable for Point {
+    fn update(&mut self, t: f32, dt: f32, m: Option<&Box<dyn Movement>>) {
+        if let Some(mv) = m {
+            mv.update_point(t, dt, &mut self.p);
+        }
+    }
+}
+
+impl Thing for Point {}
+
 #[derive(Default)]
 pub struct Torus {
     d: Vec3,
@@ -212,6 +286,24 @@ impl Torus {
         self.rot = Rotate::get(self.d, Vec3::new(0., 0., 1.), Vec3::ZERO);
     }
 
+    // Axis the torus is wrapped around.
+    pub fn axis(&self) -> Vec3 {
+        self.d
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.p
+    }
+
+    // (major, minor)
+    pub fn radii(&self) -> (f32, f32) {
+        (self.R, self.r)
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
     fn dt(&self, ray: &Ray, t: f32) -> (f32, f32) {
         let pt = ray.p + t * ray.d;
         let u = (pt.x.powi(2) + pt.y.powi(2)).sqrt();
@@ -225,10 +317,8 @@ impl Torus {
 impl Visible for Torus {
     fn intersect(&self, ray: &Ray) -> Option<(Vec3, Vec3, Color)> {
         // Transpose ray as if the torus is at standard position.
-        let mut r_p = ray.p - self.p;
-        let mut r_d = ray.d;
-        self.rot.update_point(1., &mut r_p);
-        self.rot.update_point(1., &mut r_d);
+        let r_p = self.rot.point_at(1., ray.p - self.p);
+        let r_d = self.rot.point_at(1., ray.d);
         let new_ray = Ray { p: r_p, d: r_d };
         // Use Newton's method to numerically solve intersection.
         let mut t: f32 = 0.01;
@@ -251,25 +341,34 @@ impl Visible for Torus {
         if t < 0. {
             return None;
         }
-        let mut p = new_ray.p + t * new_ray.d;
-        let mut o = self.R * p.with_z(0.).normalize();
+        let p = new_ray.p + t * new_ray.d;
+        let o = self.R * p.with_z(0.).normalize();
         // Transpose intersection and normal vec back to correct position.
-        self.rot.update_point(-1., &mut p);
-        self.rot.update_point(-1., &mut o);
-        p += self.p;
-        o += self.p;
+        let p = self.rot.point_at(-1., p) + self.p;
+        let o = self.rot.point_at(-1., o) + self.p;
         Some((p, (p - o).normalize(), self.color))
     }
 
-    fn update_aabb(&self, _aabb: &mut AABB) {}
+    fn update_aabb(&self, aabb: &mut AABB) {
+        // The tube's center line is a circle of radius R around `d`, which
+        // reaches R * sqrt(1 - d.x^2) along x, and so on.
+        let d = self.d.normalize();
+        let reach = (Vec3::ONE - d * d).max(Vec3::ZERO).powf(0.5) * self.R + Vec3::splat(self.r);
+        aabb.update(&(self.p - reach));
+        aabb.update(&(self.p + reach));
+    }
+
+    fn as_torus(&self) -> Option<&Torus> {
+        Some(self)
+    }
 }
 
 impl Updatable for Torus {
-    fn update(&mut self, _t: f32, dt: f32, m: Option<&Box<dyn Movement>>) {
+    fn update(&mut self, t: f32, dt: f32, m: Option<&Box<dyn Movement>>) {
         match m {
             Some(mv) => {
-                mv.update_direction(dt, &mut self.d);
-                mv.update_point(dt, &mut self.p);
+                mv.update_direction(t, dt, &mut self.d);
+                mv.update_point(t, dt, &mut self.p);
                 self.process();
             }
             None => {}
@@ -277,18 +376,158 @@ impl Updatable for Torus {
     }
 }
 
-unsafe impl Sync for Torus {}
-
 impl Thing for Torus {}
 
-pub struct Object {
+// Geometry in object space: the primitives, their BVH and the flat triangle
+// list for the rasterizer. Shared through an Arc by every Object placing the
+// same mesh, so instancing a mesh many times stores and builds it once.
+pub struct Mesh {
+    // Triangles of a mesh read from a file, stored inline rather than boxed
+    // one by one; there can be millions of them.
+    tris: Vec<Triangle>,
+    // Primitives of any kind, as given in an OBJ block. The BVH numbers the
+    // primitives `tris` first, then these.
     children: Vec<Box<dyn Thing>>,
-    m: Option<Box<dyn Movement>>,
     bvh: Option<Bvh>,
-    transform: Transform,
+    // Box around all the primitives.
+    bounds: AABB,
     // Flat triangle list in object space for the rasterizer. Non-triangle
     // children contribute nothing. Built once at construction.
-    raster_tris: Vec<(Vec3, Vec3, Vec3, Color, Vec3)>,
+    raster_tris: Vec<RasterTri>,
+    // The loader clause that recreates this mesh (e.g. `STL bunny.stl`) when
+    // it was read from a file, and the name it was registered under with
+    // `DEF`. Both are only used to write the scene back out.
+    source: Option<String>,
+    name: Option<String>,
+}
+
+impl Mesh {
+    pub fn new(children: Vec<Box<dyn Thing>>, enable_aabb: bool) -> Self {
+        Mesh::build(vec![], children, enable_aabb, None)
+    }
+
+    pub fn from_triangles(tris: Vec<Triangle>, enable_aabb: bool) -> Self {
+        Mesh::build(tris, vec![], enable_aabb, None)
+    }
+
+    // `from_triangles` with a BVH over `tris` that was built before, read
+    // back from a cache.
+    pub fn with_bvh(tris: Vec<Triangle>, bvh: Bvh) -> Self {
+        Mesh::build(tris, vec![], true, Some(bvh))
+    }
+
+    fn build(
+        tris: Vec<Triangle>,
+        children: Vec<Box<dyn Thing>>,
+        enable_aabb: bool,
+        bvh: Option<Bvh>,
+    ) -> Self {
+        let mut child_aabbs: Vec<AABB> = tris.par_iter().map(|t| t.aabb()).collect();
+        child_aabbs.par_extend(children.par_iter().map(|c| {
+            let mut a = AABB::new();
+            c.update_aabb(&mut a);
+            a
+        }));
+        let bvh = match bvh {
+            Some(bvh) => Some(bvh),
+            None if enable_aabb => Some(Bvh::from_bounds(&child_aabbs)),
+            None => None,
+        };
+        let raster_tris: Vec<_> = tris
+            .iter()
+            .filter_map(|t| t.raster_tri())
+            .chain(children.iter().filter_map(|c| c.raster_tri()))
+            .collect();
+        let mut bounds = AABB::new();
+        for a in &child_aabbs {
+            bounds.merge(a);
+        }
+        Mesh {
+            tris,
+            children,
+            bvh,
+            bounds,
+            raster_tris,
+            source: None,
+            name: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: String) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // Every primitive, in the order the BVH numbers them.
+    pub fn primitives(&self) -> Vec<&dyn Visible> {
+        let tris = self.tris.iter().map(|t| t as &dyn Visible);
+        tris.chain(self.children.iter().map(|c| c.as_ref() as &dyn Visible))
+            .collect()
+    }
+
+    pub fn bvh(&self) -> Option<&Bvh> {
+        self.bvh.as_ref()
+    }
+
+    pub fn raster_tris(&self) -> &[RasterTri] {
+        &self.raster_tris
+    }
+
+    // In object space.
+    pub fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn intersect_primitive(&self, i: usize, ray: &Ray) -> Option<(Vec3, Vec3, Color)> {
+        match self.tris.get(i) {
+            Some(t) => t.intersect(ray),
+            None => self.children[i - self.tris.len()].intersect(ray),
+        }
+    }
+
+    // `ray` is in object space.
+    fn intersect(&self, ray: &Ray) -> Option<(Vec3, Vec3, Color)> {
+        if let Some(bvh) = &self.bvh {
+            return bvh.intersect_with(ray, |i| self.intersect_primitive(i, ray));
+        }
+        // --aabb off: linear scan, keeping the nearest hit like the BVH
+        // does, so both give the same picture.
+        (0..self.tris.len() + self.children.len())
+            .filter_map(|i| self.intersect_primitive(i, ray))
+            .min_by(|a, b| {
+                (a.0 - ray.p)
+                    .dot(ray.d)
+                    .total_cmp(&(b.0 - ray.p).dot(ray.d))
+            })
+    }
+}
+
+pub struct Object {
+    mesh: Arc<Mesh>,
+    m: Option<Box<dyn Movement>>,
+    // Where the object is in the world at the current time: `rest` put where
+    // the movement has it, offset by the track, then placed by the parent.
+    transform: Transform,
+    track: Option<Track>,
+    // Relative to the parent for a nested object, else to the world.
+    rest: Transform,
+    // Objects nested in this one. They go wherever it goes.
+    children: Vec<Object>,
+    // Given with `NAME`, for cameras to look at.
+    name: Option<String>,
 }
 
 impl Object {
@@ -298,78 +537,182 @@ impl Object {
         enable_aabb: bool,
         _debug: bool,
     ) -> Self {
-        let bvh = if enable_aabb {
-            Some(Bvh::build(&children))
-        } else {
-            None
-        };
-        let raster_tris: Vec<_> = children.iter().filter_map(|c| c.raster_tri()).collect();
+        Object::instance(Arc::new(Mesh::new(children, enable_aabb)), m)
+    }
+
+    // Another placement of an existing mesh. Only the transform and movement
+    // are per instance.
+    pub fn instance(mesh: Arc<Mesh>, m: Option<Box<dyn Movement>>) -> Self {
         Object {
-            children,
+            mesh,
             m,
-            bvh,
             transform: Transform::identity(),
-            raster_tris,
+            track: None,
+            rest: Transform::identity(),
+            children: vec![],
+            name: None,
         }
     }
 
+    pub fn mesh(&self) -> &Arc<Mesh> {
+        &self.mesh
+    }
+
     pub fn transform(&self) -> &Transform {
         &self.transform
     }
 
-    pub fn raster_tris(&self) -> &[(Vec3, Vec3, Vec3, Color, Vec3)] {
-        &self.raster_tris
+    pub fn set_transform(&mut self, t: Transform) {
+        self.rest = t;
+        self.place(t);
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    pub fn children(&self) -> &[Object] {
+        &self.children
+    }
+
+    // Nest `child` in this object, its transform now relative to this one.
+    pub fn add_child(&mut self, mut child: Object) {
+        child.place(self.transform.compose(&child.rest));
+        self.children.push(child);
+    }
+
+    // This object and every one nested in it, parents before children.
+    pub fn subtree(&self) -> Vec<&Object> {
+        let mut all = vec![self];
+        for child in &self.children {
+            all.extend(child.subtree());
+        }
+        all
+    }
+
+    // Put the object at `world` and its children at rest relative to it.
+    fn place(&mut self, world: Transform) {
+        self.transform = world;
+        for child in &mut self.children {
+            child.place(world.compose(&child.rest));
+        }
+    }
+
+    // Pose the object and its children at time `t`, relative to `parent` if
+    // it is nested.
+    fn pose(&mut self, t: f32, parent: Option<&Transform>) {
+        let moved = match &self.m {
+            Some(mv) => mv.transform_at(t, &self.rest),
+            None => self.rest,
+        };
+        let local = match &self.track {
+            Some(track) => track.transform_at(t, &moved),
+            None => moved,
+        };
+        self.transform = match parent {
+            Some(parent) => parent.compose(&local),
+            None => local,
+        };
+        let world = self.transform;
+        for child in &mut self.children {
+            child.pose(t, Some(&world));
+        }
+    }
+
+    pub fn movement(&self) -> Option<&dyn Movement> {
+        self.m.as_deref()
+    }
+
+    pub fn track(&self) -> Option<&Track> {
+        self.track.as_ref()
+    }
+
+    pub fn track_mut(&mut self) -> &mut Track {
+        self.track.get_or_insert_with(Track::default)
+    }
+
+    // The transform before any movement or track.
+    pub fn rest(&self) -> &Transform {
+        &self.rest
+    }
+
+    pub fn raster_tris(&self) -> &[RasterTri] {
+        self.mesh.raster_tris()
     }
 }
 
+// The object named `name` among `objects` and those nested in them.
+pub fn find_object<'a>(objects: &'a [Box<dyn Thing>], name: &str) -> Option<&'a Object> {
+    objects
+        .iter()
+        .filter_map(|obj| obj.as_object())
+        .flat_map(Object::subtree)
+        .find(|o| o.name() == Some(name))
+}
+
 impl Visible for Object {
     fn intersect(&self, ray: &Ray) -> Option<(Vec3, Vec3, Color)> {
         // Transform the world-space ray into the body's object space. Both the
         // BVH and the linear-scan fallback intersect against geometry stored in
         // object space, so the input must be converted regardless of which path
-        // runs. Quaternion rotation preserves direction length, so primitives
-        // that assume a normalized direction (Triangle, Sphere) keep working.
+        // runs. The local direction is renormalized, since a scale changes its
+        // length; that changes the ray parameter of a hit but not where the
+        // hit is, and callers compare hits by their world-space points.
         let local_ray = Ray {
             p: self.transform.world_to_object_point(ray.p),
             d: self.transform.world_to_object_dir(ray.d),
         };
-        let local_hit = if let Some(bvh) = &self.bvh {
-            bvh.intersect(&local_ray, &self.children)
-        } else {
-            // --aabb off: first-hit linear scan, pre-existing semantics.
-            let mut hit = None;
-            for child in &self.children {
-                if let Some(rtn) = child.intersect(&local_ray) {
-                    hit = Some(rtn);
-                    break;
-                }
-            }
-            hit
-        };
-        local_hit.map(|(p, n, c)| {
+        let hit = self.mesh.intersect(&local_ray).map(|(p, n, c)| {
             (
                 self.transform.object_to_world_point(p),
-                self.transform.object_to_world_dir(n),
+                self.transform.object_to_world_normal(n),
                 c,
             )
-        })
+        });
+        // Children are in world space already; keep the nearest hit.
+        self.children
+            .iter()
+            .filter_map(|child| child.intersect(ray))
+            .chain(hit)
+            .min_by(|a, b| {
+                (a.0 - ray.p)
+                    .dot(ray.d)
+                    .total_cmp(&(b.0 - ray.p).dot(ray.d))
+            })
     }
 
-    fn update_aabb(&self, _aabb: &mut AABB) {}
+    // Where the mesh's box lands in the world at the current time, and the
+    // boxes of the nested objects.
+    fn update_aabb(&self, aabb: &mut AABB) {
+        if !self.mesh.bounds.is_empty() {
+            for corner in self.mesh.bounds.corners() {
+                aabb.update(&self.transform.object_to_world_point(corner));
+            }
+        }
+        for child in &self.children {
+            child.update_aabb(aabb);
+        }
+    }
 
     fn as_object(&self) -> Option<&Object> {
         Some(self)
     }
+
+    fn as_object_mut(&mut self) -> Option<&mut Object> {
+        Some(self)
+    }
 }
 
 impl Updatable for Object {
-    fn update(&mut self, _t: f32, dt: f32, _m: Option<&Box<dyn Movement>>) {
-        if let Some(mv) = &self.m {
-            mv.update_transform(dt, &mut self.transform);
-        }
+    fn update(&mut self, t: f32, dt: f32, _m: Option<&Box<dyn Movement>>) {
+        // Like a movement's step, an update brings the object to `t + dt`,
+        // worked out from the rest pose rather than from the last frame.
+        self.pose(t + dt, None);
     }
 }
 
-unsafe impl Sync for Object {}
-
 impl Thing for Object {}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+
+use base64::Engine;
+use glam::f32::{Mat4, Quat, Vec3};
+use gltf::camera::Projection;
+use gltf::khr_lights_punctual::Kind;
+use gltf::mesh::Mode;
+use gltf::{Gltf, Node};
+
+use crate::util::Transform;
+
+// glTF 2.0 reader for `.gltf` (JSON) and `.glb` (binary) files. Walks the
+// node tree of the default scene:
+//
+// - each glTF mesh becomes one list of triangles (triangle, strip and fan
+//   primitives; points and lines are skipped), read once however many nodes
+//   place it,
+// - the nodes holding meshes, and those above them, keep their names and
+//   their transforms relative to their parents, to become nested objects,
+// - a camera becomes its eye, view direction and vertical field of view or
+//   half-height, baked into scene space,
+// - a KHR_lights_punctual light becomes a directional or point light, baked
+//   the same way; spot lights are read as point lights.
+//
+// glTF is Y-up while cosmo scenes are Z-up, so the whole scene is turned
+// 90 degrees around X on the way in. An object's scale only carries over to
+// the objects nested in it when it is uniform, so a node scaled per axis
+// has the meshes below it baked into one of its own. A node given as a
+// matrix is taken apart into translation, rotation and scale, losing any
+// shear. Camera roll is dropped, cameras here only have a direction.
+// Materials, textures, skins and animations are ignored.
+
+pub struct GltfMesh {
+    pub triangles: Vec<[Vec3; 3]>,
+}
+
+pub struct GltfNode {
+    pub name: Option<String>,
+    // Relative to the parent node, or to the scene for a root node.
+    pub transform: Transform,
+    // Index into `GltfScene::meshes`, in the node's own space.
+    pub mesh: Option<usize>,
+    // Only those that hold a mesh somewhere below them.
+    pub children: Vec<GltfNode>,
+}
+
+pub enum GltfCamera {
+    Perspective { p: Vec3, d: Vec3, yfov: f32 },
+    Ortho { p: Vec3, d: Vec3, ymag: f32 },
+}
+
+pub enum GltfLight {
+    // Intensity in lux.
+    Directional { d: Vec3, intensity: f32 },
+    // Intensity in candela.
+    Point { p: Vec3, intensity: f32 },
+}
+
+pub struct GltfScene {
+    // The meshes the nodes place.
+    pub meshes: Vec<GltfMesh>,
+    // Root nodes of the scene that hold a mesh somewhere below them.
+    pub nodes: Vec<GltfNode>,
+    pub cameras: Vec<GltfCamera>,
+    pub lights: Vec<GltfLight>,
+}
+
+// Read a glTF file. `load` fetches external buffers by the URI the file
+// gives for them; `data:` URIs and the `.glb` binary chunk are handled here.
+pub fn read_gltf<F>(data: &[u8], load: F) -> Result<GltfScene, String>
+where
+    F: Fn(&str) -> Result<Vec<u8>, String>,
+{
+    let gltf = Gltf::from_slice(data).map_err(|e| e.to_string())?;
+
+    let mut buffers: Vec<Vec<u8>> = vec![];
+    for buffer in gltf.buffers() {
+        let mut bytes = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or("buffer refers to a missing binary chunk")?,
+            gltf::buffer::Source::Uri(uri) => match uri.strip_prefix("data:") {
+                Some(rest) => {
+                    let (_, payload) = rest
+                        .split_once(";base64,")
+                        .ok_or("only base64 data URIs are supported")?;
+                    base64::engine::general_purpose::STANDARD
+                        .decode(payload)
+                        .map_err(|e| format!("bad data URI: {}", e))?
+                }
+                None => load(uri)?,
+            },
+        };
+        if bytes.len() < buffer.length() {
+            return Err(format!(
+                "buffer {} is {} bytes, expected {}",
+                buffer.index(),
+                bytes.len(),
+                buffer.length()
+            ));
+        }
+        // The binary chunk may be padded past the declared length.
+        bytes.truncate(buffer.length());
+        buffers.push(bytes);
+    }
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or("file has no scene")?;
+    let mut reader = Reader {
+        buffers,
+        read: HashMap::new(),
+        out: GltfScene {
+            meshes: vec![],
+            nodes: vec![],
+            cameras: vec![],
+            lights: vec![],
+        },
+    };
+    let root = Transform {
+        rotation: Quat::from_rotation_x(FRAC_PI_2),
+        ..Transform::identity()
+    };
+    for node in scene.nodes() {
+        if let Some(mut node) = reader.visit(&node, Mat4::from_quat(root.rotation))? {
+            node.transform = root.compose(&node.transform);
+            reader.out.nodes.push(node);
+        }
+    }
+    Ok(reader.out)
+}
+
+struct Reader {
+    buffers: Vec<Vec<u8>>,
+    // Where each glTF mesh read so far went in `out.meshes`.
+    read: HashMap<usize, usize>,
+    out: GltfScene,
+}
+
+impl Reader {
+    // `node` and the mesh nodes below it, or None if it has none. Cameras
+    // and lights on the way are added to `out`, placed by `parent` and the
+    // node transforms down to them.
+    fn visit(&mut self, node: &Node, parent: Mat4) -> Result<Option<GltfNode>, String> {
+        let m = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        let eye = m.transform_point3(Vec3::ZERO);
+        let forward = m.transform_vector3(Vec3::NEG_Z).normalize();
+
+        if let Some(camera) = node.camera() {
+            self.out.cameras.push(match camera.projection() {
+                Projection::Perspective(p) => GltfCamera::Perspective {
+                    p: eye,
+                    d: forward,
+                    yfov: p.yfov(),
+                },
+                Projection::Orthographic(o) => GltfCamera::Ortho {
+                    p: eye,
+                    d: forward,
+                    ymag: o.ymag(),
+                },
+            });
+        }
+
+        if let Some(light) = node.light() {
+            let intensity = light.intensity();
+            self.out.lights.push(match light.kind() {
+                Kind::Directional => GltfLight::Directional {
+                    d: forward,
+                    intensity,
+                },
+                Kind::Point | Kind::Spot { .. } => GltfLight::Point { p: eye, intensity },
+            });
+        }
+
+        let mut children = vec![];
+        for child in node.children() {
+            children.extend(self.visit(&child, m)?);
+        }
+        let mut mesh = match node.mesh() {
+            Some(mesh) => Some(self.mesh(&mesh)?),
+            None => None,
+        };
+        let (t, r, s) = node.transform().decomposed();
+        let transform = Transform {
+            translation: Vec3::from_array(t),
+            rotation: Quat::from_array(r),
+            scale: Vec3::from_array(s),
+        };
+        if !transform.is_uniform() && !children.is_empty() {
+            let mut triangles = vec![];
+            if let Some(i) = mesh {
+                triangles.extend_from_slice(&self.out.meshes[i].triangles);
+            }
+            for child in &children {
+                self.bake(child, Mat4::IDENTITY, &mut triangles);
+            }
+            self.out.meshes.push(GltfMesh { triangles });
+            mesh = Some(self.out.meshes.len() - 1);
+            children.clear();
+        }
+        if mesh.is_none() && children.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(GltfNode {
+            name: node.name().map(|name| name.to_string()),
+            transform,
+            mesh,
+            children,
+        }))
+    }
+
+    // The triangles of `node` and those below it, in the space `parent`
+    // puts it in.
+    fn bake(&self, node: &GltfNode, parent: Mat4, triangles: &mut Vec<[Vec3; 3]>) {
+        let t = &node.transform;
+        let m = parent * Mat4::from_scale_rotation_translation(t.scale, t.rotation, t.translation);
+        // A mirroring transform turns the winding around, which would leave
+        // the faces pointing inwards.
+        let flip = m.determinant() < 0.;
+        if let Some(i) = node.mesh {
+            triangles.extend(self.out.meshes[i].triangles.iter().map(|tri| {
+                let [a, b, c] = tri.map(|p| m.transform_point3(p));
+                if flip {
+                    [a, c, b]
+                } else {
+                    [a, b, c]
+                }
+            }));
+        }
+        for child in &node.children {
+            self.bake(child, m, triangles);
+        }
+    }
+
+    // Index in `out.meshes` of `mesh`, read the first time a node places it.
+    fn mesh(&mut self, mesh: &gltf::Mesh) -> Result<usize, String> {
+        if let Some(&i) = self.read.get(&mesh.index()) {
+            return Ok(i);
+        }
+        let mut triangles = vec![];
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|b| self.buffers.get(b.index()).map(|v| v.as_slice()));
+            let positions: Vec<Vec3> = match reader.read_positions() {
+                Some(iter) => iter.map(Vec3::from_array).collect(),
+                None => continue,
+            };
+            let indices: Vec<usize> = match reader.read_indices() {
+                Some(iter) => iter.into_u32().map(|i| i as usize).collect(),
+                None => (0..positions.len()).collect(),
+            };
+            if let Some(i) = indices.iter().find(|&&i| i >= positions.len()) {
+                let name = match mesh.name() {
+                    Some(name) => format!("mesh `{}`", name),
+                    None => format!("mesh {}", mesh.index()),
+                };
+                return Err(format!(
+                    "{} primitive {}: vertex index {} out of range (has {} vertices)",
+                    name,
+                    primitive.index(),
+                    i,
+                    positions.len()
+                ));
+            }
+            let mut push = |a: usize, b: usize, c: usize| {
+                triangles.push([a, b, c].map(|i| positions[indices[i]]));
+            };
+            match primitive.mode() {
+                Mode::Triangles => {
+                    for i in (0..indices.len() / 3).map(|k| 3 * k) {
+                        push(i, i + 1, i + 2);
+                    }
+                }
+                Mode::TriangleStrip => {
+                    for i in 0..indices.len().saturating_sub(2) {
+                        // Every other triangle of a strip is wound backwards.
+                        if i % 2 == 0 {
+                            push(i, i + 1, i + 2);
+                        } else {
+                            push(i + 1, i, i + 2);
+                        }
+                    }
+                }
+                Mode::TriangleFan => {
+                    for i in 1..indices.len().saturating_sub(1) {
+                        push(0, i, i + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.out.meshes.push(GltfMesh { triangles });
+        self.read.insert(mesh.index(), self.out.meshes.len() - 1);
+        Ok(self.out.meshes.len() - 1)
+    }
+}
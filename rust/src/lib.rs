@@ -9,23 +9,37 @@ use wasm_bindgen::prelude::*;
 #[cfg(feature = "wasm_rayon")]
 pub use wasm_bindgen_rayon::init_thread_pool;
 
+#[cfg(target_arch = "wasm32")]
 use std::collections::HashMap;
+#[cfg(target_arch = "wasm32")]
 use std::panic;
 
+#[cfg(target_arch = "wasm32")]
 use crate::loader::parse_scene;
+#[cfg(target_arch = "wasm32")]
 use crate::player::Player;
+#[cfg(target_arch = "wasm32")]
+use crate::scene::RenderSettings;
 
 pub mod aabb;
 pub mod bvh;
+pub mod cache;
 pub mod camera;
 pub mod engine;
+pub mod gltf;
 pub mod light;
 pub mod loader;
 pub mod movement;
+pub mod obj;
+pub mod ply;
 pub mod player;
 pub mod raster;
+pub mod scene;
 pub mod sharpen;
+pub mod stl;
+pub mod track;
 pub mod util;
+pub mod writer;
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
@@ -65,24 +79,33 @@ impl PlayerWASM {
         sharpen: bool,
         stl_data_name: Vec<String>,
         stl_data: Vec<Uint8Array>,
-    ) -> Self {
-        let (objs, camera, lights) = parse_scene(
-            scene,
+    ) -> Result<PlayerWASM, JsError> {
+        let settings = RenderSettings {
             w,
             h,
-            false,
+            fr,
             enable_aabb,
+            disable_shade,
+            debug: false,
+            raster,
+            sharpen,
+            // A prebuilt `<file>.cosmo-bvh` passed along with the STL data
+            // is used if it matches; there is nothing to write caches to.
+            bvh_cache: true,
+        };
+        let scene = parse_scene(
+            scene,
+            settings,
             None,
             prepare_stl_data(stl_data_name, stl_data),
-        );
-        let mut p = Player::new(fr, w, h, camera, disable_shade, false, raster, sharpen);
-        for obj in objs {
-            p.add_object(obj);
-        }
-        for light in lights {
-            p.add_light(light);
-        }
-        PlayerWASM { player: p }
+        )
+        .map_err(|errors| {
+            let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            JsError::new(&lines.join("\n"))
+        })?;
+        Ok(PlayerWASM {
+            player: Player::new(scene).map_err(|e| JsError::new(&e))?,
+        })
     }
 
     pub fn get_a(&self) -> Vec<String> {
@@ -97,4 +120,23 @@ impl PlayerWASM {
     pub fn update(&mut self) {
         self.player.update();
     }
+
+    // Show the frame at `t` seconds; playing goes on from there.
+    pub fn set_time(&mut self, t: f32) {
+        self.player.seek(t);
+    }
+
+    pub fn camera_names(&self) -> Vec<String> {
+        self.player.camera_names()
+    }
+
+    // Switch to the named camera, leaving the scene's cuts; false if the
+    // scene has no camera of that name.
+    pub fn set_camera(&mut self, name: &str) -> bool {
+        self.player.set_camera(name)
+    }
+
+    pub fn follow_cuts(&mut self) {
+        self.player.follow_cuts();
+    }
 }
@@ -1,21 +1,63 @@
 use glam::Vec3;
 
-use crate::engine::Thing;
+use crate::bvh::Tlas;
 use crate::movement::Movement;
-use crate::util::Ray;
+use crate::track::Track;
+use crate::util::{fmt_num, fmt_vec3, Ray};
 
 pub trait LightInt {
     fn get_ray(&self, p: Vec3) -> Ray;
     fn get_lum(&self, p: Vec3, n: Vec3, out_d: Vec3) -> f32;
     fn update(&mut self, t: f32, dt: f32);
+    // The light as an `L` line, followed by the `KEY` lines of its track.
+    fn to_cos(&self) -> String;
+    // The light's keyframe track, started empty if it has none yet.
+    fn track_mut(&mut self) -> &mut Track;
 }
 
 pub trait Light: LightInt + Sync {}
 
+fn light_to_cos(
+    kind: char,
+    v: Vec3,
+    l: f32,
+    m: &Option<Box<dyn Movement>>,
+    track: &Option<Track>,
+) -> String {
+    let mut s = format!("L {} {} {}", kind, fmt_vec3(v), fmt_num(l));
+    if let Some(m) = m {
+        s.push(' ');
+        s.push_str(&m.to_cos());
+    }
+    if let Some(track) = track {
+        for line in track.to_cos() {
+            s.push('\n');
+            s.push_str(&line);
+        }
+    }
+    s
+}
+
 pub struct DirectionalLight {
+    // Direction at the current time, and at time 0 before any movement or
+    // track.
     pub d: Vec3,
+    pub rest: Vec3,
     pub l: f32,
     pub m: Option<Box<dyn Movement>>,
+    pub track: Option<Track>,
+}
+
+impl DirectionalLight {
+    pub fn new(d: Vec3, l: f32, m: Option<Box<dyn Movement>>) -> Self {
+        DirectionalLight {
+            d,
+            rest: d,
+            l,
+            m,
+            track: None,
+        }
+    }
 }
 
 impl LightInt for DirectionalLight {
@@ -31,24 +73,49 @@ impl LightInt for DirectionalLight {
         return -self.d.dot(n) * self.l;
     }
 
-    fn update(&mut self, _t: f32, dt: f32) {
-        match &self.m {
-            Some(mv) => {
-                mv.update_direction(dt, &mut self.d);
-            }
-            None => {}
-        }
+    fn update(&mut self, t: f32, dt: f32) {
+        let t = t + dt;
+        let d = match &self.m {
+            Some(mv) => mv.direction_at(t, self.rest),
+            None => self.rest,
+        };
+        self.d = match &self.track {
+            Some(track) => track.direction_at(t, d),
+            None => d,
+        };
+    }
+
+    fn to_cos(&self) -> String {
+        light_to_cos('D', self.rest, self.l, &self.m, &self.track)
     }
-}
 
-unsafe impl Sync for DirectionalLight {}
+    fn track_mut(&mut self) -> &mut Track {
+        self.track.get_or_insert_with(Track::default)
+    }
+}
 
 impl Light for DirectionalLight {}
 
 pub struct PointLight {
+    // Position at the current time, and at time 0 before any movement or
+    // track.
     pub p: Vec3,
+    pub rest: Vec3,
     pub l: f32,
     pub m: Option<Box<dyn Movement>>,
+    pub track: Option<Track>,
+}
+
+impl PointLight {
+    pub fn new(p: Vec3, l: f32, m: Option<Box<dyn Movement>>) -> Self {
+        PointLight {
+            p,
+            rest: p,
+            l,
+            m,
+            track: None,
+        }
+    }
 }
 
 impl LightInt for PointLight {
@@ -69,25 +136,34 @@ impl LightInt for PointLight {
         return -d.dot(n) * self.l / (dis * dis).max(1.);
     }
 
-    fn update(&mut self, _t: f32, dt: f32) {
-        match &self.m {
-            Some(mv) => {
-                mv.update_point(dt, &mut self.p);
-            }
-            None => {}
-        }
+    fn update(&mut self, t: f32, dt: f32) {
+        let t = t + dt;
+        let p = match &self.m {
+            Some(mv) => mv.point_at(t, self.rest),
+            None => self.rest,
+        };
+        self.p = match &self.track {
+            Some(track) => track.point_at(t, p),
+            None => p,
+        };
     }
-}
 
-unsafe impl Sync for PointLight {}
+    fn to_cos(&self) -> String {
+        light_to_cos('P', self.rest, self.l, &self.m, &self.track)
+    }
+
+    fn track_mut(&mut self) -> &mut Track {
+        self.track.get_or_insert_with(Track::default)
+    }
+}
 
 impl Light for PointLight {}
 
 // Brightness ramp, sparse to dense. Used by lum_to_char when --sharpen is off.
 // Expanded from the original 13-char ramp for finer gradation.
 pub const BRIGHTNESS_RAMP: &[char] = &[
-    '.', '\'', '`', ',', ':', ';', '~', '-', '+', '=', '<', '>', '!', '*', '?', 'l',
-    'i', '/', '\\', '|', '(', ')', 'o', 'x', 'X', '#', '%', '&', '$', '@', 'M',
+    '.', '\'', '`', ',', ':', ';', '~', '-', '+', '=', '<', '>', '!', '*', '?', 'l', 'i', '/',
+    '\\', '|', '(', ')', 'o', 'x', 'X', '#', '%', '&', '$', '@', 'M',
 ];
 
 pub fn lum_to_char(lum: f32) -> char {
@@ -97,12 +173,12 @@ pub fn lum_to_char(lum: f32) -> char {
 }
 
 // Compute total luminance at a surface point, optionally checking shadow rays
-// against every object. Matches the original get_color logic minus the
+// against the objects of `world`. Matches the original get_color logic minus the
 // brightness-ramp lookup so callers can either go to a char or feed a sub-cell
 // lum buffer (for --sharpen).
 pub fn get_lum(
     lights: &Vec<Box<dyn Light>>,
-    objects: &Vec<Box<dyn Thing>>,
+    world: &Tlas,
     p: Vec3,
     n: Vec3,
     out_d: Vec3,
@@ -112,19 +188,7 @@ pub fn get_lum(
     for l in lights {
         // Check for blocking
         let ray = l.get_ray(p + 0.001 * n);
-        let mut blocked: bool = false;
-        if !disable_shade {
-            for obj in objects {
-                match obj.intersect(&ray) {
-                    Some(_) => {
-                        blocked = true;
-                        break;
-                    }
-                    None => {}
-                }
-            }
-        }
-        if !blocked {
+        if disable_shade || !world.occluded(&ray) {
             lum += l.get_lum(p, n, out_d);
         }
     }
@@ -133,11 +197,11 @@ pub fn get_lum(
 
 pub fn get_color(
     lights: &Vec<Box<dyn Light>>,
-    objects: &Vec<Box<dyn Thing>>,
+    world: &Tlas,
     p: Vec3,
     n: Vec3,
     out_d: Vec3,
     disable_shade: bool,
 ) -> char {
-    lum_to_char(get_lum(lights, objects, p, n, out_d, disable_shade))
+    lum_to_char(get_lum(lights, world, p, n, out_d, disable_shade))
 }
@@ -1,223 +1,1392 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
 use std::fs::File;
-use std::fs::OpenOptions;
 use std::io::{BufRead, BufReader, Cursor};
+use std::path::Path;
+use std::sync::Arc;
 
 use glam::f32::Vec3;
-use stl_io::{read_stl, IndexedMesh};
+use glam::Quat;
 
-use crate::camera::{Camera, OrthoCamera, PerspectiveCamera};
-use crate::engine::{Object, Sphere, Thing, Torus, Triangle};
+use crate::bvh::Bvh;
+use crate::cache::{cache_name, content_hash, read_cache, write_cache};
+use crate::camera::{
+    auto_camera, Camera, Look, OrthoCamera, PerspectiveCamera, AUTO_MARGIN, CELL_ASPECT,
+};
+use crate::engine::Mesh;
+use crate::gltf::{read_gltf, GltfScene};
 use crate::light::{DirectionalLight, Light, PointLight};
-use crate::movement::{Movement, Rotate};
-use crate::util::{same_dir_file, to_rad, Ray};
+use crate::movement::{
+    stack, Movement, Orbit, Oscillate, Rotate, Scale, Shake, Spin, SplineKind, SplinePath,
+    Translate,
+};
+use crate::obj::{read_obj, ObjMesh};
+use crate::ply::{read_ply, PlyMesh};
+use crate::scene::{RenderSettings, Scene, SceneBuilder};
+use crate::stl::read_stl;
+use crate::track::{Ease, Key, Track};
+use crate::util::{fmt_num, same_dir_file, to_rad, Ray, Stopwatch, Transform};
 
-fn parse_f32(part: &String) -> f32 {
-    part.parse::<f32>().unwrap()
+// A problem found while loading a scene. `line` and `col` are 1-based; a
+// `line` of 0 means the error is about the file as a whole (it could not be
+// read, or it has no camera) rather than any particular line.
+#[derive(Debug, Clone)]
+pub struct SceneError {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+    // Text of the offending line, kept so callers can print it back with a
+    // caret under `col`.
+    pub source: String,
 }
 
-fn parse_char(part: &String) -> char {
-    part.chars().nth(0).unwrap()
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.col, self.message
+            )
+        }
+    }
+}
+
+impl SceneError {
+    fn file_level(file: &str, message: String) -> Self {
+        SceneError {
+            file: file.to_string(),
+            line: 0,
+            col: 0,
+            message,
+            source: String::new(),
+        }
+    }
 }
 
-fn parse_vec3(parts: &[String]) -> Vec3 {
-    Vec3::new(
-        parse_f32(&parts[0]),
-        parse_f32(&parts[1]),
-        parse_f32(&parts[2]),
-    )
+// Error local to one line. The line loop in `parse_scene` lifts it into a
+// SceneError once it knows the file and line number.
+struct LineError {
+    col: usize,
+    message: String,
 }
 
-fn parse_movement(parts: &[String]) -> Option<Box<dyn Movement>> {
-    match parts[0].as_str() {
-        "R" => Some(Box::new(Rotate {
-            rad: to_rad(parse_f32(&parts[1])),
-            axis: Ray {
-                p: parse_vec3(&parts[2..5]),
-                d: parse_vec3(&parts[5..8]).normalize(),
-            },
-        })),
-        _ => None,
+type LineResult<T> = Result<T, LineError>;
+
+struct Token<'a> {
+    text: &'a str,
+    col: usize,
+}
+
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    // (byte offset, column) of the token being scanned, if any.
+    let mut start: Option<(usize, usize)> = None;
+    for (col, (i, c)) in line.char_indices().enumerate() {
+        if c.is_whitespace() {
+            if let Some((s, s_col)) = start.take() {
+                tokens.push(Token {
+                    text: &line[s..i],
+                    col: s_col + 1,
+                });
+            }
+        } else if start.is_none() {
+            start = Some((i, col));
+        }
     }
+    if let Some((s, s_col)) = start {
+        tokens.push(Token {
+            text: &line[s..],
+            col: s_col + 1,
+        });
+    }
+    tokens
 }
 
-fn parse_triangle(parts: &[String], points: &HashMap<String, Vec3>) -> Box<Triangle> {
-    Box::new(Triangle::new(
-        points.get(&parts[0]).unwrap().clone(),
-        points.get(&parts[1]).unwrap().clone(),
-        points.get(&parts[2]).unwrap().clone(),
-        parse_char(&parts[3]),
-    ))
+// A window onto the tokens of one line. Parsing helpers take an `Args` the
+// way they used to take a `&[String]` slice, but every accessor reports the
+// column of whatever it choked on. `end` is the column just past the line,
+// used when an argument is missing altogether.
+#[derive(Clone, Copy)]
+struct Args<'a> {
+    tokens: &'a [Token<'a>],
+    end: usize,
 }
 
-fn parse_sphere(parts: &[String], points: &HashMap<String, Vec3>) -> Box<Sphere> {
-    Box::new(Sphere {
-        o: points.get(&parts[0]).unwrap().clone(),
-        r: parse_f32(&parts[1]),
-        color: parse_char(&parts[2]),
-    })
+impl<'a> Args<'a> {
+    fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn rest(&self, i: usize) -> Args<'a> {
+        Args {
+            tokens: &self.tokens[i.min(self.tokens.len())..],
+            end: self.end,
+        }
+    }
+
+    fn col(&self, i: usize) -> usize {
+        self.tokens.get(i).map_or(self.end, |t| t.col)
+    }
+
+    fn err<T>(&self, i: usize, message: String) -> LineResult<T> {
+        Err(LineError {
+            col: self.col(i),
+            message,
+        })
+    }
+
+    fn str(&self, i: usize) -> LineResult<&'a str> {
+        match self.tokens.get(i) {
+            Some(t) => Ok(t.text),
+            None => self.err(i, "unexpected end of line".to_string()),
+        }
+    }
+
+    fn f32(&self, i: usize) -> LineResult<f32> {
+        let s = self.str(i)?;
+        match s.parse::<f32>() {
+            Ok(v) => Ok(v),
+            Err(_) => self.err(i, format!("expected a number, found `{}`", s)),
+        }
+    }
+
+    fn char(&self, i: usize) -> LineResult<char> {
+        // Tokens are never empty, so there is always a first char.
+        Ok(self.str(i)?.chars().next().unwrap_or(' '))
+    }
+
+    fn vec3(&self, i: usize) -> LineResult<Vec3> {
+        Ok(Vec3::new(self.f32(i)?, self.f32(i + 1)?, self.f32(i + 2)?))
+    }
+
+    // A direction or axis, as a unit vector. `what` names it for the error
+    // when it is zero and has no direction to normalize to. One that is
+    // already of unit length is kept as written, so that writing the scene
+    // back out and reading it again gives the same numbers.
+    fn dir(&self, i: usize, what: &str) -> LineResult<Vec3> {
+        let v = self.vec3(i)?;
+        if v == Vec3::ZERO {
+            return self.err(i, format!("{} is zero", what));
+        }
+        if v.is_normalized() {
+            return Ok(v);
+        }
+        Ok(v.normalize())
+    }
+
+    fn point(&self, i: usize, points: &HashMap<String, Vec3>) -> LineResult<Vec3> {
+        let name = self.str(i)?;
+        match points.get(name) {
+            Some(p) => Ok(*p),
+            None => self.err(i, format!("unknown point `{}`", name)),
+        }
+    }
+}
+
+// Direction `d` of a file placed at `place`, in the scene. Left as it is in
+// the top-level file, where renormalizing it would only change its last
+// digits each time the scene is written back out.
+fn world_dir(place: &Transform, d: Vec3) -> Vec3 {
+    if place.is_identity() {
+        d
+    } else {
+        place.object_to_world_dir(d)
+    }
+}
+
+// Movements are given in the coordinates of the file they appear in, so
+// `place` moves their pivot and axis into the scene. Any number of them can
+// follow one another; they apply in the order given:
+//
+//     R deg px py pz dx dy dz     rotate around the axis through p
+//     T vx vy vz                  move at constant velocity
+//     OSC ax ay az hz             oscillate along a, |a| being the amplitude
+//     ORB deg px py pz dx dy dz   revolve around the axis, keeping orientation
+//     SPIN deg dx dy dz [AT c]    rotate around the object's own origin;
+//                                 lights and bare primitives, having none,
+//                                 turn around c or else the file's origin
+//     SHAKE amp hz seed           jitter by seeded noise
+//     PULSE amp hz [AT c]         grow and shrink, 0 <= amp < 1, about the
+//                                 origin or c as for SPIN
+//     PATH CR|BEZ n c1 .. cn speed [ALIGN] [LOOP]
+//                                 follow a Catmull-Rom or Bezier spline
+//                                 through n control points, each a `P` name
+//                                 or `x y z`
+fn parse_movement(
+    args: Args,
+    place: &Transform,
+    points: &HashMap<String, Vec3>,
+) -> LineResult<Option<Box<dyn Movement>>> {
+    if args.is_empty() || args.str(0)? == "-" {
+        return Ok(None);
+    }
+    // A vector in file coordinates, as opposed to a unit direction.
+    let vector = |v: Vec3| place.object_to_world_point(v) - place.translation;
+    let mut members: Vec<Box<dyn Movement>> = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        let (m, used): (Box<dyn Movement>, usize) = match args.str(i)? {
+            "R" => (
+                Box::new(Rotate {
+                    rad: to_rad(args.f32(i + 1)?),
+                    axis: Ray {
+                        p: place.object_to_world_point(args.vec3(i + 2)?),
+                        d: world_dir(place, args.dir(i + 5, AXIS)?),
+                    },
+                }),
+                8,
+            ),
+            "T" => (
+                Box::new(Translate {
+                    v: vector(args.vec3(i + 1)?),
+                }),
+                4,
+            ),
+            "OSC" => (
+                Box::new(Oscillate {
+                    a: vector(args.vec3(i + 1)?),
+                    hz: args.f32(i + 4)?,
+                }),
+                5,
+            ),
+            "ORB" => (
+                Box::new(Orbit {
+                    rad: to_rad(args.f32(i + 1)?),
+                    axis: Ray {
+                        p: place.object_to_world_point(args.vec3(i + 2)?),
+                        d: world_dir(place, args.dir(i + 5, AXIS)?),
+                    },
+                }),
+                8,
+            ),
+            "SPIN" => {
+                let (center, used) = parse_center(args.rest(i + 5), place)?;
+                (
+                    Box::new(Spin {
+                        rad: to_rad(args.f32(i + 1)?),
+                        d: world_dir(place, args.dir(i + 2, AXIS)?),
+                        center,
+                    }),
+                    5 + used,
+                )
+            }
+            "SHAKE" => {
+                let seed = args.str(i + 3)?;
+                let seed = match seed.parse::<u32>() {
+                    Ok(seed) => seed,
+                    Err(_) => return args.err(i + 3, format!("expected a seed, found `{}`", seed)),
+                };
+                (
+                    Box::new(Shake {
+                        amp: args.f32(i + 1)? * place.scale.x,
+                        hz: args.f32(i + 2)?,
+                        seed,
+                    }),
+                    4,
+                )
+            }
+            "PULSE" => {
+                let amp = args.f32(i + 1)?;
+                if !(0. ..1.).contains(&amp) {
+                    return args.err(i + 1, "pulse amplitude must be in [0, 1)".to_string());
+                }
+                let (center, used) = parse_center(args.rest(i + 3), place)?;
+                (
+                    Box::new(Scale {
+                        amp,
+                        hz: args.f32(i + 2)?,
+                        center,
+                    }),
+                    3 + used,
+                )
+            }
+            "PATH" => {
+                let kind = match args.str(i + 1)? {
+                    "CR" => SplineKind::CatmullRom,
+                    "BEZ" => SplineKind::Bezier,
+                    other => {
+                        return args.err(i + 1, format!("expected CR or BEZ, found `{}`", other))
+                    }
+                };
+                let n = args.f32(i + 2)?;
+                if n < 0. || n.fract() != 0. {
+                    return args.err(i + 2, "expected a number of points".to_string());
+                }
+                let mut j = i + 3;
+                let mut controls = vec![];
+                for _ in 0..n as usize {
+                    let p = if args.f32(j).is_ok() {
+                        j += 3;
+                        args.vec3(j - 3)?
+                    } else {
+                        j += 1;
+                        args.point(j - 1, points)?
+                    };
+                    controls.push(place.object_to_world_point(p));
+                }
+                let speed = args.f32(j)? * place.scale.x;
+                let (mut align, mut closed) = (false, false);
+                let mut k = j + 1;
+                loop {
+                    match args.str(k) {
+                        Ok("ALIGN") => align = true,
+                        Ok("LOOP") => closed = true,
+                        _ => break,
+                    }
+                    k += 1;
+                }
+                let path = match SplinePath::new(kind, controls, closed, speed, align) {
+                    Ok(path) => path,
+                    Err(e) => return args.err(i + 2, e),
+                };
+                (Box::new(path), k - i)
+            }
+            other => return args.err(i, format!("unknown movement type `{}`", other)),
+        };
+        members.push(m);
+        i += used;
+    }
+    Ok(stack(members))
+}
+
+// Optional `AT x y z` after SPIN and PULSE, in the coordinates of the file.
+// Without it the center is the file's origin, wherever `place` puts it.
+// Returns the center in the scene and how many tokens it used.
+fn parse_center(args: Args, place: &Transform) -> LineResult<(Vec3, usize)> {
+    if args.is_empty() || args.str(0)? != "AT" {
+        return Ok((place.translation, 0));
+    }
+    Ok((place.object_to_world_point(args.vec3(1)?), 4))
+}
+
+// Optional `SIZE r` after a PLY path: the radius of each point when the file
+// is a point cloud. Returns the size and how many tokens it used.
+fn parse_point_size(args: Args) -> LineResult<(Option<f32>, usize)> {
+    if args.is_empty() || args.str(0)? != "SIZE" {
+        return Ok((None, 0));
+    }
+    let r = args.f32(1)?;
+    if r <= 0. {
+        return args.err(1, "point size must be positive".to_string());
+    }
+    Ok((Some(r), 2))
+}
+
+// Optional `POS x y z`, `ROT deg ax ay az` and `SCALE s` (or
+// `SCALE sx sy sz`) clauses, in any order. The scale is applied first, then the rotation, then the
+// translation. Stops at the first token that starts none of the clauses and
+// returns how many tokens were used.
+fn parse_placement(args: Args) -> LineResult<(Transform, usize)> {
+    let mut t = Transform::identity();
+    let used = parse_placement_into(args, &mut t)?;
+    Ok((t, used))
 }
 
-fn parse_torus(parts: &[String], _points: &HashMap<String, Vec3>, debug: bool) -> Box<Torus> {
-    Box::new(Torus::new(
-        parse_vec3(&parts[0..3]).normalize(),
-        parse_vec3(&parts[3..6]),
-        parse_f32(&parts[6]),
-        parse_f32(&parts[7]),
-        parse_char(&parts[8]),
-        debug,
-    ))
+// `parse_placement`, setting the parts it finds on an existing transform.
+fn parse_placement_into(args: Args, t: &mut Transform) -> LineResult<usize> {
+    let mut i = 0;
+    while i < args.len() {
+        match args.str(i)? {
+            "POS" => {
+                t.translation = args.vec3(i + 1)?;
+                i += 4;
+            }
+            "ROT" => {
+                let rad = to_rad(args.f32(i + 1)?);
+                let axis = args.dir(i + 2, AXIS)?;
+                t.rotation = Quat::from_axis_angle(axis, rad);
+                i += 5;
+            }
+            "SCALE" => {
+                // `SCALE s` or, per axis, `SCALE sx sy sz`.
+                let per_axis =
+                    i + 3 < args.len() && args.f32(i + 2).is_ok() && args.f32(i + 3).is_ok();
+                let n = if per_axis { 3 } else { 1 };
+                for k in 1..=n {
+                    if args.f32(i + k)? <= 0. {
+                        return args.err(i + k, "scale must be positive".to_string());
+                    }
+                }
+                t.scale = if per_axis {
+                    args.vec3(i + 1)?
+                } else {
+                    Vec3::splat(args.f32(i + 1)?)
+                };
+                i += 1 + n;
+            }
+            _ => break,
+        }
+    }
+    Ok(i)
 }
 
-fn parse_camera(parts: &[String], w: usize, h: usize) -> Option<Box<dyn Camera>> {
-    match parts[0].as_str() {
+// `O d p scale [movement]`, `P d p scale f [movement]`,
+// `FOV eye target up deg [ASPECT a] [movement]` or
+// `AUTO [O|P] [d] [m] [movement]`. The movement carries the eye like
+// a point light and turns the view direction with it. An AUTO camera looks
+// along `d` (default -1 -1 -1) and is placed to fit the whole scene once it
+// is loaded, leaving `m` (default 0.1) of its size free on each side.
+fn parse_camera(
+    args: Args,
+    w: usize,
+    h: usize,
+    points: &HashMap<String, Vec3>,
+) -> LineResult<Box<dyn Camera>> {
+    let (mut camera, used): (Box<dyn Camera>, usize) = match args.str(0)? {
         "O" => {
-            let d = parse_vec3(&parts[1..4]).normalize();
-            let p = parse_vec3(&parts[4..7]);
-            let scale = parse_f32(&parts[7]);
-            Some(Box::new(OrthoCamera::new(d, p, scale, w, h)))
+            let d = args.dir(1, "camera direction")?;
+            let p = args.vec3(4)?;
+            let scale = args.f32(7)?;
+            (Box::new(OrthoCamera::new(d, p, scale, w, h)), 8)
         }
         "P" => {
-            let d = parse_vec3(&parts[1..4]).normalize();
-            let p = parse_vec3(&parts[4..7]);
-            let scale = parse_f32(&parts[7]);
-            let f = parse_f32(&parts[8]);
-            Some(Box::new(PerspectiveCamera::new(d, p, scale, f, w, h)))
+            let d = args.dir(1, "camera direction")?;
+            let p = args.vec3(4)?;
+            let scale = args.f32(7)?;
+            let f = args.f32(8)?;
+            (Box::new(PerspectiveCamera::new(d, p, scale, f, w, h)), 9)
+        }
+        "AUTO" => {
+            let (ortho, mut i) = match args.str(1) {
+                Ok("O") => (true, 2),
+                Ok("P") => (false, 2),
+                _ => (false, 1),
+            };
+            // The numbers after the lens say which form it is: one is a
+            // margin, three a direction and four a direction and a margin.
+            let numbers = (i..i + 5).take_while(|&j| args.f32(j).is_ok()).count();
+            let (mut d, mut margin) = (Vec3::new(-1., -1., -1.).normalize(), AUTO_MARGIN);
+            if numbers == 3 || numbers == 4 {
+                d = args.dir(i, "camera direction")?;
+                i += 3;
+            }
+            match numbers {
+                0 | 3 => {}
+                1 | 4 => {
+                    margin = args.f32(i)?;
+                    if margin < 0. {
+                        return args.err(i, "margin must not be negative".to_string());
+                    }
+                    i += 1;
+                }
+                _ => {
+                    return args.err(
+                        i,
+                        "expected a margin `m`, a direction `dx dy dz` or both".to_string(),
+                    )
+                }
+            }
+            (auto_camera(ortho, d, margin, w, h), i)
+        }
+        "FOV" => {
+            let eye = args.vec3(1)?;
+            let target = args.vec3(4)?;
+            if target == eye {
+                return args.err(4, "camera target is the same as its eye".to_string());
+            }
+            let up = args.vec3(7)?;
+            if (target - eye).cross(up).length_squared() == 0. {
+                return args.err(7, "up vector is zero or along the view".to_string());
+            }
+            let fov = args.f32(10)?;
+            if fov <= 0. || fov >= 180. {
+                return args.err(
+                    10,
+                    "field of view must be between 0 and 180 degrees".to_string(),
+                );
+            }
+            let (aspect, used) = match args.str(11) {
+                Ok("ASPECT") => {
+                    let aspect = args.f32(12)?;
+                    if aspect <= 0. {
+                        return args.err(12, "cell aspect must be positive".to_string());
+                    }
+                    (aspect, 13)
+                }
+                _ => (CELL_ASPECT, 11),
+            };
+            let camera = PerspectiveCamera::look_at(eye, target, up, fov, aspect, w, h);
+            (Box::new(camera), used)
+        }
+        other => return args.err(0, format!("unknown camera type `{}`", other)),
+    };
+    camera.rig_mut().m = parse_movement(args.rest(used), &Transform::identity(), points)?;
+    Ok(camera)
+}
+
+// `t x y z deg ax ay az s [ease]`, the rest of a KEY line: at time `t`, an
+// offset of (x, y, z), a turn of `deg` around the axis and a scale of `s`
+// (or `sx sy sz`) from the rest pose. The ease is LINEAR (the default),
+// SMOOTH, STEP or `BEZIER x1 y1 x2 y2`.
+fn parse_key(args: Args, place: &Transform) -> LineResult<Key> {
+    let t = args.f32(0)?;
+    let pos = args.vec3(1)?;
+    let rad = to_rad(args.f32(4)?);
+    let axis = args.dir(5, AXIS)?;
+    let per_axis = args.len() >= 11 && args.f32(9).is_ok() && args.f32(10).is_ok();
+    let n = if per_axis { 3 } else { 1 };
+    for k in 0..n {
+        if args.f32(8 + k)? <= 0. {
+            return args.err(8 + k, "scale must be positive".to_string());
+        }
+    }
+    let scale = if per_axis {
+        args.vec3(8)?
+    } else {
+        Vec3::splat(args.f32(8)?)
+    };
+    let i = 8 + n;
+    let (ease, used) = match args.str(i) {
+        Err(_) => (Ease::Linear, 0),
+        Ok("LINEAR") => (Ease::Linear, 1),
+        Ok("SMOOTH") => (Ease::Smooth, 1),
+        Ok("STEP") => (Ease::Step, 1),
+        Ok("BEZIER") => {
+            for k in [1, 3] {
+                if !(0. ..=1.).contains(&args.f32(i + k)?) {
+                    return args.err(i + k, "Bezier x must be in [0, 1]".to_string());
+                }
+            }
+            (
+                Ease::Bezier(
+                    args.f32(i + 1)?,
+                    args.f32(i + 2)?,
+                    args.f32(i + 3)?,
+                    args.f32(i + 4)?,
+                ),
+                5,
+            )
         }
-        _ => None,
+        Ok(other) => return args.err(i, format!("unknown ease `{}`", other)),
+    };
+    if i + used < args.len() {
+        let found = args.str(i + used)?;
+        return args.err(i + used, format!("unexpected `{}`", found));
+    }
+    // The offset is given in file coordinates, like a movement.
+    let axis = world_dir(place, axis);
+    Ok(Key {
+        t,
+        pos: place.object_to_world_point(pos) - place.translation,
+        rot: Quat::from_axis_angle(axis, rad),
+        scale,
+        ease,
+    })
+}
+
+fn parse_light(
+    args: Args,
+    place: &Transform,
+    points: &HashMap<String, Vec3>,
+) -> LineResult<Box<dyn Light>> {
+    match args.str(0)? {
+        "D" => Ok(Box::new(DirectionalLight::new(
+            world_dir(place, args.dir(1, "light direction")?),
+            args.f32(4)?,
+            parse_movement(args.rest(5), place, points)?,
+        ))),
+        "P" => Ok(Box::new(PointLight::new(
+            place.object_to_world_point(args.vec3(1)?),
+            args.f32(4)?,
+            parse_movement(args.rest(5), place, points)?,
+        ))),
+        other => args.err(0, format!("unknown light type `{}`", other)),
+    }
+}
+
+// Write the BVH of `mesh`, read from STL file `name`, to its cache next to
+// the file. Only on disk: the WASM build takes caches through the data map
+// but has nowhere to keep new ones. A cache that cannot be written only
+// costs the next run the time to build it, so that is just a warning.
+fn write_bvh_cache(name: &str, base: Option<&str>, mesh: &Mesh, hash: u64) {
+    let (base, bvh) = match (base, mesh.bvh()) {
+        (Some(base), Some(bvh)) => (base, bvh),
+        _ => return,
+    };
+    let path = same_dir_file(&cache_name(name), base);
+    if let Err(e) = fs::write(&path, write_cache(bvh, hash)) {
+        eprintln!("warning: cannot write BVH cache `{}`: {}", path, e);
     }
 }
 
-fn parse_light(parts: &[String]) -> Box<dyn Light> {
-    match parts[0].as_str() {
-        "D" => Box::new(DirectionalLight {
-            d: parse_vec3(&parts[1..4]).normalize(),
-            l: parse_f32(&parts[4]),
-            m: parse_movement(&parts[5..]),
-        }),
-        "P" => Box::new(PointLight {
-            p: parse_vec3(&parts[1..4]),
-            l: parse_f32(&parts[4]),
-            m: parse_movement(&parts[5..]),
-        }),
-        _ => panic!("Unknown light type: {}", parts[0].as_str()),
+// Key identifying a file on the include stack. Canonical paths make
+// `a.cos` and `./a.cos` the same file; fall back to the path as written if
+// it does not exist (the open will fail and report that anyway).
+fn include_key(path: &str) -> String {
+    match fs::canonicalize(path) {
+        Ok(p) => p.to_string_lossy().to_string(),
+        Err(_) => path.to_string(),
     }
 }
 
-fn parse_stl_file(filename: &str, base_path: &str) -> IndexedMesh {
-    let filepath = same_dir_file(filename, base_path);
-    let mut file = OpenOptions::new().read(true).open(filepath).unwrap();
-    read_stl(&mut file).unwrap()
+const AXIS: &str = "rotation axis";
+
+const NESTED_SCALE: &str = "scale of an OBJ block holding OBJ blocks must be uniform";
+
+// Per-file parsing state. The top-level scene and every INCLUDEd file get
+// their own point table and placement.
+struct FileCtx {
+    // Name used in error messages.
+    label: String,
+    // Path that relative STL and INCLUDE names resolve against. None when
+    // files come from the in-memory data map instead (WASM).
+    path: Option<String>,
+    points: HashMap<String, Vec3>,
+    // This file's path relative to the top-level file's directory. File
+    // names are recorded relative to the top-level file so the scene can be
+    // written back out as one file.
+    rel: String,
+    // Where this file sits in the scene; identity for the top-level file.
+    place: Transform,
+    // Placement of the OBJ block being read, from its POS/ROT/SCALE lines.
+    obj_place: Transform,
+    // Inside an OBJ block.
+    in_obj: bool,
+    // Placements of the blocks the one being read is nested in, innermost
+    // last.
+    outer: Vec<Transform>,
+    // The block being read holds blocks of its own. Their placements
+    // compose with its one, which only works out for a uniform scale.
+    has_nested: bool,
+    // What the last line added, for the KEY, TRACK, LOOK and NAME lines
+    // after it. None after any other line, so that those cannot reach past
+    // it to something added further up.
+    last: Option<Added>,
+    included: bool,
 }
 
-fn load_stl(stl: IndexedMesh, parts: &[String], enable_aabb: bool, debug: bool) -> Box<dyn Thing> {
-    println!(
-        "num vertices: {}, num faces: {}",
-        stl.vertices.len(),
-        stl.faces.len()
-    );
+#[derive(Clone, Copy)]
+enum Added {
+    Object,
+    Light,
+    Camera,
+    // A camera line of an included file, which is dropped along with its
+    // keys.
+    Skipped,
+}
 
-    let mut children: Vec<Box<dyn Thing>> = vec![];
-    for face in stl.faces {
-        children.push(Box::new(Triangle::new(
-            Vec3::from_array(stl.vertices[face.vertices[0]].into()),
-            Vec3::from_array(stl.vertices[face.vertices[1]].into()),
-            Vec3::from_array(stl.vertices[face.vertices[2]].into()),
-            '.',
-        )));
+// `name`, as given in `ctx`'s file, relative to the top-level file instead.
+// Names in the data map are looked up as they are, wherever they appear.
+fn source_name(name: &str, ctx: &FileCtx) -> String {
+    match ctx.path {
+        Some(_) => same_dir_file(name, &ctx.rel),
+        None => name.to_string(),
     }
-    let m = parse_movement(&parts[0..]);
-    Box::new(Object::new(children, m, enable_aabb, debug))
 }
 
-pub fn parse_scene(
-    scene: Vec<String>,
-    w: usize,
-    h: usize,
-    debug: bool,
-    enable_aabb: bool,
-    filename: Option<&str>,
-    stl_data: HashMap<String, Vec<u8>>,
-) -> (Vec<Box<dyn Thing>>, Box<dyn Camera>, Vec<Box<dyn Light>>) {
-    let mut points: HashMap<String, Vec3> = HashMap::new();
-    let mut things: Vec<Box<dyn Thing>> = vec![];
-    let mut children: Vec<Box<dyn Thing>> = vec![];
-    let mut camera: Option<Box<dyn Camera>> = None;
-    let mut lights: Vec<Box<dyn Light>> = vec![];
-    let mut m: Option<Box<dyn Movement>> = None;
-
-    for line in scene {
-        if line.starts_with("//") {
-            continue;
-        }
-        let parts: Vec<String> = line.split(' ').map(|s| s.to_string()).collect();
-        match parts[0].as_str() {
+// A name a line refers to that may only be given further down the scene.
+enum Ref {
+    Object(String),
+    Camera(String),
+}
+
+// Loader state carried across the lines of a scene and its includes.
+struct SceneParser<'a> {
+    stl_data: &'a HashMap<String, Vec<u8>>,
+    builder: SceneBuilder,
+    errors: Vec<SceneError>,
+    // Keys of the files currently being parsed, outermost first.
+    include_stack: Vec<String>,
+    // Object and camera names given with NAME so far.
+    names: HashSet<String>,
+    camera_names: HashSet<String>,
+    // `LOOK OBJ` targets and `CUT` cameras, with the error to give if
+    // nothing has the name by the end of the scene. Those of the line being
+    // read wait in `new_refs` until it turns out to be fine.
+    refs: Vec<(Ref, SceneError)>,
+    new_refs: Vec<(Ref, LineError)>,
+}
+
+impl SceneParser<'_> {
+    fn parse_lines(&mut self, lines: &[String], ctx: &mut FileCtx) {
+        for (i, line) in lines.iter().enumerate() {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with("//") {
+                continue;
+            }
+            let tokens = tokenize(line);
+            let args = Args {
+                tokens: &tokens,
+                end: line.chars().count() + 1,
+            };
+            let result = self.parse_line(args, ctx);
+            let new_refs = std::mem::take(&mut self.new_refs);
+            let error = |e: LineError| SceneError {
+                file: ctx.label.clone(),
+                line: i + 1,
+                col: e.col,
+                message: e.message,
+                source: line.clone(),
+            };
+            match result {
+                Ok(()) => {
+                    for (r, e) in new_refs {
+                        self.refs.push((r, error(e)));
+                    }
+                }
+                Err(e) => self.errors.push(error(e)),
+            }
+        }
+    }
+
+    fn parse_line(&mut self, args: Args, ctx: &mut FileCtx) -> LineResult<()> {
+        match args.str(0)? {
             "P" => {
-                points.insert(parts[1].clone(), parse_vec3(&parts[2..5]));
+                let name = args.str(1)?.to_string();
+                let p = args.vec3(2)?;
+                ctx.points.insert(name, p);
+                ctx.last = None;
+            }
+            "OBJ" => {
+                // A block inside another is a child of it, placed relative
+                // to it.
+                if ctx.in_obj {
+                    if !ctx.obj_place.is_uniform() {
+                        return args.err(0, NESTED_SCALE.to_string());
+                    }
+                    let place = std::mem::replace(&mut ctx.obj_place, Transform::identity());
+                    ctx.outer.push(place);
+                    self.builder.begin_child();
+                }
+                ctx.in_obj = true;
+                ctx.has_nested = false;
             }
-            "OBJ" => { /* start parsing object, nothing to do */ }
-            "END_OBJ" => {
-                things.push(Box::new(Object::new(children, m, enable_aabb, debug)));
-                children = vec![];
-                m = None;
+            "END_OBJ" if !ctx.in_obj => {
+                return args.err(0, "END_OBJ without an open OBJ".to_string());
             }
-            "M" => match m {
+            "END_OBJ" => match ctx.outer.pop() {
+                Some(outer) => {
+                    let place = std::mem::replace(&mut ctx.obj_place, outer);
+                    self.builder.transform(place).end_object();
+                    ctx.has_nested = true;
+                }
                 None => {
-                    m = parse_movement(&parts[1..]);
+                    let place = ctx.place.compose(&ctx.obj_place);
+                    self.builder.transform(place).end_object();
+                    ctx.obj_place = Transform::identity();
+                    ctx.in_obj = false;
+                    ctx.last = Some(Added::Object);
                 }
-                _ => {}
             },
-            "T" => children.push(parse_triangle(&parts[1..], &points)),
-            "S" => children.push(parse_sphere(&parts[1..], &points)),
-            "TRS" => children.push(parse_torus(&parts[1..], &points, debug)),
-            "C" => match camera {
-                None => {
-                    camera = parse_camera(&parts[1..], w, h);
+            "POS" | "ROT" | "SCALE" => {
+                // Placement of the enclosing OBJ block; each line sets its
+                // part, wherever it appears in the block.
+                let mut place = ctx.obj_place;
+                let used = parse_placement_into(args, &mut place)?;
+                if used < args.len() {
+                    let found = args.str(used)?;
+                    return args.err(used, format!("unexpected `{}`", found));
+                }
+                if ctx.has_nested && !place.is_uniform() {
+                    return args.err(0, NESTED_SCALE.to_string());
+                }
+                ctx.obj_place = place;
+            }
+            "M" => {
+                if let Some(m) = parse_movement(args.rest(1), &ctx.place, &ctx.points)? {
+                    self.builder.movement(m);
+                }
+                ctx.last = None;
+            }
+            "T" => {
+                let a = args.point(1, &ctx.points)?;
+                let b = args.point(2, &ctx.points)?;
+                let c = args.point(3, &ctx.points)?;
+                self.builder.triangle(a, b, c, args.char(4)?);
+                ctx.last = None;
+            }
+            "S" => {
+                let o = args.point(1, &ctx.points)?;
+                self.builder.sphere(o, args.f32(2)?, args.char(3)?);
+                ctx.last = None;
+            }
+            "TRS" => {
+                let d = args.dir(1, "torus axis")?;
+                let p = args.vec3(4)?;
+                self.builder
+                    .torus(d, p, args.f32(7)?, args.f32(8)?, args.char(9)?);
+                ctx.last = None;
+            }
+            "C" => {
+                // The including scene decides where to look from, so an
+                // included file's camera is ignored. This lets a complete
+                // scene be dropped into a bigger one.
+                if !ctx.included {
+                    let settings = self.builder.settings();
+                    let camera = parse_camera(args.rest(1), settings.w, settings.h, &ctx.points)?;
+                    self.builder.camera(camera);
+                    ctx.last = Some(Added::Camera);
+                } else {
+                    ctx.last = Some(Added::Skipped);
+                }
+            }
+            "L" => {
+                self.builder
+                    .light(parse_light(args.rest(1), &ctx.place, &ctx.points)?);
+                ctx.last = Some(Added::Light);
+            }
+            "LOOK" => {
+                // `LOOK point` or `LOOK OBJ name`, turning the camera before
+                // it to face the point or the named object's origin.
+                let look = match args.str(1)? {
+                    "OBJ" => Look::Object(args.str(2)?.to_string()),
+                    _ if args.f32(1).is_ok() => Look::Point(args.vec3(1)?),
+                    _ => Look::Point(args.point(1, &ctx.points)?),
+                };
+                match ctx.last {
+                    Some(Added::Camera) => {}
+                    Some(Added::Skipped) => return Ok(()),
+                    _ => return args.err(0, "LOOK has to follow a C line".to_string()),
+                }
+                if let Look::Object(name) = &look {
+                    let e = args.err::<()>(2, format!("no object named `{}`", name));
+                    self.new_refs
+                        .push((Ref::Object(name.clone()), e.unwrap_err()));
+                }
+                if let Some(camera) = self.builder.last_camera_mut() {
+                    camera.rig_mut().look = Some(look);
+                }
+            }
+            "NAME" => {
+                // Names the object of the OBJ block it is in, or else the
+                // camera or object the line before it added.
+                let name = args.str(1)?;
+                match ctx.last {
+                    _ if ctx.in_obj => {}
+                    Some(Added::Camera) => {
+                        if self.camera_names.contains(name) {
+                            return args.err(1, format!("camera name `{}` is already used", name));
+                        }
+                        if let Some(camera) = self.builder.last_camera_mut() {
+                            camera.rig_mut().name = Some(name.to_string());
+                        }
+                        self.camera_names.insert(name.to_string());
+                        return Ok(());
+                    }
+                    Some(Added::Skipped) => return Ok(()),
+                    _ => {}
+                }
+                if self.names.contains(name) {
+                    return args.err(1, format!("object name `{}` is already used", name));
+                }
+                if ctx.in_obj {
+                    self.builder.name(name);
+                } else {
+                    match (ctx.last, self.builder.last_object_mut()) {
+                        (Some(Added::Object), Some(o)) => o.set_name(name),
+                        _ => {
+                            return args.err(
+                                0,
+                                "nothing to name here; put it in an OBJ block or after a C, mesh or INST line"
+                                    .to_string(),
+                            )
+                        }
+                    }
+                }
+                self.names.insert(name.to_string());
+            }
+            "CUT" => {
+                // `CUT t camera [DISSOLVE secs]`: switch to the named camera
+                // at time `t`. Like cameras, the cuts of an included file
+                // are ignored.
+                let t = args.f32(1)?;
+                let name = args.str(2)?.to_string();
+                let dissolve = match args.len() {
+                    3 => 0.,
+                    _ if args.str(3)? == "DISSOLVE" => {
+                        let d = args.f32(4)?;
+                        if d < 0. {
+                            return args.err(4, "dissolve time cannot be negative".to_string());
+                        }
+                        if args.len() > 5 {
+                            return args.err(5, format!("unexpected `{}`", args.str(5)?));
+                        }
+                        d
+                    }
+                    _ => {
+                        return args.err(3, format!("expected DISSOLVE, found `{}`", args.str(3)?))
+                    }
+                };
+                if ctx.included {
+                    return Ok(());
+                }
+                if self.builder.last_cut().is_some_and(|c| c.t >= t) {
+                    return args.err(1, "cut times must increase".to_string());
+                }
+                let e = args.err::<()>(2, format!("no camera named `{}`", name));
+                self.new_refs
+                    .push((Ref::Camera(name.clone()), e.unwrap_err()));
+                self.builder.cut(t, &name, dissolve);
+            }
+            "KEY" => {
+                let key = parse_key(args.rest(1), &ctx.place)?;
+                if let Some(track) = self.track(args, ctx)? {
+                    if track.keys.last().is_some_and(|k| k.t >= key.t) {
+                        return args.err(1, "key times must increase".to_string());
+                    }
+                    track.keys.push(key);
+                }
+            }
+            "TRACK" => {
+                let looped = match args.str(1)? {
+                    "LOOP" => true,
+                    "CLAMP" => false,
+                    other => {
+                        return args.err(1, format!("expected LOOP or CLAMP, found `{}`", other))
+                    }
+                };
+                if let Some(track) = self.track(args, ctx)? {
+                    track.looped = looped;
                 }
-                _ => {}
+            }
+            "STL" | "OBJ_FILE" | "PLY" | "INST" | "GLTF" | "INCLUDE" if ctx.in_obj => {
+                // These add objects of their own, which would take the
+                // movements, track and name meant for the open block.
+                return args.err(
+                    0,
+                    format!("`{}` cannot go inside an OBJ block", args.str(0)?),
+                );
+            }
+            "STL" | "OBJ_FILE" | "PLY" => {
+                // `STL file.stl [POS ..] [ROT ..] [SCALE s] [movement]`, and
+                // the same after the options of OBJ_FILE and PLY.
+                let (mesh, used) = self.load_mesh(args, 0, ctx)?;
+                let (local, placed) = parse_placement(args.rest(used))?;
+                let m = parse_movement(args.rest(used + placed), &ctx.place, &ctx.points)?;
+                self.builder
+                    .instance(Arc::new(mesh), ctx.place.compose(&local), m);
+                ctx.last = Some(Added::Object);
+            }
+            "GLTF" => {
+                ctx.last = match self.gltf(args, ctx)? {
+                    true => Some(Added::Object),
+                    false => None,
+                };
+            }
+            "DEF" => self.define(args, ctx)?,
+            "INST" => {
+                let name = args.str(1)?;
+                let mesh = match self.builder.mesh(name) {
+                    Some(mesh) => mesh,
+                    None => return args.err(1, format!("unknown mesh `{}`", name)),
+                };
+                let (local, used) = parse_placement(args.rest(2))?;
+                let m = parse_movement(args.rest(2 + used), &ctx.place, &ctx.points)?;
+                self.builder.instance(mesh, ctx.place.compose(&local), m);
+                ctx.last = Some(Added::Object);
+            }
+            "INCLUDE" => {
+                self.include(args, ctx)?;
+                ctx.last = None;
+            }
+            other => return args.err(0, format!("unknown line type `{}`", other)),
+        }
+        Ok(())
+    }
+
+    // The track a KEY or TRACK line adds to: that of the OBJ block it is in,
+    // or else that of whatever the line before it added. None when that was
+    // dropped.
+    fn track(&mut self, args: Args, ctx: &FileCtx) -> LineResult<Option<&mut Track>> {
+        if ctx.in_obj {
+            return Ok(Some(self.builder.track()));
+        }
+        let track = match ctx.last {
+            Some(Added::Object) => self.builder.last_object_mut().map(|o| o.track_mut()),
+            Some(Added::Light) => self.builder.last_light_mut().map(|l| l.track_mut()),
+            Some(Added::Camera) => self.builder.last_camera_mut().map(|c| c.track_mut()),
+            Some(Added::Skipped) => return Ok(None),
+            None => None,
+        };
+        match track {
+            Some(track) => Ok(Some(track)),
+            None => args.err(
+                0,
+                "nothing to animate here; put it in an OBJ block or after an L, C, mesh or INST line"
+                    .to_string(),
+            ),
+        }
+    }
+
+    // Contents of the file named `name`, read from disk next to `base` or,
+    // without one, taken from the data map. Also returns the path it
+    // resolved to, for messages.
+    fn read_named(&self, name: &str, base: Option<&str>) -> Result<(String, Vec<u8>), String> {
+        match base {
+            Some(base) => {
+                let path = same_dir_file(name, base);
+                match fs::read(&path) {
+                    Ok(data) => Ok((path, data)),
+                    Err(e) => Err(format!("cannot open `{}`: {}", path, e)),
+                }
+            }
+            None => match self.stl_data.get(name) {
+                Some(data) => Ok((name.to_string(), data.clone())),
+                None => Err(format!("no data provided for `{}`", name)),
             },
-            "L" => lights.push(parse_light(&parts[1..])),
+        }
+    }
+
+    // `read_named` for the file named by token `i`.
+    fn read_data(&self, args: Args, i: usize, ctx: &FileCtx) -> LineResult<(String, Vec<u8>)> {
+        self.read_named(args.str(i)?, ctx.path.as_deref())
+            .or_else(|e| args.err(i, e))
+    }
+
+    // The faces of STL `data`, read from `path` for token `i`.
+    fn read_stl(
+        &self,
+        args: Args,
+        i: usize,
+        path: &str,
+        data: Vec<u8>,
+    ) -> LineResult<Vec<[Vec3; 3]>> {
+        let progress = |done, total| match total {
+            Some(total) => eprintln!("{}: {} of {} faces", path, done, total),
+            None => eprintln!("{}: {} faces", path, done),
+        };
+        read_stl(&mut Cursor::new(data), progress)
+            .or_else(|e| args.err(i, format!("cannot read STL `{}`: {}", path, e)))
+    }
+
+    // Whether STL meshes go through `.cosmo-bvh` caches. Only with --aabb,
+    // which is what gives them a BVH.
+    fn use_bvh_cache(&self) -> bool {
+        let settings = self.builder.settings();
+        settings.bvh_cache && settings.enable_aabb
+    }
+
+    // The BVH cached for STL file `name` with content hash `hash` and `n`
+    // faces, found the way `read_named` finds files. A cache that is stale
+    // or damaged is reported and left to be rebuilt.
+    fn read_bvh_cache(&self, name: &str, base: Option<&str>, hash: u64, n: usize) -> Option<Bvh> {
+        let (path, data) = self.read_named(&cache_name(name), base).ok()?;
+        match read_cache(&data, hash, n) {
+            Ok(bvh) => Some(bvh),
+            Err(e) => {
+                eprintln!("{}: {}, rebuilding", path, e);
+                None
+            }
+        }
+    }
+
+    fn read_obj(&self, args: Args, i: usize, ctx: &FileCtx) -> LineResult<ObjMesh> {
+        let (path, data) = self.read_data(args, i, ctx)?;
+        read_obj(Cursor::new(data))
+            .or_else(|e| args.err(i, format!("cannot read OBJ `{}`: {}", path, e)))
+    }
+
+    fn read_ply(&self, args: Args, i: usize, ctx: &FileCtx) -> LineResult<PlyMesh> {
+        let (path, data) = self.read_data(args, i, ctx)?;
+        read_ply(&data).or_else(|e| args.err(i, format!("cannot read PLY `{}`: {}", path, e)))
+    }
+
+    fn read_gltf(&self, args: Args, i: usize, ctx: &FileCtx) -> LineResult<GltfScene> {
+        let (path, data) = self.read_data(args, i, ctx)?;
+        // External buffers are named relative to the glTF file itself.
+        let base = ctx.path.as_ref().map(|_| path.as_str());
+        read_gltf(&data, |uri| self.read_named(uri, base).map(|(_, d)| d))
+            .or_else(|e| args.err(i, format!("cannot read glTF `{}`: {}", path, e)))
+    }
+
+    // `GLTF file.gltf [POS ..] [ROT ..] [SCALE s] [movement]`: the mesh
+    // nodes of the file become one object, with the node tree nested in it
+    // and placed and moved as a whole by the optional placement and
+    // movement. Node names are kept for LOOK OBJ, but for those already
+    // taken. Its cameras and lights join the scene's, positioned by the
+    // same placement; those of an included file are ignored, like its `C`
+    // lines. Returns whether there was an object to add.
+    fn gltf(&mut self, args: Args, ctx: &FileCtx) -> LineResult<bool> {
+        let (local, used) = parse_placement(args.rest(2))?;
+        // The node transforms compose with this one, which only works out
+        // for a uniform scale.
+        if !local.is_uniform() {
+            let i = (2..args.len())
+                .find(|&i| args.str(i).is_ok_and(|s| s == "SCALE"))
+                .unwrap();
+            return args.err(i, "GLTF scale must be uniform".to_string());
+        }
+        let place = ctx.place.compose(&local);
+        // Parsed before the file is read so a bad movement is reported
+        // whether or not the file has meshes.
+        let m = parse_movement(args.rest(2 + used), &ctx.place, &ctx.points)?;
+        let start = Stopwatch::start();
+        let mut gltf = self.read_gltf(args, 1, ctx)?;
+        self.builder.phase("read", start);
+        let mut nodes: Vec<_> = gltf.nodes.iter_mut().collect();
+        while let Some(node) = nodes.pop() {
+            if let Some(name) = node.name.take() {
+                if self.names.insert(name.clone()) {
+                    node.name = Some(name);
+                }
+            }
+            nodes.extend(node.children.iter_mut());
+        }
+        if !ctx.included {
+            for camera in &gltf.cameras {
+                self.builder.gltf_camera(camera, &place);
+            }
+        }
+        for light in &gltf.lights {
+            self.builder.gltf_light(light, &place);
+        }
+        if gltf.nodes.is_empty() {
+            return Ok(false);
+        }
+        self.builder.gltf(&gltf, place, m);
+        Ok(true)
+    }
+
+    // Optional `GROUP name` after an OBJ_FILE path, picking one `g`/`o`
+    // group out of the file. Returns the group and how many tokens it used.
+    fn parse_obj_group(&self, args: Args, obj: &ObjMesh) -> LineResult<(Option<String>, usize)> {
+        if args.is_empty() || args.str(0)? != "GROUP" {
+            return Ok((None, 0));
+        }
+        let name = args.str(1)?;
+        if !obj.groups.iter().any(|g| g.name == name) {
+            return args.err(1, format!("no group `{}` in OBJ file", name));
+        }
+        Ok((Some(name.to_string()), 2))
+    }
+
+    // `STL file.stl`, `OBJ_FILE file.obj [GROUP g]` or `PLY file.ply [SIZE r]`
+    // starting at token `i`. Returns the mesh and how many tokens it used.
+    fn load_mesh(&mut self, args: Args, i: usize, ctx: &FileCtx) -> LineResult<(Mesh, usize)> {
+        let name = source_name(args.str(i + 1)?, ctx);
+        let start = Stopwatch::start();
+        match args.str(i)? {
             "STL" => {
-                let stl = match filename {
-                    Some(f) => parse_stl_file(&parts[1], f),
-                    None => {
-                        let data = stl_data.get(&parts[1]).unwrap();
-                        let mut reader = Cursor::new(data);
-                        read_stl(&mut reader).unwrap()
-                    }
+                let (path, data) = self.read_data(args, i + 1, ctx)?;
+                let hash = self.use_bvh_cache().then(|| content_hash(&data));
+                let stl = self.read_stl(args, i + 1, &path, data)?;
+                self.builder.phase("read", start);
+                let file = args.str(i + 1)?;
+                let base = ctx.path.as_deref();
+                let start = Stopwatch::start();
+                let cached = hash.and_then(|h| self.read_bvh_cache(file, base, h, stl.len()));
+                // Written back only when there was no cache to use.
+                let write = hash.filter(|_| cached.is_none());
+                self.builder.phase("cache", start);
+                let mesh = self.builder.stl_mesh(stl, cached);
+                if let Some(h) = write {
+                    let start = Stopwatch::start();
+                    write_bvh_cache(file, base, &mesh, h);
+                    self.builder.phase("cache", start);
+                }
+                Ok((mesh.with_source(format!("STL {}", name)), i + 2))
+            }
+            "OBJ_FILE" => {
+                let obj = self.read_obj(args, i + 1, ctx)?;
+                self.builder.phase("read", start);
+                let (group, used) = self.parse_obj_group(args.rest(i + 2), &obj)?;
+                let mesh = self.builder.obj_file_mesh(&obj, group.as_deref());
+                let source = match &group {
+                    Some(g) => format!("OBJ_FILE {} GROUP {}", name, g),
+                    None => format!("OBJ_FILE {}", name),
                 };
-                things.push(load_stl(stl, &parts[2..], enable_aabb, debug));
+                Ok((mesh.with_source(source), i + 2 + used))
             }
-            _ => {
-                panic!("Unknown line type: {}", line);
+            "PLY" => {
+                let ply = self.read_ply(args, i + 1, ctx)?;
+                self.builder.phase("read", start);
+                let (size, used) = parse_point_size(args.rest(i + 2))?;
+                let mesh = self.builder.ply_mesh(&ply, size);
+                let source = match size {
+                    Some(r) => format!("PLY {} SIZE {}", name, fmt_num(r)),
+                    None => format!("PLY {}", name),
+                };
+                Ok((mesh.with_source(source), i + 2 + used))
+            }
+            other => args.err(i, format!("unknown mesh type `{}`", other)),
+        }
+    }
+
+    // `DEF name STL file.stl`, `DEF name OBJ_FILE file.obj [GROUP g]` or
+    // `DEF name PLY file.ply [SIZE r]`: load a mesh once under `name`, to be
+    // placed any number of times with
+    // `INST name [POS ..] [ROT ..] [SCALE s] [movement]`.
+    // Mesh names are shared by the scene and everything it includes.
+    fn define(&mut self, args: Args, ctx: &FileCtx) -> LineResult<()> {
+        let name = args.str(1)?;
+        if self.builder.mesh(name).is_some() {
+            return args.err(1, format!("mesh `{}` is already defined", name));
+        }
+        let (mesh, _) = self.load_mesh(args, 2, ctx)?;
+        self.builder.define(name, mesh);
+        Ok(())
+    }
+
+    // `INCLUDE file.cos [POS ..] [ROT ..] [SCALE s]`: parse another scene
+    // file in place, with its own point names, positioned by the optional
+    // placement relative to the including file.
+    fn include(&mut self, args: Args, ctx: &FileCtx) -> LineResult<()> {
+        let (local, used) = parse_placement(args.rest(2))?;
+        // Placements inside the file compose with this one, which only
+        // works out for a uniform scale.
+        if !local.is_uniform() {
+            let i = (2..args.len())
+                .find(|&i| args.str(i).is_ok_and(|s| s == "SCALE"))
+                .unwrap();
+            return args.err(i, "INCLUDE scale must be uniform".to_string());
+        }
+        if used + 2 < args.len() {
+            let found = args.str(used + 2)?;
+            return args.err(
+                used + 2,
+                format!("expected `POS`, `ROT` or `SCALE`, found `{}`", found),
+            );
+        }
+        let (path, data) = self.read_data(args, 1, ctx)?;
+        let lines: Vec<String> = String::from_utf8_lossy(&data)
+            .lines()
+            .map(|l| l.to_string())
+            .collect();
+        // On disk the key is the canonical path; in the data map the name
+        // itself.
+        let key = match ctx.path {
+            Some(_) => include_key(&path),
+            None => path.clone(),
+        };
+        if let Some(start) = self.include_stack.iter().position(|k| *k == key) {
+            let mut cycle: Vec<&str> = self.include_stack[start..]
+                .iter()
+                .map(|k| k.as_str())
+                .collect();
+            cycle.push(&key);
+            return args.err(1, format!("include cycle: {}", cycle.join(" -> ")));
+        }
+
+        let mut child = FileCtx {
+            label: path.clone(),
+            path: ctx.path.as_ref().map(|_| path),
+            points: HashMap::new(),
+            rel: same_dir_file(args.str(1)?, &ctx.rel),
+            place: ctx.place.compose(&local),
+            obj_place: Transform::identity(),
+            in_obj: false,
+            outer: vec![],
+            has_nested: false,
+            last: None,
+            included: true,
+        };
+        self.include_stack.push(key);
+        self.parse_lines(&lines, &mut child);
+        self.include_stack.pop();
+        // A block left open would take the lines that follow the INCLUDE as
+        // its own. Closed here so that they stay out of it.
+        if child.in_obj {
+            for _ in 0..=child.outer.len() {
+                self.builder.end_object();
             }
+            return args.err(1, format!("`{}` ends inside an OBJ block", child.label));
         }
+        Ok(())
     }
-    (things, camera.unwrap(), lights)
 }
 
-pub fn parse_file(
-    filename: &str,
-    w: usize,
-    h: usize,
-    debug: bool,
-    enable_aabb: bool,
-) -> (Vec<Box<dyn Thing>>, Box<dyn Camera>, Vec<Box<dyn Light>>) {
-    let file = File::open(&filename).unwrap();
+// Parse a whole scene, reporting every bad line rather than stopping at the
+// first one. STL and INCLUDE files are read relative to `filename` when it is
+// given, and looked up by name in `stl_data` otherwise (the WASM build has no
+// file system).
+pub fn parse_scene(
+    scene: Vec<String>,
+    settings: RenderSettings,
+    filename: Option<&str>,
+    stl_data: HashMap<String, Vec<u8>>,
+) -> Result<Scene, Vec<SceneError>> {
+    let file_label = filename.unwrap_or("<scene>");
+    let mut builder = SceneBuilder::new(settings);
+    if let Some(f) = filename {
+        builder.meta("source", f);
+    }
+    let mut parser = SceneParser {
+        stl_data: &stl_data,
+        builder,
+        errors: vec![],
+        include_stack: filename.map(include_key).into_iter().collect(),
+        names: HashSet::new(),
+        camera_names: HashSet::new(),
+        refs: vec![],
+        new_refs: vec![],
+    };
+    let mut ctx = FileCtx {
+        label: file_label.to_string(),
+        path: filename.map(|f| f.to_string()),
+        points: HashMap::new(),
+        rel: filename
+            .and_then(|f| Path::new(f).file_name())
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        place: Transform::identity(),
+        obj_place: Transform::identity(),
+        in_obj: false,
+        outer: vec![],
+        has_nested: false,
+        last: None,
+        included: false,
+    };
+    parser.parse_lines(&scene, &mut ctx);
+
+    let mut errors = parser.errors;
+    // Not closed quietly by `build`, the same as for an included file.
+    if ctx.in_obj {
+        errors.push(SceneError::file_level(
+            file_label,
+            format!("`{}` ends inside an OBJ block", file_label),
+        ));
+    }
+    for (r, e) in parser.refs {
+        let known = match &r {
+            Ref::Object(name) => parser.names.contains(name),
+            Ref::Camera(name) => parser.camera_names.contains(name),
+        };
+        if !known {
+            errors.push(e);
+        }
+    }
+    if !parser.builder.has_camera() {
+        errors.push(SceneError::file_level(
+            file_label,
+            "scene has no camera (`C` line)".to_string(),
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(parser.builder.build())
+}
+
+pub fn parse_file(filename: &str, settings: RenderSettings) -> Result<Scene, Vec<SceneError>> {
+    let file = File::open(filename).map_err(|e| {
+        vec![SceneError::file_level(
+            filename,
+            format!("cannot open scene: {}", e),
+        )]
+    })?;
     let reader = BufReader::new(file);
     let mut scene: Vec<String> = vec![];
 
     for line in reader.lines() {
-        scene.push(line.expect("fail to read line"));
-    }
-
-    parse_scene(
-        scene,
-        w,
-        h,
-        debug,
-        enable_aabb,
-        Some(filename),
-        HashMap::new(),
-    )
+        match line {
+            Ok(l) => scene.push(l),
+            Err(e) => {
+                return Err(vec![SceneError::file_level(
+                    filename,
+                    format!("cannot read scene: {}", e),
+                )])
+            }
+        }
+    }
+
+    parse_scene(scene, settings, Some(filename), HashMap::new())
 }
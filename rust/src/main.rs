@@ -1,20 +1,33 @@
+use std::process;
+use std::time::{Duration, Instant};
+
 use clap::Parser;
 use rayon::ThreadPoolBuilder;
 
-use crate::loader::parse_file;
+use crate::loader::{parse_file, SceneError};
 use crate::player::Player;
+use crate::scene::{MeshSize, RenderSettings};
+use crate::writer::write_scene;
 
 pub mod aabb;
 pub mod bvh;
+pub mod cache;
 pub mod camera;
 pub mod engine;
+pub mod gltf;
 pub mod light;
 pub mod loader;
 pub mod movement;
+pub mod obj;
+pub mod ply;
 pub mod player;
 pub mod raster;
+pub mod scene;
 pub mod sharpen;
+pub mod stl;
+pub mod track;
 pub mod util;
+pub mod writer;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -25,11 +38,19 @@ struct Args {
     #[arg(long, default_value_t = 24)]
     fr: i32,
 
-    #[arg(short, long)]
-    size: String,
+    #[arg(short, long, required_unless_present = "fmt")]
+    size: Option<String>,
 
-    #[arg(short, long)]
-    duration: f32,
+    #[arg(short, long, required_unless_present_any = ["fmt", "time", "load_only"])]
+    duration: Option<f32>,
+
+    // Time in seconds to start playing from.
+    #[arg(long, default_value_t = 0.)]
+    start: f32,
+
+    // Print the one frame at this time in seconds instead of playing.
+    #[arg(long)]
+    time: Option<f32>,
 
     #[arg(long, default_value_t = false)]
     aabb: bool,
@@ -37,9 +58,15 @@ struct Args {
     #[arg(long, default_value_t = false)]
     debug: bool,
 
+    // Load the scene and report how long each phase of loading took.
     #[arg(long, default_value_t = false)]
     load_only: bool,
 
+    // Keep the BVH built for each STL file in a `.cosmo-bvh` file next to
+    // it, and read it back instead of building it on the next run.
+    #[arg(long, default_value_t = false)]
+    bvh_cache: bool,
+
     #[arg(long, default_value_t = false)]
     disable_shade: bool,
 
@@ -51,6 +78,11 @@ struct Args {
 
     #[arg(long, default_value_t = false)]
     sharpen: bool,
+
+    // Print the scene back out in canonical `.cos` form instead of playing
+    // it.
+    #[arg(long, default_value_t = false)]
+    fmt: bool,
 }
 
 fn parse_size(v: &String) -> (usize, usize) {
@@ -60,33 +92,110 @@ fn parse_size(v: &String) -> (usize, usize) {
     (w, h)
 }
 
+// Print a scene error the way rustc prints a compile error: the message,
+// a `-->` location line, then the offending source line with a caret.
+fn print_scene_error(e: &SceneError) {
+    eprintln!("error: {}", e.message);
+    if e.line == 0 {
+        eprintln!("  --> {}", e.file);
+        return;
+    }
+    let gutter = " ".repeat(e.line.to_string().len());
+    eprintln!("{}--> {}:{}:{}", gutter, e.file, e.line, e.col);
+    eprintln!("{} |", gutter);
+    eprintln!("{} | {}", e.line, e.source);
+    eprintln!("{} | {}^", gutter, " ".repeat(e.col.saturating_sub(1)));
+    eprintln!();
+}
+
+// The size of each mesh the scene read from a file.
+fn print_mesh_sizes(sizes: &[MeshSize]) {
+    for size in sizes {
+        match size.vertices {
+            Some(v) => eprintln!("num vertices: {}, num faces: {}", v, size.faces),
+            None => eprintln!("num faces: {}", size.faces),
+        }
+    }
+}
+
+// Time spent in each loading phase, then in the rest of parsing, setting up
+// the Player and all of it.
+fn print_load_times(phases: &[(&'static str, Duration)], parse: Duration, player: Duration) {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.;
+    let mut rest = parse;
+    for (phase, d) in phases {
+        println!("{:<10} {:>10.2}ms", phase, ms(*d));
+        rest = rest.saturating_sub(*d);
+    }
+    println!("{:<10} {:>10.2}ms", "other", ms(rest));
+    println!("{:<10} {:>10.2}ms", "player", ms(player));
+    println!("{:<10} {:>10.2}ms", "total", ms(parse + player));
+}
+
 fn main() {
     let args = Args::parse();
-    let (w, h) = parse_size(&args.size);
+    let defaults = RenderSettings::default();
+    let (w, h) = match &args.size {
+        Some(size) => parse_size(size),
+        None => (defaults.w, defaults.h),
+    };
     ThreadPoolBuilder::new()
         .num_threads(args.n_threads)
         .build_global()
         .unwrap();
 
-    let (objs, camera, lights) = parse_file(&args.filename, w, h, args.debug, args.aabb);
-    // Somehow setting hight to odd number will cause fuzz edge
-    let mut p = Player::new(
-        args.fr,
+    let settings = RenderSettings {
         w,
         h,
-        camera,
-        args.disable_shade,
-        args.debug,
-        args.raster,
-        args.sharpen,
-    );
-    for obj in objs {
-        p.add_object(obj);
+        fr: args.fr,
+        enable_aabb: args.aabb,
+        disable_shade: args.disable_shade,
+        debug: args.debug,
+        raster: args.raster,
+        sharpen: args.sharpen,
+        bvh_cache: args.bvh_cache,
+    };
+    let start = Instant::now();
+    let mut scene = match parse_file(&args.filename, settings) {
+        Ok(scene) => scene,
+        Err(errors) => {
+            for e in &errors {
+                print_scene_error(e);
+            }
+            eprintln!(
+                "error: could not load `{}` due to {} previous error{}",
+                args.filename,
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" }
+            );
+            process::exit(1);
+        }
+    };
+    print_mesh_sizes(&scene.mesh_sizes);
+    if args.fmt {
+        print!("{}", write_scene(&scene));
+        return;
     }
-    for light in lights {
-        p.add_light(light);
+    let parse_time = start.elapsed();
+    let load_times = std::mem::take(&mut scene.load_times);
+    // Somehow setting hight to odd number will cause fuzz edge
+    let start = Instant::now();
+    let mut p = match Player::new(scene) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+    if args.load_only {
+        print_load_times(&load_times, parse_time, start.elapsed());
+        return;
     }
-    if !&args.load_only {
-        p.run(args.duration);
+    match args.time {
+        Some(t) => {
+            p.seek(t);
+            p.render();
+        }
+        None => p.run(args.start, args.duration.unwrap()),
     }
 }
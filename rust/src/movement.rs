@@ -1,11 +1,32 @@
-use glam::f32::Vec3;
+use std::f32::consts::PI;
 
-use crate::util::{get_norm_vec, Ray, Transform};
+use glam::f32::{Quat, Vec3};
 
-pub trait Movement {
-    fn update_direction(&self, dt: f32, p: &mut Vec3);
-    fn update_point(&self, dt: f32, p: &mut Vec3);
-    fn update_transform(&self, dt: f32, t: &mut Transform);
+use crate::util::{fmt_deg, fmt_num, fmt_vec3, get_norm_vec, Ray, Transform};
+
+// A movement is a closed-form function of time: given where something was
+// when the movement started (time 0), it says where that thing is at any
+// time `t`, with no state carried from one frame to the next. Seeking to a
+// time costs the same as playing up to it and nothing drifts.
+pub trait Movement: Send + Sync {
+    // Where a point that was at `p` at time 0 is at time `t`.
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3;
+    fn direction_at(&self, t: f32, d: Vec3) -> Vec3;
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform;
+    // The inverses: where a point that is at `p` at time `t` was at time 0.
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3;
+    fn direction_from(&self, t: f32, d: Vec3) -> Vec3;
+    // The movement as the loader reads it, e.g. `R 45 0 0 0 0 0 1`.
+    fn to_cos(&self) -> String;
+
+    // One step from `t` to `t + dt`, for things that only keep where they
+    // are now rather than where they started.
+    fn update_point(&self, t: f32, dt: f32, p: &mut Vec3) {
+        *p = self.point_at(t + dt, self.point_from(t, *p));
+    }
+    fn update_direction(&self, t: f32, dt: f32, d: &mut Vec3) {
+        *d = self.direction_at(t + dt, self.direction_from(t, *d));
+    }
 }
 
 #[derive(Default)]
@@ -30,20 +51,588 @@ impl Rotate {
 }
 
 impl Movement for Rotate {
-    fn update_direction(&self, dt: f32, p: &mut Vec3) {
-        let dr = self.rad * dt;
+    fn direction_at(&self, t: f32, p: Vec3) -> Vec3 {
+        let dr = self.rad * t;
         let sin = dr.sin();
         let cos = dr.cos();
-        let dot = self.axis.d.dot(*p);
-        let cross = self.axis.d.cross(*p);
-        *p = (*p) * cos + cross * sin + self.axis.d * dot * (1. - cos);
+        let dot = self.axis.d.dot(p);
+        let cross = self.axis.d.cross(p);
+        p * cos + cross * sin + self.axis.d * dot * (1. - cos)
+    }
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        self.direction_at(t, p - self.axis.p) + self.axis.p
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        let mut moved = *rest;
+        moved.rotate_around(self.axis.d, self.axis.p, self.rad * t);
+        moved
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        self.point_at(-t, p)
+    }
+    fn direction_from(&self, t: f32, d: Vec3) -> Vec3 {
+        self.direction_at(-t, d)
+    }
+    fn to_cos(&self) -> String {
+        format!(
+            "R {} {} {}",
+            fmt_deg(self.rad),
+            fmt_vec3(self.axis.p),
+            fmt_vec3(self.axis.d)
+        )
+    }
+}
+
+// Constant velocity `v`, in units per second.
+pub struct Translate {
+    pub v: Vec3,
+}
+
+impl Movement for Translate {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        p + self.v * t
+    }
+    fn direction_at(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        Transform {
+            translation: rest.translation + self.v * t,
+            ..*rest
+        }
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        p - self.v * t
+    }
+    fn direction_from(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn to_cos(&self) -> String {
+        format!("T {}", fmt_vec3(self.v))
+    }
+}
+
+// Back and forth along `a`, `hz` times a second: the displacement is
+// `a * sin(2 pi hz t)`, so it starts at rest at its placed position.
+pub struct Oscillate {
+    pub a: Vec3,
+    pub hz: f32,
+}
+
+impl Oscillate {
+    fn offset(&self, t: f32) -> Vec3 {
+        self.a * (2. * PI * self.hz * t).sin()
+    }
+}
+
+impl Movement for Oscillate {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        p + self.offset(t)
+    }
+    fn direction_at(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        Transform {
+            translation: rest.translation + self.offset(t),
+            ..*rest
+        }
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        p - self.offset(t)
+    }
+    fn direction_from(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn to_cos(&self) -> String {
+        format!("OSC {} {}", fmt_vec3(self.a), fmt_num(self.hz))
+    }
+}
+
+// Revolves around `axis` like Rotate, but keeps the orientation it has: a
+// moon that always shows the same side to the camera, not to its planet.
+#[derive(Default)]
+pub struct Orbit {
+    pub rad: f32,
+    pub axis: Ray,
+}
+
+impl Orbit {
+    fn turn(&self, t: f32) -> Quat {
+        Quat::from_axis_angle(self.axis.d, self.rad * t)
+    }
+}
+
+impl Movement for Orbit {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        self.turn(t) * (p - self.axis.p) + self.axis.p
+    }
+    fn direction_at(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        Transform {
+            translation: self.point_at(t, rest.translation),
+            ..*rest
+        }
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        self.point_at(-t, p)
+    }
+    fn direction_from(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn to_cos(&self) -> String {
+        format!(
+            "ORB {} {} {}",
+            fmt_deg(self.rad),
+            fmt_vec3(self.axis.p),
+            fmt_vec3(self.axis.d)
+        )
+    }
+}
+
+// Turns around direction `d` through the object's own origin. Points
+// (lights, bare primitives) have no origin of their own and turn around
+// `center` instead. Movements after it in a stack move the object, origin
+// and all, so a spin followed by an orbit keeps spinning in place as it goes
+// round.
+pub struct Spin {
+    pub rad: f32,
+    pub d: Vec3,
+    pub center: Vec3,
+}
+
+impl Spin {
+    fn turn(&self, t: f32) -> Quat {
+        Quat::from_axis_angle(self.d, self.rad * t)
+    }
+}
+
+impl Movement for Spin {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        self.turn(t) * (p - self.center) + self.center
+    }
+    fn direction_at(&self, t: f32, d: Vec3) -> Vec3 {
+        self.turn(t) * d
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        Transform {
+            rotation: self.turn(t) * rest.rotation,
+            ..*rest
+        }
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        self.point_at(-t, p)
+    }
+    fn direction_from(&self, t: f32, d: Vec3) -> Vec3 {
+        self.direction_at(-t, d)
+    }
+    fn to_cos(&self) -> String {
+        format!(
+            "SPIN {} {}{}",
+            fmt_deg(self.rad),
+            fmt_vec3(self.d),
+            fmt_center(self.center)
+        )
+    }
+}
+
+// The `AT` clause of SPIN and PULSE, left out for the scene's origin.
+fn fmt_center(center: Vec3) -> String {
+    if center == Vec3::ZERO {
+        String::new()
+    } else {
+        format!(" AT {}", fmt_vec3(center))
+    }
+}
+
+// Jitter of up to `amp` on each axis from smooth value noise that changes
+// about `hz` times a second. The same `seed` always shakes the same way.
+pub struct Shake {
+    pub amp: f32,
+    pub hz: f32,
+    pub seed: u32,
+}
+
+impl Shake {
+    fn noise_at(&self, t: f32) -> Vec3 {
+        let x = t * self.hz;
+        let i = x.floor();
+        let f = x - i;
+        let f = f * f * (3. - 2. * f);
+        let axis = |k: u32| {
+            let a = noise(self.seed, k, i as i32);
+            let b = noise(self.seed, k, i as i32 + 1);
+            a + (b - a) * f
+        };
+        Vec3::new(axis(0), axis(1), axis(2)) * self.amp
+    }
+
+    // Relative to time 0, so things start where they were placed.
+    fn offset(&self, t: f32) -> Vec3 {
+        self.noise_at(t) - self.noise_at(0.)
+    }
+}
+
+// Hash of (seed, axis, lattice step) to [-1, 1].
+fn noise(seed: u32, axis: u32, i: i32) -> f32 {
+    let mut h = seed
+        .wrapping_mul(0x9e37_79b9)
+        .wrapping_add(axis.wrapping_mul(0x85eb_ca6b))
+        .wrapping_add((i as u32).wrapping_mul(0xc2b2_ae35));
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+impl Movement for Shake {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        p + self.offset(t)
+    }
+    fn direction_at(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        Transform {
+            translation: rest.translation + self.offset(t),
+            ..*rest
+        }
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        p - self.offset(t)
+    }
+    fn direction_from(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn to_cos(&self) -> String {
+        format!(
+            "SHAKE {} {} {}",
+            fmt_num(self.amp),
+            fmt_num(self.hz),
+            self.seed
+        )
+    }
+}
+
+// Pulsing size: the scale goes as `1 + amp * sin(2 pi hz t)` about the
+// object's own origin (`center` for points, as with Spin). `amp` is below 1
+// so the size never reaches zero.
+pub struct Scale {
+    pub amp: f32,
+    pub hz: f32,
+    pub center: Vec3,
+}
+
+impl Scale {
+    fn factor(&self, t: f32) -> f32 {
+        1. + self.amp * (2. * PI * self.hz * t).sin()
+    }
+}
+
+impl Movement for Scale {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        self.center + (p - self.center) * self.factor(t)
+    }
+    fn direction_at(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        Transform {
+            scale: rest.scale * self.factor(t),
+            ..*rest
+        }
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        self.center + (p - self.center) / self.factor(t)
+    }
+    fn direction_from(&self, _t: f32, d: Vec3) -> Vec3 {
+        d
+    }
+    fn to_cos(&self) -> String {
+        format!(
+            "PULSE {} {}{}",
+            fmt_num(self.amp),
+            fmt_num(self.hz),
+            fmt_center(self.center)
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SplineKind {
+    // Through every control point.
+    CatmullRom,
+    // Piecewise cubic: every third point is on the curve, the two between
+    // are handles.
+    Bezier,
+}
+
+// Travels along a spline through `points` at `speed` units per second of
+// arc length, so the speed stays the same however the control points are
+// spaced. Like the other movements it moves things by how far the path has
+// got from its start, so a body placed at the first point rides on the
+// curve. With `align` it also turns with the path, by the turn from the
+// tangent at the start to the tangent where it is at time t. An open path
+// stops at its end; a `closed` one goes round and round.
+pub struct SplinePath {
+    pub kind: SplineKind,
+    pub points: Vec<Vec3>,
+    pub closed: bool,
+    pub speed: f32,
+    pub align: bool,
+    // Arc length at each of PATH_SAMPLES steps per segment.
+    lengths: Vec<f32>,
+}
+
+const PATH_SAMPLES: usize = 32;
+
+impl SplinePath {
+    pub fn new(
+        kind: SplineKind,
+        points: Vec<Vec3>,
+        closed: bool,
+        speed: f32,
+        align: bool,
+    ) -> Result<SplinePath, String> {
+        let n = points.len();
+        match kind {
+            SplineKind::CatmullRom if n < 2 => {
+                return Err("a Catmull-Rom path needs at least 2 points".to_string())
+            }
+            SplineKind::Bezier if closed && (n < 3 || !n.is_multiple_of(3)) => {
+                return Err("a closed Bezier path needs a multiple of 3 points".to_string())
+            }
+            SplineKind::Bezier if !closed && (n < 4 || !(n - 1).is_multiple_of(3)) => {
+                return Err("a Bezier path needs 3k + 1 points".to_string())
+            }
+            _ => {}
+        }
+        let mut path = SplinePath {
+            kind,
+            points,
+            closed,
+            speed,
+            align,
+            lengths: vec![],
+        };
+        let steps = path.segments() * PATH_SAMPLES;
+        let mut total = 0.;
+        let mut prev = path.eval(0.);
+        path.lengths.push(0.);
+        for i in 1..=steps {
+            let p = path.eval(i as f32 / PATH_SAMPLES as f32);
+            total += (p - prev).length();
+            path.lengths.push(total);
+            prev = p;
+        }
+        // With nowhere to go there is no way to face.
+        if align && total == 0. {
+            return Err("an ALIGN path needs points that are not all the same".to_string());
+        }
+        Ok(path)
+    }
+
+    fn segments(&self) -> usize {
+        let n = self.points.len();
+        match (self.kind, self.closed) {
+            (SplineKind::CatmullRom, false) => n - 1,
+            (SplineKind::CatmullRom, true) => n,
+            (SplineKind::Bezier, false) => (n - 1) / 3,
+            (SplineKind::Bezier, true) => n / 3,
+        }
+    }
+
+    fn point(&self, i: isize) -> Vec3 {
+        let n = self.points.len() as isize;
+        let i = if self.closed {
+            i.rem_euclid(n)
+        } else {
+            i.clamp(0, n - 1)
+        };
+        self.points[i as usize]
+    }
+
+    // Position at spline parameter `g`, which runs from 0 to the number of
+    // segments.
+    fn eval(&self, g: f32) -> Vec3 {
+        let last = self.segments() - 1;
+        let seg = (g.max(0.) as usize).min(last);
+        let u = (g - seg as f32).clamp(0., 1.);
+        match self.kind {
+            SplineKind::CatmullRom => {
+                let i = seg as isize;
+                let (p0, p1, p2, p3) = (
+                    self.point(i - 1),
+                    self.point(i),
+                    self.point(i + 1),
+                    self.point(i + 2),
+                );
+                let (u2, u3) = (u * u, u * u * u);
+                0.5 * (2. * p1
+                    + (p2 - p0) * u
+                    + (2. * p0 - 5. * p1 + 4. * p2 - p3) * u2
+                    + (3. * p1 - p0 - 3. * p2 + p3) * u3)
+            }
+            SplineKind::Bezier => {
+                let i = 3 * seg as isize;
+                let (p0, p1, p2, p3) = (
+                    self.point(i),
+                    self.point(i + 1),
+                    self.point(i + 2),
+                    self.point(i + 3),
+                );
+                let r = 1. - u;
+                r * r * r * p0 + 3. * r * r * u * p1 + 3. * r * u * u * p2 + u * u * u * p3
+            }
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.lengths.last().unwrap_or(&0.)
+    }
+
+    // Spline parameter at arc length `s`, wrapped or clamped to the path.
+    fn param(&self, s: f32) -> f32 {
+        let len = self.length();
+        let s = if self.closed && len > 0. {
+            s.rem_euclid(len)
+        } else {
+            s.clamp(0., len)
+        };
+        let i = self
+            .lengths
+            .partition_point(|&l| l <= s)
+            .clamp(1, self.lengths.len() - 1);
+        let (l0, l1) = (self.lengths[i - 1], self.lengths[i]);
+        let frac = if l1 > l0 { (s - l0) / (l1 - l0) } else { 0. };
+        (i - 1) as f32 / PATH_SAMPLES as f32 + frac / PATH_SAMPLES as f32
+    }
+
+    // Position and unit tangent at arc length `s`.
+    pub fn at(&self, s: f32) -> (Vec3, Vec3) {
+        let g = self.param(s);
+        let h = 0.25 / PATH_SAMPLES as f32;
+        let end = self.segments() as f32;
+        let (a, b) = if self.closed {
+            (g - h, g + h)
+        } else {
+            ((g - h).max(0.), (g + h).min(end))
+        };
+        let mut tangent = (self.eval(b) - self.eval(a)).normalize_or_zero();
+        if tangent == Vec3::ZERO {
+            // Stopped on a segment between repeated points, where the curve
+            // stands still: go by where it is heading a step of arc either
+            // side instead, from the end of an open path once past it.
+            let len = self.length();
+            let s = if self.closed { s } else { s.clamp(0., len) };
+            let ds = len / (self.lengths.len() - 1) as f32;
+            let (a, b) = (self.param(s - ds), self.param(s + ds));
+            tangent = (self.eval(b) - self.eval(a)).normalize_or_zero();
+        }
+        (self.eval(g), tangent)
+    }
+
+    // How far the path has moved things by time `t`, and the turn that goes
+    // with it.
+    fn offset(&self, t: f32) -> (Vec3, Quat) {
+        let (p0, t0) = self.at(0.);
+        let (p1, t1) = self.at(self.speed * t);
+        let turn = if self.align {
+            Quat::from_rotation_arc(t0, t1)
+        } else {
+            Quat::IDENTITY
+        };
+        (p1 - p0, turn)
+    }
+}
+
+impl Movement for SplinePath {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        p + self.offset(t).0
+    }
+    fn direction_at(&self, t: f32, d: Vec3) -> Vec3 {
+        self.offset(t).1 * d
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        let (d, turn) = self.offset(t);
+        Transform {
+            rotation: turn * rest.rotation,
+            translation: rest.translation + d,
+            scale: rest.scale,
+        }
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        p - self.offset(t).0
+    }
+    fn direction_from(&self, t: f32, d: Vec3) -> Vec3 {
+        self.offset(t).1.inverse() * d
+    }
+    fn to_cos(&self) -> String {
+        let kind = match self.kind {
+            SplineKind::CatmullRom => "CR",
+            SplineKind::Bezier => "BEZ",
+        };
+        let points: Vec<String> = self.points.iter().map(|p| fmt_vec3(*p)).collect();
+        let mut s = format!(
+            "PATH {} {} {} {}",
+            kind,
+            self.points.len(),
+            points.join(" "),
+            fmt_num(self.speed)
+        );
+        if self.align {
+            s.push_str(" ALIGN");
+        }
+        if self.closed {
+            s.push_str(" LOOP");
+        }
+        s
+    }
+}
+
+// Several movements applied one after another, each to where the ones
+// before it have put things. A spin followed by an orbit turns a body about
+// its own center while it goes around the orbit.
+pub struct Composite {
+    pub members: Vec<Box<dyn Movement>>,
+}
+
+// `members` as one movement: None for none, the movement itself for one.
+pub fn stack(mut members: Vec<Box<dyn Movement>>) -> Option<Box<dyn Movement>> {
+    match members.len() {
+        0 => None,
+        1 => members.pop(),
+        _ => Some(Box::new(Composite { members })),
+    }
+}
+
+impl Movement for Composite {
+    fn point_at(&self, t: f32, p: Vec3) -> Vec3 {
+        self.members.iter().fold(p, |p, m| m.point_at(t, p))
+    }
+    fn direction_at(&self, t: f32, d: Vec3) -> Vec3 {
+        self.members.iter().fold(d, |d, m| m.direction_at(t, d))
+    }
+    fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        self.members
+            .iter()
+            .fold(*rest, |tr, m| m.transform_at(t, &tr))
+    }
+    fn point_from(&self, t: f32, p: Vec3) -> Vec3 {
+        self.members.iter().rev().fold(p, |p, m| m.point_from(t, p))
     }
-    fn update_point(&self, dt: f32, p: &mut Vec3) {
-        *p = *p - self.axis.p;
-        self.update_direction(dt, p);
-        *p = *p + self.axis.p;
+    fn direction_from(&self, t: f32, d: Vec3) -> Vec3 {
+        self.members
+            .iter()
+            .rev()
+            .fold(d, |d, m| m.direction_from(t, d))
     }
-    fn update_transform(&self, dt: f32, t: &mut Transform) {
-        t.rotate_around(self.axis.d, self.axis.p, self.rad * dt);
+    fn to_cos(&self) -> String {
+        let members: Vec<String> = self.members.iter().map(|m| m.to_cos()).collect();
+        members.join(" ")
     }
 }
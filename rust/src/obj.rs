@@ -0,0 +1,156 @@
+use std::io::BufRead;
+
+use glam::f32::Vec3;
+
+// Wavefront OBJ reader: `v` positions, optional `vn` normals, `f` polygons
+// (fan-triangulated, 1-based or negative indices) and `g`/`o` groups.
+// Texture coordinates, materials and everything else are skipped.
+
+pub struct ObjFace {
+    // Indices into ObjMesh::vertices.
+    pub v: [usize; 3],
+    // Indices into ObjMesh::normals, when every corner of the face had one.
+    pub vn: Option<[usize; 3]>,
+}
+
+pub struct ObjGroup {
+    pub name: String,
+    pub faces: Vec<ObjFace>,
+}
+
+pub struct ObjMesh {
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    // Faces before the first `g`/`o` line land in a group named "default".
+    // Groups without faces are dropped.
+    pub groups: Vec<ObjGroup>,
+}
+
+impl ObjMesh {
+    pub fn num_faces(&self) -> usize {
+        self.groups.iter().map(|g| g.faces.len()).sum()
+    }
+
+    // Triangles of the whole mesh, or of the named group only. The engine
+    // only sees the front of a triangle, taken from its winding, so a face
+    // whose vertex normals point the other way is flipped to agree with
+    // them.
+    pub fn triangles(&self, group: Option<&str>) -> Vec<[Vec3; 3]> {
+        let mut tris = vec![];
+        for g in &self.groups {
+            if group.is_some_and(|name| name != g.name) {
+                continue;
+            }
+            for face in &g.faces {
+                let [a, b, c] = face.v.map(|i| self.vertices[i]);
+                let flip = match face.vn {
+                    Some(vn) => {
+                        let shading: Vec3 = vn.iter().map(|&i| self.normals[i]).sum();
+                        (b - a).cross(c - a).dot(shading) < 0.
+                    }
+                    None => false,
+                };
+                tris.push(if flip { [a, c, b] } else { [a, b, c] });
+            }
+        }
+        tris
+    }
+}
+
+// Resolve a 1-based or negative (relative to the end) OBJ index into a
+// 0-based one.
+fn resolve_index(s: &str, len: usize) -> Result<usize, String> {
+    let i = s.parse::<i64>().map_err(|_| format!("bad index `{}`", s))?;
+    let resolved = if i > 0 { i - 1 } else { len as i64 + i };
+    if i == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(format!("index {} out of range", i));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_vec3(parts: &[&str]) -> Result<Vec3, String> {
+    if parts.len() < 3 {
+        return Err("expected 3 coordinates".to_string());
+    }
+    let mut v = [0.; 3];
+    for k in 0..3 {
+        v[k] = parts[k]
+            .parse::<f32>()
+            .map_err(|_| format!("bad number `{}`", parts[k]))?;
+    }
+    Ok(Vec3::from_array(v))
+}
+
+pub fn read_obj<R: BufRead>(reader: R) -> Result<ObjMesh, String> {
+    let mut mesh = ObjMesh {
+        vertices: vec![],
+        normals: vec![],
+        groups: vec![],
+    };
+    let mut current = ObjGroup {
+        name: "default".to_string(),
+        faces: vec![],
+    };
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let at = |e: String| format!("line {}: {}", n + 1, e);
+        match parts.first() {
+            Some(&"v") => mesh.vertices.push(parse_vec3(&parts[1..]).map_err(at)?),
+            Some(&"vn") => mesh.normals.push(parse_vec3(&parts[1..]).map_err(at)?),
+            Some(&"f") => {
+                // Each corner is `v`, `v/vt`, `v/vt/vn` or `v//vn`.
+                let mut corners: Vec<(usize, Option<usize>)> = vec![];
+                for corner in &parts[1..] {
+                    let mut fields = corner.split('/');
+                    let v = resolve_index(fields.next().unwrap_or(""), mesh.vertices.len())
+                        .map_err(at)?;
+                    let vn = match fields.nth(1) {
+                        Some(s) if !s.is_empty() => {
+                            Some(resolve_index(s, mesh.normals.len()).map_err(at)?)
+                        }
+                        _ => None,
+                    };
+                    corners.push((v, vn));
+                }
+                if corners.len() < 3 {
+                    return Err(at("face with fewer than 3 vertices".to_string()));
+                }
+                for i in 1..corners.len() - 1 {
+                    let tri = [corners[0], corners[i], corners[i + 1]];
+                    let vn = match tri.map(|c| c.1) {
+                        [Some(a), Some(b), Some(c)] => Some([a, b, c]),
+                        _ => None,
+                    };
+                    current.faces.push(ObjFace {
+                        v: tri.map(|c| c.0),
+                        vn,
+                    });
+                }
+            }
+            Some(&"g") | Some(&"o") => {
+                let name = if parts.len() > 1 {
+                    parts[1..].join(" ")
+                } else {
+                    "default".to_string()
+                };
+                let done = std::mem::replace(
+                    &mut current,
+                    ObjGroup {
+                        name,
+                        faces: vec![],
+                    },
+                );
+                if !done.faces.is_empty() {
+                    mesh.groups.push(done);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !current.faces.is_empty() {
+        mesh.groups.push(current);
+    }
+    Ok(mesh)
+}
@@ -4,10 +4,11 @@ use std::time::Instant;
 
 use rayon::prelude::*;
 
+use crate::bvh::Tlas;
 use crate::camera::Camera;
-use crate::engine::Thing;
 use crate::light::{get_color, get_lum, Light};
 use crate::raster;
+use crate::scene::Scene;
 use crate::sharpen;
 use crate::util::Color;
 
@@ -24,8 +25,15 @@ pub struct Player {
     lum_samples: Vec<Vec<[f32; 6]>>,
     t: f32,
     dt: f32,
-    objects: Vec<Box<dyn Thing>>,
-    camera: Box<dyn Camera>,
+    // The scene's objects, and the top-level BVH over them with --aabb.
+    world: Tlas,
+    cameras: Vec<Box<dyn Camera>>,
+    // The camera shot through, an index into `cameras`.
+    active: usize,
+    // The scene's cuts as (time, camera index, dissolve time), and whether
+    // the Player follows them or stays on a camera picked by `set_camera`.
+    cuts: Vec<(f32, usize, f32)>,
+    follow_cuts: bool,
     lights: Vec<Box<dyn Light>>,
     disable_shade: bool,
     debug: bool,
@@ -34,46 +42,50 @@ pub struct Player {
 }
 
 impl Player {
-    pub fn new(
-        fr: i32,
-        w: usize,
-        h: usize,
-        camera: Box<dyn Camera>,
-        disable_shade: bool,
-        debug: bool,
-        raster: bool,
-        sharpen: bool,
-    ) -> Self {
-        let a = vec![vec![' '; w]; h];
-        let lum_samples = if sharpen {
-            vec![vec![[0.0_f32; 6]; w]; h]
+    // Takes over everything in `scene`. The first camera is shot through
+    // until the cuts say otherwise; a scene with none cannot be played.
+    pub fn new(scene: Scene) -> Result<Self, String> {
+        let s = scene.settings;
+        if scene.cameras.is_empty() {
+            return Err("scene has no camera".to_string());
+        }
+        let cameras = scene.cameras;
+        // Cuts to cameras the scene does not have are dropped.
+        let cuts = scene
+            .cuts
+            .iter()
+            .filter_map(|cut| {
+                let i = cameras
+                    .iter()
+                    .position(|c| c.rig().name.as_deref() == Some(cut.camera.as_str()))?;
+                Some((cut.t, i, cut.dissolve))
+            })
+            .collect();
+        let a = vec![vec![' '; s.w]; s.h];
+        let lum_samples = if s.sharpen {
+            vec![vec![[0.0_f32; 6]; s.w]; s.h]
         } else {
             vec![]
         };
-        let dt = if debug { 1.0 } else { 1.0 / (fr as f32) };
-        Player {
-            w,
-            h,
+        let dt = if s.debug { 1.0 } else { 1.0 / (s.fr as f32) };
+        Ok(Player {
+            w: s.w,
+            h: s.h,
             a,
             lum_samples,
             t: 0.,
             dt,
-            camera,
-            objects: vec![],
-            lights: vec![],
-            disable_shade,
-            debug: debug,
-            raster,
-            sharpen,
-        }
-    }
-
-    pub fn add_object(&mut self, obj: Box<dyn Thing>) {
-        self.objects.push(obj);
-    }
-
-    pub fn add_light(&mut self, light: Box<dyn Light>) {
-        self.lights.push(light);
+            cameras,
+            active: 0,
+            cuts,
+            follow_cuts: true,
+            world: Tlas::new(scene.objects, s.enable_aabb),
+            lights: scene.lights,
+            disable_shade: s.disable_shade,
+            debug: s.debug,
+            raster: s.raster,
+            sharpen: s.sharpen,
+        })
     }
 
     pub fn render(&self) {
@@ -84,17 +96,111 @@ impl Player {
         }
     }
 
-    pub fn update(&mut self) {
-        // Update objects
-        for obj in &mut self.objects {
-            obj.update(self.t, self.dt, None);
-        }
+    // Time of the frame in `a`.
+    pub fn time(&self) -> f32 {
+        self.t
+    }
 
-        // Update lights
+    // Put everything where it is at time `t` and draw that frame. Movements
+    // and tracks are worked out from time 0 rather than from the last frame,
+    // so this costs the same for any `t`, forwards or back.
+    pub fn seek(&mut self, t: f32) {
+        self.t = t;
+        // An update of zero length poses things at `t` itself.
+        for obj in self.world.objects_mut() {
+            obj.update(t, 0., None);
+        }
+        self.world.refit();
         for light in &mut self.lights {
-            light.update(self.t, self.dt);
+            light.update(t, 0.);
+        }
+        for camera in &mut self.cameras {
+            camera.update(t, 0., self.world.objects());
+        }
+        self.draw();
+    }
+
+    // Names of the cameras that have one, for `set_camera`.
+    pub fn camera_names(&self) -> Vec<String> {
+        self.cameras
+            .iter()
+            .filter_map(|c| c.rig().name.clone())
+            .collect()
+    }
+
+    // Shoot through the camera named `name` from now on, ignoring the cuts
+    // until `follow_cuts`. False if there is no such camera.
+    pub fn set_camera(&mut self, name: &str) -> bool {
+        let found = self
+            .cameras
+            .iter()
+            .position(|c| c.rig().name.as_deref() == Some(name));
+        match found {
+            Some(i) => {
+                self.active = i;
+                self.follow_cuts = false;
+                self.draw();
+                true
+            }
+            None => false,
         }
+    }
 
+    // Go back to switching cameras at the scene's cuts.
+    pub fn follow_cuts(&mut self) {
+        self.follow_cuts = true;
+        self.draw();
+    }
+
+    // The camera to shoot through at `t`, and during a dissolve the one
+    // being faded out with how far the fade has got, from 0 to 1.
+    fn shot(&self, t: f32) -> (usize, Option<(usize, f32)>) {
+        if !self.follow_cuts {
+            return (self.active, None);
+        }
+        let mut camera = 0;
+        let mut fade = None;
+        for &(start, i, dissolve) in &self.cuts {
+            if start > t {
+                break;
+            }
+            let u = (t - start) / dissolve;
+            fade = if dissolve > 0. && u < 1. {
+                Some((camera, u))
+            } else {
+                None
+            };
+            camera = i;
+        }
+        (camera, fade)
+    }
+
+    // Move `frames` frames forwards, or backwards if negative.
+    pub fn step(&mut self, frames: i32) {
+        self.seek(self.t + frames as f32 * self.dt);
+    }
+
+    pub fn update(&mut self) {
+        self.step(1);
+    }
+
+    fn draw(&mut self) {
+        let (camera, fade) = self.shot(self.t);
+        if let Some((from, u)) = fade {
+            self.active = from;
+            self.draw_camera();
+            let before = self.a.clone();
+            self.active = camera;
+            self.draw_camera();
+            dissolve(&mut self.a, &before, u);
+        } else {
+            self.active = camera;
+            self.draw_camera();
+        }
+    }
+
+    // Draw the frame through the active camera.
+    fn draw_camera(&mut self) {
         if self.sharpen {
             if self.raster {
                 self.raster_render_sharpen();
@@ -110,6 +216,7 @@ impl Player {
     }
 
     fn rt_render_sharpen(&mut self) {
+        let camera = self.cameras[self.active].as_ref();
         self.lum_samples
             .par_iter_mut()
             .enumerate()
@@ -117,35 +224,14 @@ impl Player {
                 for j in 0..self.w {
                     for k in 0..6 {
                         let (dx, dy) = sharpen::SAMPLE_POSITIONS[k];
-                        let ray = self
-                            .camera
-                            .ray_at(i as f32 + dy, j as f32 + dx);
-                        let mut dist = f32::MAX;
-                        let mut hit_lum = 0.0_f32;
-                        let mut hit = false;
-                        for obj in &self.objects {
-                            if let Some((p, n, _c)) = obj.intersect(&ray) {
-                                let cur_dist = (p - ray.p).dot(ray.d);
-                                if cur_dist > dist {
-                                    continue;
-                                }
-                                dist = cur_dist;
-                                hit = true;
-                                hit_lum = if !self.lights.is_empty() {
-                                    get_lum(
-                                        &self.lights,
-                                        &self.objects,
-                                        p,
-                                        n,
-                                        ray.d,
-                                        self.disable_shade,
-                                    )
-                                } else {
-                                    1.0
-                                };
+                        let ray = camera.ray_at(i as f32 + dy, j as f32 + dx);
+                        row[j][k] = match self.world.intersect(&ray) {
+                            Some((p, n, _c)) if !self.lights.is_empty() => {
+                                get_lum(&self.lights, &self.world, p, n, ray.d, self.disable_shade)
                             }
-                        }
-                        row[j][k] = if hit { hit_lum } else { 0.0 };
+                            Some(_) => 1.0,
+                            None => 0.0,
+                        };
                     }
                 }
             });
@@ -153,9 +239,9 @@ impl Player {
 
     fn raster_render_sharpen(&mut self) {
         raster::raster_frame_sharpen(
-            &self.objects,
+            &self.world,
             &self.lights,
-            self.camera.as_ref(),
+            self.cameras[self.active].as_ref(),
             &mut self.lum_samples,
             self.w,
             self.h,
@@ -164,44 +250,29 @@ impl Player {
     }
 
     fn rt_render(&mut self) {
+        let camera = self.cameras[self.active].as_ref();
         self.a.par_iter_mut().enumerate().for_each(|(i, row)| {
             for j in 0..self.w {
-                row[j] = ' ';
-                let mut dist = f32::MAX;
-                let ray = self.camera.get_ray(i, j);
-                for obj in &self.objects {
-                    match obj.intersect(ray) {
-                        Some((p, n, c)) => {
-                            let cur_dist = (p - ray.p).dot(ray.d);
-                            if cur_dist > dist {
-                                continue;
-                            }
-                            dist = cur_dist;
-                            row[j] = if self.lights.len() > 0 {
-                                get_color(
-                                    &self.lights,
-                                    &self.objects,
-                                    p,
-                                    n,
-                                    ray.d,
-                                    self.disable_shade,
-                                )
-                            } else {
-                                c
-                            };
+                let ray = camera.ray_at(i as f32, j as f32);
+                row[j] = match self.world.intersect(&ray) {
+                    Some((p, n, c)) => {
+                        if self.lights.len() > 0 {
+                            get_color(&self.lights, &self.world, p, n, ray.d, self.disable_shade)
+                        } else {
+                            c
                         }
-                        None => {}
                     }
-                }
+                    None => ' ',
+                };
             }
         });
     }
 
     fn raster_render(&mut self) {
         raster::raster_frame(
-            &self.objects,
+            &self.world,
             &self.lights,
-            self.camera.as_ref(),
+            self.cameras[self.active].as_ref(),
             &mut self.a,
             self.w,
             self.h,
@@ -209,16 +280,18 @@ impl Player {
         );
     }
 
-    pub fn run(&mut self, duration: f32) {
+    // Play `duration` seconds from time `start`.
+    pub fn run(&mut self, start: f32, duration: f32) {
+        self.t = start;
         let mut total_wait: f32 = 0.;
         let mut total_compute: f32 = 0.;
         loop {
-            let start = Instant::now();
+            let frame_start = Instant::now();
             self.update();
             if !self.debug {
                 self.render();
             }
-            let compute_t = start.elapsed().as_secs_f32();
+            let compute_t = frame_start.elapsed().as_secs_f32();
             let wait_t: f32 = if self.dt >= compute_t {
                 self.dt - compute_t
             } else {
@@ -234,8 +307,7 @@ impl Player {
             }
             total_compute += compute_t;
             total_wait += wait_t;
-            self.t += self.dt;
-            if self.t > duration {
+            if self.t > start + duration {
                 break;
             }
             thread::sleep(Duration::from_secs_f32(wait_t));
@@ -251,3 +323,25 @@ impl Player {
         }
     }
 }
+
+// Mix `before` into the frame in `a` for a dissolve `u` of the way through:
+// each cell switches over to the new frame at its own point of the fade, so
+// the new shot shows through more and more cells.
+fn dissolve(a: &mut [Vec<Color>], before: &[Vec<Color>], u: f32) {
+    for (i, (row, old)) in a.iter_mut().zip(before).enumerate() {
+        for (j, (c, o)) in row.iter_mut().zip(old).enumerate() {
+            if cell_threshold(i, j) >= u {
+                *c = *o;
+            }
+        }
+    }
+}
+
+// A fixed pseudo-random value in [0, 1) for cell (i, j).
+fn cell_threshold(i: usize, j: usize) -> f32 {
+    let mut x = (i as u32).wrapping_mul(0x9E37_79B9) ^ (j as u32).wrapping_mul(0x85EB_CA6B);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    (x >> 8) as f32 / (1 << 24) as f32
+}
@@ -0,0 +1,323 @@
+use glam::f32::Vec3;
+
+// PLY reader for ASCII and binary (little and big endian) files. Keeps vertex
+// positions, an optional per-vertex brightness and the face index lists;
+// every other element and property is read past and dropped.
+//
+// Brightness comes from `red`/`green`/`blue` (as luma) or from `intensity` /
+// `scalar_intensity`, normalized to [0, 1] by the range of the stored type.
+
+pub struct PlyMesh {
+    pub vertices: Vec<Vec3>,
+    pub brightness: Option<Vec<f32>>,
+    // Polygons as vertex indices, in file order. Empty for point clouds.
+    pub faces: Vec<Vec<usize>>,
+}
+
+impl PlyMesh {
+    // Fan-triangulated faces, each with the mean brightness of its corners
+    // when the file has one.
+    pub fn triangles(&self) -> Vec<([Vec3; 3], Option<f32>)> {
+        let mut tris = vec![];
+        for face in &self.faces {
+            for i in 1..face.len().saturating_sub(1) {
+                let idx = [face[0], face[i], face[i + 1]];
+                let lum = self
+                    .brightness
+                    .as_ref()
+                    .map(|b| idx.iter().map(|&k| b[k]).sum::<f32>() / 3.);
+                tris.push((idx.map(|k| self.vertices[k]), lum));
+            }
+        }
+        tris
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLe,
+    BinaryBe,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(s: &str) -> Result<Scalar, String> {
+        Ok(match s {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("unknown property type `{}`", s)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // Full scale of the type, so integer colors map onto [0, 1].
+    fn full_scale(self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    // (name, count type, item type)
+    List(String, Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Sequential reader over the body of the file, in whichever format the
+// header declared.
+struct Body<'a> {
+    format: Format,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Body<'_> {
+    fn ascii_token(&mut self) -> Result<&str, String> {
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err("unexpected end of file".to_string());
+        }
+        std::str::from_utf8(&self.data[start..self.pos]).map_err(|e| e.to_string())
+    }
+
+    fn read(&mut self, t: Scalar) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let tok = self.ascii_token()?;
+            return tok
+                .parse::<f64>()
+                .map_err(|_| format!("bad number `{}`", tok));
+        }
+        let n = t.size();
+        if self.pos + n > self.data.len() {
+            return Err("unexpected end of file".to_string());
+        }
+        let mut b = [0u8; 8];
+        b[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        if self.format == Format::BinaryBe {
+            b[..n].reverse();
+        }
+        Ok(match t {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b),
+        })
+    }
+
+    // The length of a list, which has to be a whole number and fit in what
+    // is left of the file: at least a byte per item in ASCII, `item` bytes
+    // in binary.
+    fn count(&mut self, t: Scalar, item: Scalar) -> Result<usize, String> {
+        let v = self.read(t)?;
+        let n = whole(v).ok_or_else(|| format!("bad list count `{}`", v))?;
+        let per_item = match self.format {
+            Format::Ascii => 1,
+            _ => item.size(),
+        };
+        if n.saturating_mul(per_item) > self.data.len() - self.pos {
+            return Err(format!("list of {} items runs past the end of the file", n));
+        }
+        Ok(n)
+    }
+}
+
+// `v` as an index or count, if it is a whole number that fits in one.
+fn whole(v: f64) -> Option<usize> {
+    if v >= 0. && v.fract() == 0. && v <= usize::MAX as f64 {
+        Some(v as usize)
+    } else {
+        None
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+    const END: &[u8] = b"end_header";
+    let end = data
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or("missing `end_header`")?;
+    // The body starts after the newline that ends the `end_header` line.
+    let body = match data[end..].iter().position(|&c| c == b'\n') {
+        Some(nl) => end + nl + 1,
+        None => data.len(),
+    };
+    let header = String::from_utf8_lossy(&data[..end]);
+    let mut lines = header.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in lines {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["format", f, ..] => {
+                format = Some(match *f {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLe,
+                    "binary_big_endian" => Format::BinaryBe,
+                    _ => return Err(format!("unknown format `{}`", f)),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("bad element count `{}`", count))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_t, item_t, name] => {
+                let el = elements.last_mut().ok_or("property before any element")?;
+                el.properties.push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(count_t)?,
+                    Scalar::parse(item_t)?,
+                ));
+            }
+            ["property", t, name] => {
+                let el = elements.last_mut().ok_or("property before any element")?;
+                el.properties
+                    .push(Property::Scalar(name.to_string(), Scalar::parse(t)?));
+            }
+            _ => {}
+        }
+    }
+    let format = format.ok_or("missing `format` line")?;
+    Ok((format, elements, body))
+}
+
+pub fn read_ply(data: &[u8]) -> Result<PlyMesh, String> {
+    let (format, elements, body) = parse_header(data)?;
+    let mut body = Body {
+        format,
+        data: &data[body..],
+        pos: 0,
+    };
+    let mut mesh = PlyMesh {
+        vertices: vec![],
+        brightness: None,
+        faces: vec![],
+    };
+
+    for el in &elements {
+        let is_vertex = el.name == "vertex";
+        let is_face = el.name == "face";
+        let has = |n: &str| {
+            el.properties
+                .iter()
+                .any(|p| matches!(p, Property::Scalar(name, _) if name == n))
+        };
+        let rgb = is_vertex && has("red") && has("green") && has("blue");
+        let intensity = is_vertex && (has("intensity") || has("scalar_intensity"));
+        if rgb || intensity {
+            mesh.brightness = Some(vec![]);
+        }
+
+        for _ in 0..el.count {
+            let mut pos = [0f32; 3];
+            let mut color = [0f32; 3];
+            let mut lum = 0f32;
+            for p in &el.properties {
+                match p {
+                    Property::Scalar(name, t) => {
+                        let v = body.read(*t)?;
+                        let unit = (v / t.full_scale()) as f32;
+                        match name.as_str() {
+                            "x" => pos[0] = v as f32,
+                            "y" => pos[1] = v as f32,
+                            "z" => pos[2] = v as f32,
+                            "red" => color[0] = unit,
+                            "green" => color[1] = unit,
+                            "blue" => color[2] = unit,
+                            "intensity" | "scalar_intensity" => lum = unit,
+                            _ => {}
+                        }
+                    }
+                    Property::List(name, count_t, item_t) => {
+                        let n = body.count(*count_t, *item_t)?;
+                        let indices =
+                            is_face && (name == "vertex_indices" || name == "vertex_index");
+                        let mut items = vec![];
+                        for _ in 0..n {
+                            let v = body.read(*item_t)?;
+                            if indices {
+                                items.push(
+                                    whole(v).ok_or_else(|| format!("bad vertex index `{}`", v))?,
+                                );
+                            }
+                        }
+                        if indices {
+                            mesh.faces.push(items);
+                        }
+                    }
+                }
+            }
+            if is_vertex {
+                mesh.vertices.push(Vec3::from_array(pos));
+                if let Some(b) = mesh.brightness.as_mut() {
+                    if rgb {
+                        lum = 0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2];
+                    }
+                    b.push(lum.clamp(0., 1.));
+                }
+            }
+        }
+    }
+
+    let n = mesh.vertices.len();
+    if let Some(bad) = mesh.faces.iter().flatten().find(|&&i| i >= n) {
+        return Err(format!("face refers to vertex {} of {}", bad, n));
+    }
+    Ok(mesh)
+}
@@ -1,7 +1,8 @@
 use glam::{Vec2, Vec3};
 
+use crate::bvh::Tlas;
 use crate::camera::Camera;
-use crate::engine::Thing;
+use crate::engine::Object;
 use crate::light::{get_color, get_lum, Light};
 use crate::sharpen;
 use crate::util::Color;
@@ -18,7 +19,7 @@ fn edge_fn(a: Vec2, b: Vec2, c: Vec2) -> f32 {
 // that exact sample position. Triangle setup (vertices, projection, area)
 // is shared across the 6 samples in the inner loop.
 pub fn raster_frame_sharpen(
-    objects: &Vec<Box<dyn Thing>>,
+    world: &Tlas,
     lights: &Vec<Box<dyn Light>>,
     camera: &dyn Camera,
     lum_samples: &mut Vec<Vec<[f32; 6]>>,
@@ -36,11 +37,8 @@ pub fn raster_frame_sharpen(
     let eye = camera.eye();
     let has_lights = !lights.is_empty();
 
-    for obj in objects {
-        let o = match obj.as_object() {
-            Some(o) => o,
-            None => continue,
-        };
+    let placed = world.objects().iter().filter_map(|obj| obj.as_object());
+    for o in placed.flat_map(Object::subtree) {
         let t = o.transform();
         for tri in o.raster_tris() {
             let (a_o, b_o, c_o, _color, n_o) = *tri;
@@ -48,7 +46,7 @@ pub fn raster_frame_sharpen(
             let a_w = t.object_to_world_point(a_o);
             let b_w = t.object_to_world_point(b_o);
             let c_w = t.object_to_world_point(c_o);
-            let n_w = t.object_to_world_dir(n_o);
+            let n_w = t.object_to_world_normal(n_o);
 
             let centroid_w = (a_w + b_w + c_w) / 3.0;
             if n_w.dot(eye - centroid_w) <= 0.0 {
@@ -106,7 +104,7 @@ pub fn raster_frame_sharpen(
                         depth[iu][ju][k] = z;
                         lum_samples[iu][ju][k] = if has_lights {
                             let p_world = w0 * a_w + w1 * b_w + w2 * c_w;
-                            get_lum(lights, objects, p_world, n_w, Vec3::ZERO, disable_shade)
+                            get_lum(lights, world, p_world, n_w, Vec3::ZERO, disable_shade)
                         } else {
                             1.0
                         };
@@ -118,7 +116,7 @@ pub fn raster_frame_sharpen(
 }
 
 pub fn raster_frame(
-    objects: &Vec<Box<dyn Thing>>,
+    world: &Tlas,
     lights: &Vec<Box<dyn Light>>,
     camera: &dyn Camera,
     framebuffer: &mut Vec<Vec<Color>>,
@@ -138,11 +136,8 @@ pub fn raster_frame(
     let eye = camera.eye();
     let has_lights = !lights.is_empty();
 
-    for obj in objects {
-        let o = match obj.as_object() {
-            Some(o) => o,
-            None => continue,
-        };
+    let placed = world.objects().iter().filter_map(|obj| obj.as_object());
+    for o in placed.flat_map(Object::subtree) {
         let t = o.transform();
         for tri in o.raster_tris() {
             let (a_o, b_o, c_o, color, n_o) = *tri;
@@ -151,7 +146,7 @@ pub fn raster_frame(
             let a_w = t.object_to_world_point(a_o);
             let b_w = t.object_to_world_point(b_o);
             let c_w = t.object_to_world_point(c_o);
-            let n_w = t.object_to_world_dir(n_o);
+            let n_w = t.object_to_world_normal(n_o);
 
             // Backface cull in world space: skip if the face normal does not
             // point toward the eye. For ortho this still works because `eye`
@@ -220,9 +215,9 @@ pub fn raster_frame(
                     framebuffer[iu][ju] = if has_lights {
                         let p_world = w0 * a_w + w1 * b_w + w2 * c_w;
                         // When disable_shade is false, get_color shoots a
-                        // shadow ray per light against the same BVH-backed
-                        // Object::intersect that RT uses.
-                        get_color(lights, objects, p_world, n_w, Vec3::ZERO, disable_shade)
+                        // shadow ray per light through the same Tlas that RT
+                        // uses.
+                        get_color(lights, world, p_world, n_w, Vec3::ZERO, disable_shade)
                     } else {
                         color
                     };
@@ -0,0 +1,610 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use glam::f32::Vec3;
+use rayon::prelude::*;
+
+use crate::aabb::AABB;
+use crate::bvh::Bvh;
+use crate::camera::{fov_scale, ortho_scale, Camera, OrthoCamera, PerspectiveCamera, CELL_ASPECT};
+use crate::engine::{Mesh, Object, Point, Sphere, Thing, Torus, Triangle};
+use crate::gltf::{GltfCamera, GltfLight, GltfMesh, GltfNode, GltfScene};
+use crate::light::{lum_to_char, DirectionalLight, Light, PointLight};
+use crate::movement::{stack, Movement};
+use crate::obj::ObjMesh;
+use crate::ply::PlyMesh;
+use crate::track::Track;
+use crate::util::{to_deg, Color, Stopwatch, Transform};
+
+// How a scene is meant to be played back. Geometry that depends on the
+// output size (camera screens) and on --aabb (object BVHs) is built from
+// these while loading, the rest is read by the Player.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub w: usize,
+    pub h: usize,
+    pub fr: i32,
+    pub enable_aabb: bool,
+    pub disable_shade: bool,
+    pub debug: bool,
+    pub raster: bool,
+    pub sharpen: bool,
+    // Read mesh BVHs back from `.cosmo-bvh` caches, and write the ones built
+    // to disk.
+    pub bvh_cache: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            w: 80,
+            h: 40,
+            fr: 24,
+            enable_aabb: false,
+            disable_shade: false,
+            debug: false,
+            raster: false,
+            sharpen: false,
+            bvh_cache: false,
+        }
+    }
+}
+
+// A switch to the camera named `camera` at time `t`, fading over from the
+// one before it for `dissolve` seconds (0 for a hard cut).
+#[derive(Clone, Debug)]
+pub struct Cut {
+    pub t: f32,
+    pub camera: String,
+    pub dissolve: f32,
+}
+
+// How big a mesh read from a file is: its vertices, for the formats that
+// list them apart from the faces (OBJ, PLY) and the distinct corners of STL
+// faces, and its faces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshSize {
+    pub vertices: Option<usize>,
+    pub faces: usize,
+}
+
+pub struct Scene {
+    pub objects: Vec<Box<dyn Thing>>,
+    pub lights: Vec<Box<dyn Light>>,
+    // The Player shoots through the first camera until the first of `cuts`.
+    pub cameras: Vec<Box<dyn Camera>>,
+    // In increasing order of time.
+    pub cuts: Vec<Cut>,
+    // Time loading took, summed by phase in the order each first came up.
+    pub load_times: Vec<(&'static str, Duration)>,
+    // Every mesh read from a file, in the order they were read.
+    pub mesh_sizes: Vec<MeshSize>,
+    pub settings: RenderSettings,
+    // Free-form key/value pairs, e.g. "source" for the file a scene was
+    // loaded from.
+    pub metadata: HashMap<String, String>,
+}
+
+impl Scene {
+    // World box around every object where it is now.
+    pub fn bounds(&self) -> AABB {
+        let mut bounds = AABB::new();
+        for obj in &self.objects {
+            obj.update_aabb(&mut bounds);
+        }
+        bounds
+    }
+}
+
+// Radius of PLY points when the file gives nothing to size them by: one
+// vertex, or all of them in the same spot.
+pub const POINT_SIZE: f32 = 0.1;
+
+// Builds a Scene from Rust code the same way the `.cos` loader does.
+// Primitives (`triangle`, `sphere`, `torus`), `movement`, `track` and
+// `transform` accumulate into the object being built, like the lines of an
+// `OBJ` block;
+// `end_object` closes it. `begin_child` starts an object inside the one
+// being built, like an `OBJ` block nested in another. `stl`, `obj_file` and
+// `ply` add a whole mesh as one object of its own, like the lines of those
+// names; the movements, transform, track and name given for the object
+// being built stay with it.
+//
+// Meshes registered with `define` can be placed any number of times with
+// `instance`; every instance shares the one copy of the triangles and BVH.
+//
+//     let mut b = SceneBuilder::new(settings);
+//     b.triangle(a, c, d, '-').triangle(c, f, d, '-').movement(spin).end_object();
+//     b.light(light).camera(camera);
+//     let scene = b.build();
+pub struct SceneBuilder {
+    scene: Scene,
+    children: Vec<Box<dyn Thing>>,
+    // Movements given for the object being built, applied in this order.
+    m: Vec<Box<dyn Movement>>,
+    transform: Transform,
+    // Keyframe track of the object being built.
+    track: Option<Track>,
+    // Name of the object being built.
+    name: Option<String>,
+    // Objects already closed inside the one being built.
+    nested: Vec<Object>,
+    // The objects the one being built is nested in, innermost last.
+    parents: Vec<Pending>,
+    meshes: HashMap<String, Arc<Mesh>>,
+}
+
+// An object still being built, set aside while a child of it is.
+struct Pending {
+    children: Vec<Box<dyn Thing>>,
+    m: Vec<Box<dyn Movement>>,
+    transform: Transform,
+    track: Option<Track>,
+    name: Option<String>,
+    nested: Vec<Object>,
+}
+
+impl SceneBuilder {
+    pub fn new(settings: RenderSettings) -> Self {
+        SceneBuilder {
+            scene: Scene {
+                objects: vec![],
+                lights: vec![],
+                cameras: vec![],
+                cuts: vec![],
+                load_times: vec![],
+                mesh_sizes: vec![],
+                settings,
+                metadata: HashMap::new(),
+            },
+            children: vec![],
+            m: vec![],
+            transform: Transform::identity(),
+            track: None,
+            name: None,
+            nested: vec![],
+            parents: vec![],
+            meshes: HashMap::new(),
+        }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.scene.settings
+    }
+
+    pub fn has_camera(&self) -> bool {
+        !self.scene.cameras.is_empty()
+    }
+
+    pub fn meta(&mut self, key: &str, value: &str) -> &mut Self {
+        self.scene
+            .metadata
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn triangle(&mut self, a: Vec3, b: Vec3, c: Vec3, color: Color) -> &mut Self {
+        self.children.push(Box::new(Triangle::new(a, b, c, color)));
+        self
+    }
+
+    pub fn sphere(&mut self, o: Vec3, r: f32, color: Color) -> &mut Self {
+        self.children.push(Box::new(Sphere { o, r, color }));
+        self
+    }
+
+    // `d` is the axis the torus is wrapped around, `R` and `r` the major and
+    // minor radius.
+    #[allow(non_snake_case)]
+    pub fn torus(&mut self, d: Vec3, p: Vec3, R: f32, r: f32, color: Color) -> &mut Self {
+        let debug = self.scene.settings.debug;
+        self.children
+            .push(Box::new(Torus::new(d, p, R, r, color, debug)));
+        self
+    }
+
+    // Movement of the object being built. Each call adds to the ones given
+    // before, see `movement::Composite`.
+    pub fn movement(&mut self, m: Box<dyn Movement>) -> &mut Self {
+        self.m.push(m);
+        self
+    }
+
+    // Keyframe track of the object being built, see `track::Track`.
+    pub fn track(&mut self) -> &mut Track {
+        self.track.get_or_insert_with(Track::default)
+    }
+
+    // Name of the object being built, for cameras to look at.
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    // The objects, lights and cameras added so far, most recent last. Lets
+    // the loader attach what follows a line to the thing the line added.
+    pub fn last_object_mut(&mut self) -> Option<&mut Object> {
+        self.scene
+            .objects
+            .last_mut()
+            .and_then(|o| o.as_object_mut())
+    }
+
+    pub fn last_light_mut(&mut self) -> Option<&mut Box<dyn Light>> {
+        self.scene.lights.last_mut()
+    }
+
+    pub fn last_camera_mut(&mut self) -> Option<&mut Box<dyn Camera>> {
+        self.scene.cameras.last_mut()
+    }
+
+    // Initial placement of the object being built, before any movement.
+    pub fn transform(&mut self, t: Transform) -> &mut Self {
+        self.transform = t;
+        self
+    }
+
+    // Start an object nested in the one being built. Its transform is
+    // relative to that object, and its `end_object` adds it there instead of
+    // to the scene.
+    pub fn begin_child(&mut self) -> &mut Self {
+        let parent = Pending {
+            children: std::mem::take(&mut self.children),
+            m: std::mem::take(&mut self.m),
+            transform: std::mem::replace(&mut self.transform, Transform::identity()),
+            track: self.track.take(),
+            name: self.name.take(),
+            nested: std::mem::take(&mut self.nested),
+        };
+        self.parents.push(parent);
+        self
+    }
+
+    pub fn end_object(&mut self) -> &mut Self {
+        let children = std::mem::take(&mut self.children);
+        let mesh = Mesh::new(children, self.scene.settings.enable_aabb);
+        let mut obj = self.object(Arc::new(mesh));
+        for child in std::mem::take(&mut self.nested) {
+            obj.add_child(child);
+        }
+        match self.parents.pop() {
+            Some(parent) => {
+                self.children = parent.children;
+                self.m = parent.m;
+                self.transform = parent.transform;
+                self.track = parent.track;
+                self.name = parent.name;
+                self.nested = parent.nested;
+                self.nested.push(obj);
+            }
+            None => self.scene.objects.push(Box::new(obj)),
+        }
+        self
+    }
+
+    // Add the time since `start` to loading phase `phase`.
+    pub fn phase(&mut self, phase: &'static str, start: Stopwatch) {
+        let elapsed = start.elapsed();
+        match self.scene.load_times.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.scene.load_times.push((phase, elapsed)),
+        }
+    }
+
+    // A mesh of triangles made since `start`, timing both steps. With
+    // `cached`, its BVH is that rather than one built here.
+    fn triangle_mesh(
+        &mut self,
+        start: Stopwatch,
+        tris: Vec<Triangle>,
+        cached: Option<Bvh>,
+    ) -> Mesh {
+        self.phase("triangles", start);
+        let start = Stopwatch::start();
+        let mesh = match cached {
+            Some(bvh) => Mesh::with_bvh(tris, bvh),
+            None => Mesh::from_triangles(tris, self.scene.settings.enable_aabb),
+        };
+        self.phase("bvh", start);
+        mesh
+    }
+
+    // Object-space mesh of an STL file, ready to `define` or place, using
+    // the BVH `cached` for it if there is one. STL files repeat the corners
+    // faces share, so its vertices are counted by telling those apart.
+    pub fn stl_mesh(&mut self, stl: Vec<[Vec3; 3]>, cached: Option<Bvh>) -> Mesh {
+        let corners = stl.iter().flatten().map(|p| p.to_array().map(f32::to_bits));
+        self.scene.mesh_sizes.push(MeshSize {
+            vertices: Some(corners.collect::<HashSet<_>>().len()),
+            faces: stl.len(),
+        });
+
+        let start = Stopwatch::start();
+        let tris = stl
+            .into_par_iter()
+            .map(|[a, b, c]| Triangle::new(a, b, c, '.'))
+            .collect();
+        self.triangle_mesh(start, tris, cached)
+    }
+
+    // The faces of STL file `name`, read with `stl::read_stl`, placed as an
+    // `STL name` line would, moving by `m`.
+    pub fn stl(
+        &mut self,
+        name: &str,
+        stl: Vec<[Vec3; 3]>,
+        m: Option<Box<dyn Movement>>,
+    ) -> &mut Self {
+        let mesh = self
+            .stl_mesh(stl, None)
+            .with_source(format!("STL {}", name));
+        self.instance(Arc::new(mesh), Transform::identity(), m)
+    }
+
+    // Object-space mesh of a Wavefront OBJ file, or of one of its groups.
+    pub fn obj_file_mesh(&mut self, obj: &ObjMesh, group: Option<&str>) -> Mesh {
+        self.scene.mesh_sizes.push(MeshSize {
+            vertices: Some(obj.vertices.len()),
+            faces: obj.num_faces(),
+        });
+
+        let start = Stopwatch::start();
+        let tris = obj
+            .triangles(group)
+            .into_par_iter()
+            .map(|[a, b, c]| Triangle::new(a, b, c, '.'))
+            .collect();
+        self.triangle_mesh(start, tris, None)
+    }
+
+    pub fn obj_file(
+        &mut self,
+        obj: &ObjMesh,
+        group: Option<&str>,
+        m: Option<Box<dyn Movement>>,
+    ) -> &mut Self {
+        let mesh = Arc::new(self.obj_file_mesh(obj, group));
+        self.instance(mesh, Transform::identity(), m)
+    }
+
+    // Object-space mesh of a PLY file. Faces become triangles; a file with
+    // vertices only becomes a point cloud of `point_size` radius spheres,
+    // sized from the vertex spacing when not given, or POINT_SIZE when the
+    // vertices have no spacing. Per-vertex brightness,
+    // if the file has one, picks each primitive's color from the ramp.
+    pub fn ply_mesh(&mut self, ply: &PlyMesh, point_size: Option<f32>) -> Mesh {
+        self.scene.mesh_sizes.push(MeshSize {
+            vertices: Some(ply.vertices.len()),
+            faces: ply.faces.len(),
+        });
+
+        let color = |lum: Option<f32>| lum.map_or('.', lum_to_char);
+        if !ply.faces.is_empty() {
+            let start = Stopwatch::start();
+            let tris = ply
+                .triangles()
+                .into_par_iter()
+                .map(|([a, b, c], lum)| Triangle::new(a, b, c, color(lum)))
+                .collect();
+            return self.triangle_mesh(start, tris, None);
+        }
+        let r = point_size.unwrap_or_else(|| {
+            let (lo, hi) = ply.vertices.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(lo, hi), v| (lo.min(*v), hi.max(*v)),
+            );
+            // Roughly half the spacing of points spread over a surface.
+            let r = 0.5 * (hi - lo).length() / (ply.vertices.len() as f32).sqrt();
+            if r > 0. {
+                r
+            } else {
+                POINT_SIZE
+            }
+        });
+        let mut children: Vec<Box<dyn Thing>> = vec![];
+        for (i, &p) in ply.vertices.iter().enumerate() {
+            let lum = ply.brightness.as_ref().map(|b| b[i]);
+            children.push(Box::new(Point {
+                p,
+                r,
+                color: color(lum),
+            }));
+        }
+        let start = Stopwatch::start();
+        let mesh = Mesh::new(children, self.scene.settings.enable_aabb);
+        self.phase("bvh", start);
+        mesh
+    }
+
+    pub fn ply(
+        &mut self,
+        ply: &PlyMesh,
+        point_size: Option<f32>,
+        m: Option<Box<dyn Movement>>,
+    ) -> &mut Self {
+        let mesh = Arc::new(self.ply_mesh(ply, point_size));
+        self.instance(mesh, Transform::identity(), m)
+    }
+
+    // Object-space mesh of one glTF mesh.
+    pub fn gltf_mesh(&mut self, mesh: &GltfMesh) -> Mesh {
+        self.scene.mesh_sizes.push(MeshSize {
+            vertices: None,
+            faces: mesh.triangles.len(),
+        });
+
+        let start = Stopwatch::start();
+        let tris = mesh
+            .triangles
+            .par_iter()
+            .map(|&[a, b, c]| Triangle::new(a, b, c, '.'))
+            .collect();
+        self.triangle_mesh(start, tris, None)
+    }
+
+    // The mesh nodes of a glTF file as one object at `place`, moved by `m`,
+    // with the root nodes nested in it and the nodes below those in them.
+    // Nodes placing the same mesh share it, like instances.
+    pub fn gltf(
+        &mut self,
+        gltf: &GltfScene,
+        place: Transform,
+        m: Option<Box<dyn Movement>>,
+    ) -> &mut Self {
+        let meshes: Vec<_> = gltf
+            .meshes
+            .iter()
+            .map(|mesh| Arc::new(self.gltf_mesh(mesh)))
+            .collect();
+        let empty = Arc::new(Mesh::new(vec![], self.scene.settings.enable_aabb));
+        let mut model = Object::instance(empty.clone(), m);
+        model.set_transform(place);
+        for node in &gltf.nodes {
+            model.add_child(gltf_object(node, &meshes, &empty));
+        }
+        self.scene.objects.push(Box::new(model));
+        self
+    }
+
+    // A glTF camera, moved by `place`. The field of view (or the
+    // orthographic half-height) spans the output height.
+    pub fn gltf_camera(&mut self, camera: &GltfCamera, place: &Transform) -> &mut Self {
+        let (w, h) = (self.scene.settings.w, self.scene.settings.h);
+        let camera: Box<dyn Camera> = match *camera {
+            GltfCamera::Perspective { p, d, yfov } => Box::new(PerspectiveCamera::new(
+                place.object_to_world_dir(d),
+                place.object_to_world_point(p),
+                fov_scale(to_deg(yfov), CELL_ASPECT, h),
+                1.,
+                w,
+                h,
+            )),
+            GltfCamera::Ortho { p, d, ymag } => Box::new(OrthoCamera::new(
+                place.object_to_world_dir(d),
+                place.object_to_world_point(p),
+                ortho_scale(ymag, CELL_ASPECT, h),
+                w,
+                h,
+            )),
+        };
+        self.camera(camera)
+    }
+
+    // A glTF light, moved by `place`. Lights here give a surface facing
+    // them its illuminance in lux, full brightness being 1. A sun's lux is
+    // its strength, clamped to 1 as more looks no different; a lamp's
+    // candela are already what an `L P` strength means, so taken as is.
+    pub fn gltf_light(&mut self, light: &GltfLight, place: &Transform) -> &mut Self {
+        let light: Box<dyn Light> = match *light {
+            GltfLight::Directional { d, intensity } => Box::new(DirectionalLight::new(
+                place.object_to_world_dir(d),
+                intensity.min(1.),
+                None,
+            )),
+            GltfLight::Point { p, intensity } => Box::new(PointLight::new(
+                place.object_to_world_point(p),
+                intensity,
+                None,
+            )),
+        };
+        self.light(light)
+    }
+
+    // Register `mesh` under `name`, replacing any earlier definition.
+    pub fn define(&mut self, name: &str, mesh: Mesh) -> &mut Self {
+        self.meshes
+            .insert(name.to_string(), Arc::new(mesh.with_name(name)));
+        self
+    }
+
+    pub fn mesh(&self, name: &str) -> Option<Arc<Mesh>> {
+        self.meshes.get(name).cloned()
+    }
+
+    // Place a shared mesh at `place` as an object of its own, moving by `m`.
+    pub fn instance(
+        &mut self,
+        mesh: Arc<Mesh>,
+        place: Transform,
+        m: Option<Box<dyn Movement>>,
+    ) -> &mut Self {
+        let mut obj = Object::instance(mesh, m);
+        obj.set_transform(place);
+        self.scene.objects.push(Box::new(obj));
+        self
+    }
+
+    // `mesh` as an object with the pending movements, transform and track.
+    fn object(&mut self, mesh: Arc<Mesh>) -> Object {
+        let mut obj = Object::instance(mesh, stack(std::mem::take(&mut self.m)));
+        obj.set_transform(self.transform);
+        if let Some(track) = self.track.take() {
+            *obj.track_mut() = track;
+        }
+        if let Some(name) = self.name.take() {
+            obj.set_name(&name);
+        }
+        self.transform = Transform::identity();
+        obj
+    }
+
+    pub fn light(&mut self, light: Box<dyn Light>) -> &mut Self {
+        self.scene.lights.push(light);
+        self
+    }
+
+    pub fn camera(&mut self, camera: Box<dyn Camera>) -> &mut Self {
+        self.scene.cameras.push(camera);
+        self
+    }
+
+    // Switch to the camera named `camera` at `t`, see `Cut`. Cuts are added
+    // in order of time.
+    pub fn cut(&mut self, t: f32, camera: &str, dissolve: f32) -> &mut Self {
+        self.scene.cuts.push(Cut {
+            t,
+            camera: camera.to_string(),
+            dissolve,
+        });
+        self
+    }
+
+    pub fn last_cut(&self) -> Option<&Cut> {
+        self.scene.cuts.last()
+    }
+
+    // Objects left open, and primitives added after the last `end_object`,
+    // are closed rather than dropped. Cameras to fit to the scene are framed
+    // on what it holds at rest.
+    pub fn build(mut self) -> Scene {
+        while !self.parents.is_empty() {
+            self.end_object();
+        }
+        if !self.children.is_empty() || !self.nested.is_empty() {
+            self.end_object();
+        }
+        let bounds = self.scene.bounds();
+        for camera in &mut self.scene.cameras {
+            if let Some(margin) = camera.rig().fit {
+                camera.frame(&bounds, margin);
+            }
+        }
+        self.scene
+    }
+}
+
+// `node` and the nodes below it as nested objects, `meshes` holding those of
+// the file and `empty` standing in for a node without one.
+fn gltf_object(node: &GltfNode, meshes: &[Arc<Mesh>], empty: &Arc<Mesh>) -> Object {
+    let mesh = node.mesh.map_or(empty, |i| &meshes[i]);
+    let mut obj = Object::instance(mesh.clone(), None);
+    obj.set_transform(node.transform);
+    if let Some(name) = &node.name {
+        obj.set_name(name);
+    }
+    for child in &node.children {
+        obj.add_child(gltf_object(child, meshes, empty));
+    }
+    obj
+}
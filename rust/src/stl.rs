@@ -0,0 +1,30 @@
+use std::io::{Read, Seek};
+
+use glam::f32::Vec3;
+
+// STL reader, binary or ASCII. Faces are streamed straight into a list of
+// corners rather than an indexed mesh: the engine keeps every triangle's
+// corners anyway, and merging the vertices they share only costs time.
+
+// Faces between calls to the progress callback.
+const PROGRESS_STEP: usize = 1 << 20;
+
+// The triangles of an STL file. `progress` is called every PROGRESS_STEP
+// faces with how many have been read and, for a binary file, how many there
+// are, so huge files can report how far along they are.
+pub fn read_stl<R: Read + Seek>(
+    reader: &mut R,
+    mut progress: impl FnMut(usize, Option<usize>),
+) -> Result<Vec<[Vec3; 3]>, String> {
+    let faces = stl_io::create_stl_reader(reader).map_err(|e| e.to_string())?;
+    let total = faces.size_hint().1;
+    let mut tris = Vec::with_capacity(total.unwrap_or(0));
+    for (i, face) in faces.enumerate() {
+        let face = face.map_err(|e| e.to_string())?;
+        tris.push(face.vertices.map(|v| Vec3::from_array(v.into())));
+        if (i + 1) % PROGRESS_STEP == 0 {
+            progress(i + 1, total);
+        }
+    }
+    Ok(tris)
+}
@@ -0,0 +1,171 @@
+use glam::f32::{Quat, Vec3};
+
+use crate::util::{axis_angle, fmt_deg, fmt_num, fmt_vec3, Transform};
+
+// Keyframe animation: rather than following a formula like a Movement, the
+// thing passes through poses given at set times. Each key is an offset from the animated thing's rest
+// pose: `pos` is added to its position, `rot` turns it around its own origin
+// and `scale` multiplies its size, so a key of (0, identity, 1) leaves it
+// where it was placed.
+//
+// Between two keys the offset is interpolated (lerp for position and scale,
+// slerp for rotation) with the easing of the first of the two. Before the
+// first key and after the last one the track holds still, or with `looped`
+// plays over again from the first key.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ease {
+    Linear,
+    // Hermite smoothstep: slow out of one key and into the next.
+    Smooth,
+    // CSS-style cubic Bezier through (0, 0), (x1, y1), (x2, y2), (1, 1).
+    Bezier(f32, f32, f32, f32),
+    // Hold the key until the next one.
+    Step,
+}
+
+impl Ease {
+    pub fn apply(self, u: f32) -> f32 {
+        match self {
+            Ease::Linear => u,
+            Ease::Smooth => u * u * (3. - 2. * u),
+            Ease::Bezier(x1, y1, x2, y2) => {
+                let s = bezier_solve(x1, x2, u);
+                bezier(y1, y2, s)
+            }
+            Ease::Step => 0.,
+        }
+    }
+
+    fn to_cos(self) -> String {
+        match self {
+            Ease::Linear => "LINEAR".to_string(),
+            Ease::Smooth => "SMOOTH".to_string(),
+            Ease::Bezier(x1, y1, x2, y2) => format!(
+                "BEZIER {} {} {} {}",
+                fmt_num(x1),
+                fmt_num(y1),
+                fmt_num(x2),
+                fmt_num(y2)
+            ),
+            Ease::Step => "STEP".to_string(),
+        }
+    }
+}
+
+// One coordinate of the Bezier with end points 0 and 1 at parameter `s`.
+fn bezier(c1: f32, c2: f32, s: f32) -> f32 {
+    let r = 1. - s;
+    3. * r * r * s * c1 + 3. * r * s * s * c2 + s * s * s
+}
+
+// The parameter at which the x coordinate reaches `x`. x(s) only grows while
+// the control points stay within [0, 1], which the loader checks, so
+// bisection always finds it.
+fn bezier_solve(x1: f32, x2: f32, x: f32) -> f32 {
+    let (mut lo, mut hi) = (0f32, 1f32);
+    for _ in 0..32 {
+        let mid = 0.5 * (lo + hi);
+        if bezier(x1, x2, mid) < x {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Key {
+    pub t: f32,
+    pub pos: Vec3,
+    pub rot: Quat,
+    pub scale: Vec3,
+    // Easing towards the next key.
+    pub ease: Ease,
+}
+
+#[derive(Clone, Default)]
+pub struct Track {
+    // In increasing order of `t`.
+    pub keys: Vec<Key>,
+    pub looped: bool,
+}
+
+impl Track {
+    // The offset at time `t`, as a transform. Identity for a track with no
+    // keys.
+    pub fn offset(&self, t: f32) -> Transform {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Transform::identity(),
+        };
+        let span = last.t - first.t;
+        let t = if self.looped && span > 0. {
+            first.t + (t - first.t).rem_euclid(span)
+        } else {
+            t.clamp(first.t, last.t)
+        };
+        // The segment [a, b] holding `t`.
+        let i = self.keys.partition_point(|k| k.t <= t).saturating_sub(1);
+        let a = &self.keys[i];
+        let b = match self.keys.get(i + 1) {
+            Some(b) => b,
+            None => a,
+        };
+        let u = if b.t > a.t {
+            a.ease.apply((t - a.t) / (b.t - a.t))
+        } else {
+            0.
+        };
+        Transform {
+            rotation: a.rot.slerp(b.rot, u),
+            translation: a.pos.lerp(b.pos, u),
+            scale: a.scale.lerp(b.scale, u),
+        }
+    }
+
+    // `rest` moved by the offset at `t`.
+    pub fn transform_at(&self, t: f32, rest: &Transform) -> Transform {
+        let k = self.offset(t);
+        Transform {
+            rotation: k.rotation * rest.rotation,
+            translation: rest.translation + k.translation,
+            scale: rest.scale * k.scale,
+        }
+    }
+
+    pub fn point_at(&self, t: f32, rest: Vec3) -> Vec3 {
+        rest + self.offset(t).translation
+    }
+
+    pub fn direction_at(&self, t: f32, rest: Vec3) -> Vec3 {
+        self.offset(t).rotation * rest
+    }
+
+    // The track as `KEY` lines, plus `TRACK LOOP` when it loops.
+    pub fn to_cos(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for k in &self.keys {
+            let (axis, rad) = axis_angle(k.rot);
+            let scale = if k.scale.x == k.scale.y && k.scale.y == k.scale.z {
+                fmt_num(k.scale.x)
+            } else {
+                fmt_vec3(k.scale)
+            };
+            lines.push(format!(
+                "KEY {} {} {} {} {} {}",
+                fmt_num(k.t),
+                fmt_vec3(k.pos),
+                fmt_deg(rad),
+                fmt_vec3(axis),
+                scale,
+                k.ease.to_cos()
+            ));
+        }
+        if self.looped {
+            lines.push("TRACK LOOP".to_string());
+        }
+        lines
+    }
+}
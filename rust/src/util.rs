@@ -1,5 +1,8 @@
 use std::cmp::Ordering;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 
 use glam::{Quat, Vec2, Vec3};
 
@@ -11,10 +14,15 @@ pub struct Ray {
     pub d: Vec3,
 }
 
+// Object space to world space: scale (per axis), then rotate, then
+// translate. Points map exactly both ways. Directions and normals come out
+// unit length; normals go through the inverse transpose (the inverse scale),
+// so they stay perpendicular to surfaces under a non-uniform scale.
 #[derive(Clone, Copy)]
 pub struct Transform {
     pub rotation: Quat,
     pub translation: Vec3,
+    pub scale: Vec3,
 }
 
 impl Transform {
@@ -22,23 +30,48 @@ impl Transform {
         Transform {
             rotation: Quat::IDENTITY,
             translation: Vec3::ZERO,
+            scale: Vec3::ONE,
         }
     }
 
+    pub fn is_identity(&self) -> bool {
+        self.rotation == Quat::IDENTITY && self.translation == Vec3::ZERO && self.scale == Vec3::ONE
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.scale.x == self.scale.y && self.scale.y == self.scale.z
+    }
+
     pub fn object_to_world_point(&self, p: Vec3) -> Vec3 {
-        self.rotation * p + self.translation
+        self.rotation * (self.scale * p) + self.translation
     }
 
     pub fn object_to_world_dir(&self, d: Vec3) -> Vec3 {
-        self.rotation * d
+        (self.rotation * (self.scale * d)).normalize()
+    }
+
+    pub fn object_to_world_normal(&self, n: Vec3) -> Vec3 {
+        (self.rotation * (n / self.scale)).normalize()
     }
 
     pub fn world_to_object_point(&self, p: Vec3) -> Vec3 {
-        self.rotation.inverse() * (p - self.translation)
+        self.rotation.inverse() * (p - self.translation) / self.scale
     }
 
     pub fn world_to_object_dir(&self, d: Vec3) -> Vec3 {
-        self.rotation.inverse() * d
+        (self.rotation.inverse() * d / self.scale).normalize()
+    }
+
+    // `self` applied after `other`: object space of `other` -> world space of
+    // `self`. Used to place a transformed object inside a parent frame. Only
+    // exact when `self` has a uniform scale; a per-axis scale followed by a
+    // rotation would shear, which a Transform cannot hold.
+    pub fn compose(&self, other: &Transform) -> Transform {
+        Transform {
+            rotation: self.rotation * other.rotation,
+            translation: self.object_to_world_point(other.translation),
+            scale: self.scale * other.scale,
+        }
     }
 
     // Compose an incremental rotation about the line (axis_pivot, axis_dir).
@@ -51,6 +84,44 @@ impl Transform {
     }
 }
 
+pub fn to_deg(rad: f32) -> f32 {
+    rad * (180. / std::f32::consts::PI)
+}
+
+// A number as written to a `.cos` file: the shortest form that reads back
+// as the same f32, without "-0".
+pub fn fmt_num(x: f32) -> String {
+    if x == 0. {
+        return "0".to_string();
+    }
+    format!("{}", x)
+}
+
+// An angle in radians as written to a `.cos` file, in degrees. Degrees
+// only come back from radians to about 6 significant digits, so that is
+// what is written: enough for the angle as it was typed, and stable when
+// the file is read and written again.
+pub fn fmt_deg(rad: f32) -> String {
+    let deg = format!("{:.5e}", to_deg(rad));
+    fmt_num(deg.parse().unwrap())
+}
+
+// Axis and angle of a rotation, like `Quat::to_axis_angle` but exact for
+// small angles, which that works out from `acos(w)` and loses. The axis is
+// X for no rotation.
+pub fn axis_angle(q: Quat) -> (Vec3, f32) {
+    let v = Vec3::new(q.x, q.y, q.z);
+    let len = v.length();
+    if len == 0. {
+        return (Vec3::X, 0.);
+    }
+    (v / len, 2. * len.atan2(q.w))
+}
+
+pub fn fmt_vec3(v: Vec3) -> String {
+    format!("{} {} {}", fmt_num(v.x), fmt_num(v.y), fmt_num(v.z))
+}
+
 pub fn to_rad(degree: f32) -> f32 {
     degree * (std::f32::consts::PI / 180.)
 }
@@ -84,3 +155,27 @@ pub fn same_dir_file(filename: &str, base_file: &str) -> String {
     let target_path: PathBuf = dir.join(filename);
     target_path.to_string_lossy().to_string()
 }
+
+// Wall-clock time since `start`, for reporting how long loading takes. The
+// browser build has no clock to read, so there it always reads zero.
+#[derive(Clone, Copy)]
+pub struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
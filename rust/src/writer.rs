@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use glam::f32::Vec3;
+
+use crate::engine::{Mesh, Object, Visible};
+use crate::movement::Movement;
+use crate::scene::Scene;
+use crate::util::{axis_angle, fmt_deg, fmt_num, fmt_vec3, Transform};
+
+// Writes a Scene as `.cos` text that loads back into the same scene. The
+// output is canonical rather than a copy of any original file:
+//
+// - cameras first, then lights, then objects, in scene order,
+// - includes are flattened, and objects built from primitives are written
+//   as `OBJ` blocks with numbered points (`p0`, `p1`, ...) and their
+//   placement as `POS`/`ROT`/`SCALE` lines,
+// - meshes read from a file are written as the line that loaded them
+//   (`STL`, `OBJ_FILE`, `PLY`), or as `DEF` plus `INST` lines when they are
+//   shared,
+// - numbers are written in the shortest form that reads back the same, and
+//   angles to the 6 digits they keep through radians.
+//
+// Primitives with no `.cos` form (the points of a PLY cloud that did not
+// come from a file) are left out with a comment saying so.
+pub fn write_scene(scene: &Scene) -> String {
+    let mut w = Writer::default();
+    for camera in &scene.cameras {
+        w.line(camera.to_cos());
+    }
+    for cut in &scene.cuts {
+        let dissolve = if cut.dissolve > 0. {
+            format!(" DISSOLVE {}", fmt_num(cut.dissolve))
+        } else {
+            String::new()
+        };
+        w.line(format!("CUT {} {}{}", fmt_num(cut.t), cut.camera, dissolve));
+    }
+    for light in &scene.lights {
+        w.line(light.to_cos());
+    }
+
+    // Meshes placed more than once have to go through DEF/INST. Names the
+    // scene already uses are kept clear of the ones made up here.
+    let mut uses: HashMap<*const Mesh, usize> = HashMap::new();
+    for obj in &scene.objects {
+        if let Some(o) = obj.as_object() {
+            *uses.entry(Arc::as_ptr(o.mesh())).or_default() += 1;
+            if let Some(name) = o.mesh().name() {
+                w.reserved.insert(name.to_string());
+            }
+        }
+    }
+    for obj in &scene.objects {
+        match obj.as_object() {
+            Some(o) => {
+                let shared = uses[&Arc::as_ptr(o.mesh())] > 1;
+                w.object(o, shared);
+            }
+            None => w.primitives(
+                &[obj.as_ref() as &dyn Visible],
+                &Transform::identity(),
+                None,
+                vec![],
+                &[],
+            ),
+        }
+    }
+    w.out
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    // Point names by the bits of their coordinates, shared by every block.
+    points: HashMap<[u32; 3], String>,
+    // `P` lines for points named since the last block was written.
+    pending: Vec<String>,
+    // Names given to DEF'd meshes so far, by mesh.
+    defs: HashMap<*const Mesh, String>,
+    written: HashSet<String>,
+    // DEF names the scene was loaded with.
+    reserved: HashSet<String>,
+}
+
+impl Writer {
+    fn line(&mut self, line: String) {
+        self.out.push_str(&line);
+        self.out.push('\n');
+    }
+
+    fn object(&mut self, o: &Object, shared: bool) {
+        let mesh = o.mesh();
+        let source = match mesh.source() {
+            Some(source) => source,
+            None => return self.block(o),
+        };
+        let mut placement = placement(o.rest()).concat();
+        placement.push_str(&movement_suffix(o.movement()));
+        if !shared && mesh.name().is_none() {
+            self.line(format!("{}{}", source, placement));
+            self.tail(o);
+            return;
+        }
+
+        let ptr = Arc::as_ptr(mesh);
+        let name = match self.defs.get(&ptr) {
+            Some(name) => name.clone(),
+            None => {
+                let name = self.def_name(mesh.name());
+                self.line(format!("DEF {} {}", name, source));
+                self.defs.insert(ptr, name.clone());
+                name
+            }
+        };
+        self.line(format!("INST {}{}", name, placement));
+        self.tail(o);
+    }
+
+    fn tail(&mut self, o: &Object) {
+        for line in tail_lines(o) {
+            self.line(line);
+        }
+    }
+
+    // The DEF name the mesh was loaded with, or a fresh `meshN`.
+    fn def_name(&mut self, name: Option<&str>) -> String {
+        let name = match name {
+            Some(name) if !self.written.contains(name) => name.to_string(),
+            _ => (0..)
+                .map(|i| format!("mesh{}", i))
+                .find(|n| !self.written.contains(n) && !self.reserved.contains(n))
+                .unwrap(),
+        };
+        self.written.insert(name.clone());
+        name
+    }
+
+    // An object whose mesh has no source as an `OBJ` block, with the
+    // objects nested in it as blocks inside it.
+    fn block(&mut self, o: &Object) {
+        let mesh = o.mesh().primitives();
+        self.primitives(&mesh, o.rest(), o.movement(), tail_lines(o), o.children());
+    }
+
+    // Primitives as an `OBJ` block placed by `t`, holding `nested` and ending
+    // with `extra` lines.
+    fn primitives(
+        &mut self,
+        children: &[&dyn Visible],
+        t: &Transform,
+        m: Option<&dyn Movement>,
+        extra: Vec<String>,
+        nested: &[Object],
+    ) {
+        let mut body = vec![];
+        let mut skipped = 0;
+        for child in children {
+            if let Some(tri) = child.as_triangle() {
+                let (a, b, c) = tri.vertices();
+                let names = [a, b, c].map(|p| self.point(p));
+                body.push(format!(
+                    "T {} {} {} {}",
+                    names[0],
+                    names[1],
+                    names[2],
+                    tri.color()
+                ));
+            } else if let Some(s) = child.as_sphere() {
+                let o = self.point(s.o);
+                body.push(format!("S {} {} {}", o, fmt_num(s.r), s.color));
+            } else if let Some(torus) = child.as_torus() {
+                let (big, small) = torus.radii();
+                body.push(format!(
+                    "TRS {} {} {} {} {}",
+                    fmt_vec3(torus.axis()),
+                    fmt_vec3(torus.center()),
+                    fmt_num(big),
+                    fmt_num(small),
+                    torus.color()
+                ));
+            } else {
+                skipped += 1;
+            }
+        }
+
+        // Points introduced by this block go right before it.
+        let new_points = std::mem::take(&mut self.pending);
+        for line in new_points {
+            self.line(line);
+        }
+        self.line("OBJ".to_string());
+        for clause in placement(t) {
+            self.line(clause.trim_start().to_string());
+        }
+        for line in body {
+            self.line(line);
+        }
+        for child in nested {
+            self.block(child);
+        }
+        if let Some(m) = m {
+            self.line(format!("M {}", m.to_cos()));
+        }
+        for line in extra {
+            self.line(line);
+        }
+        if skipped > 0 {
+            self.line(format!(
+                "// {} primitive{} with no .cos form left out",
+                skipped,
+                if skipped == 1 { "" } else { "s" }
+            ));
+        }
+        self.line("END_OBJ".to_string());
+    }
+
+    fn point(&mut self, p: Vec3) -> String {
+        let key = p.to_array().map(|x| x.to_bits());
+        if let Some(name) = self.points.get(&key) {
+            return name.clone();
+        }
+        let name = format!("p{}", self.points.len());
+        self.pending.push(format!("P {} {}", name, fmt_vec3(p)));
+        self.points.insert(key, name.clone());
+        name
+    }
+}
+
+// The `NAME` and `KEY` lines that follow an object.
+fn tail_lines(o: &Object) -> Vec<String> {
+    let mut lines: Vec<String> = o
+        .name()
+        .map(|n| format!("NAME {}", n))
+        .into_iter()
+        .collect();
+    lines.extend(o.track().map(|t| t.to_cos()).unwrap_or_default());
+    lines
+}
+
+// ` POS ..`, ` ROT ..` and ` SCALE ..` clauses for the parts of `t` that are
+// not the identity.
+fn placement(t: &Transform) -> Vec<String> {
+    let mut clauses = vec![];
+    if t.translation != Vec3::ZERO {
+        clauses.push(format!(" POS {}", fmt_vec3(t.translation)));
+    }
+    let (axis, rad) = axis_angle(t.rotation);
+    if rad != 0. {
+        clauses.push(format!(" ROT {} {}", fmt_deg(rad), fmt_vec3(axis)));
+    }
+    if t.is_uniform() {
+        if t.scale.x != 1. {
+            clauses.push(format!(" SCALE {}", fmt_num(t.scale.x)));
+        }
+    } else {
+        clauses.push(format!(" SCALE {}", fmt_vec3(t.scale)));
+    }
+    clauses
+}
+
+fn movement_suffix(m: Option<&dyn Movement>) -> String {
+    match m {
+        Some(m) => format!(" {}", m.to_cos()),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,286 @@
+// Bounding boxes of the primitives and objects, and the BVH built from them:
+// every hit has to land inside the box of what was hit, and tracing through
+// the BVH (--aabb) has to give the same hits as the linear scan.
+
+use glam::{Quat, Vec3};
+
+use cosmo::aabb::AABB;
+use cosmo::bvh::Tlas;
+use cosmo::engine::{Object, Sphere, Thing, Torus, Triangle, Updatable, Visible};
+use cosmo::movement::{stack, Movement, Spin, Translate};
+use cosmo::util::{Ray, Transform};
+
+const EPS: f32 = 1e-3;
+
+// A grid of `n` by `n` rays from `eye` through a square of half-size `half`
+// around `target`.
+fn rays(eye: Vec3, target: Vec3, half: f32, n: usize) -> Vec<Ray> {
+    let forward = (target - eye).normalize();
+    let right = forward.cross(Vec3::Z).normalize();
+    let up = right.cross(forward);
+    let mut rays = vec![];
+    for i in 0..n {
+        for j in 0..n {
+            let u = (i as f32 / (n - 1) as f32 * 2. - 1.) * half;
+            let v = (j as f32 / (n - 1) as f32 * 2. - 1.) * half;
+            let aim = target + right * u + up * v;
+            rays.push(Ray {
+                p: eye,
+                d: (aim - eye).normalize(),
+            });
+        }
+    }
+    rays
+}
+
+// Rays at `target` from all around it.
+fn rays_around(target: Vec3, dist: f32, half: f32) -> Vec<Ray> {
+    let eyes = [
+        Vec3::new(1., 0.3, 0.2),
+        Vec3::new(-0.4, 1., -0.3),
+        Vec3::new(0.2, -0.5, 1.),
+        Vec3::new(-1., -1., -1.),
+        Vec3::new(0.5, -1., 0.1),
+    ];
+    eyes.iter()
+        .flat_map(|e| rays(target + e.normalize() * dist, target, half, 25))
+        .collect()
+}
+
+fn contains(aabb: &AABB, p: Vec3) -> bool {
+    p.cmpge(aabb.min() - Vec3::splat(EPS)).all() && p.cmple(aabb.max() + Vec3::splat(EPS)).all()
+}
+
+fn bounds_of(thing: &dyn Visible) -> AABB {
+    let mut aabb = AABB::new();
+    thing.update_aabb(&mut aabb);
+    aabb
+}
+
+// Asserts every hit of `rays` on `thing` is inside its box, and that there
+// are hits at all.
+fn assert_hits_inside(thing: &dyn Visible, rays: &[Ray]) {
+    let aabb = bounds_of(thing);
+    assert!(!aabb.is_empty());
+    let mut hits = 0;
+    for ray in rays {
+        if let Some((p, _, _)) = thing.intersect(ray) {
+            assert!(
+                contains(&aabb, p),
+                "hit {} outside {} .. {}",
+                p,
+                aabb.min(),
+                aabb.max()
+            );
+            hits += 1;
+        }
+    }
+    assert!(hits > 0, "no ray hit");
+}
+
+// Triangles, spheres and tori all mixed up, some overlapping, enough of them
+// for the BVH to split into several leaves.
+fn mixed() -> Vec<Box<dyn Thing>> {
+    let mut children: Vec<Box<dyn Thing>> = vec![];
+    for k in 0..4 {
+        let o = Vec3::new(k as f32 * 3. - 4.5, (k % 2) as f32 * 2. - 1., 0.);
+        children.push(Box::new(Triangle::new(
+            o + Vec3::new(-2., -1., -1.),
+            o + Vec3::new(2., -1., 0.),
+            o + Vec3::new(0., 2., 1.),
+            '#',
+        )));
+        children.push(Box::new(Sphere {
+            o: o + Vec3::new(0.5, 0., 0.5),
+            r: 1. + k as f32 * 0.2,
+            color: '.',
+        }));
+        children.push(Box::new(Torus::new(
+            Vec3::new(k as f32, 1., 2.).normalize(),
+            o + Vec3::new(0., 0.5, -0.5),
+            1.5,
+            0.4,
+            '*',
+            false,
+        )));
+    }
+    children
+}
+
+fn object(enable_aabb: bool, m: Option<Box<dyn Movement>>) -> Object {
+    let mut obj = Object::new(mixed(), m, enable_aabb, false);
+    obj.set_transform(Transform {
+        rotation: Quat::from_axis_angle(Vec3::new(1., 1., 0.).normalize(), 0.7),
+        translation: Vec3::new(3., -2., 1.),
+        scale: Vec3::new(1.5, 0.8, 1.2),
+    });
+    obj
+}
+
+fn movement() -> Option<Box<dyn Movement>> {
+    stack(vec![
+        Box::new(Spin {
+            rad: 0.9,
+            d: Vec3::new(0.2, 0.3, 1.).normalize(),
+            center: Vec3::ZERO,
+        }),
+        Box::new(Translate {
+            v: Vec3::new(1., -0.5, 0.25),
+        }),
+    ])
+}
+
+fn assert_same_hits(a: &dyn Visible, b: &dyn Visible, rays: &[Ray]) {
+    let mut hits = 0;
+    for ray in rays {
+        match (a.intersect(ray), b.intersect(ray)) {
+            (Some((p, _, c)), Some((q, _, d))) => {
+                assert!(p.distance(q) < EPS, "hits differ: {} and {}", p, q);
+                assert_eq!(c, d);
+                hits += 1;
+            }
+            (None, None) => {}
+            (x, y) => panic!(
+                "one hit and one miss: {:?} and {:?}",
+                x.map(|h| h.0),
+                y.map(|h| h.0)
+            ),
+        }
+    }
+    assert!(hits > 0, "no ray hit");
+}
+
+#[test]
+fn sphere_hits_are_inside_its_box() {
+    let sphere = Sphere {
+        o: Vec3::new(1., -2., 3.),
+        r: 2.5,
+        color: '.',
+    };
+    assert_hits_inside(&sphere, &rays_around(sphere.o, 10., 3.));
+}
+
+#[test]
+fn torus_hits_are_inside_its_box() {
+    for d in [
+        Vec3::Z,
+        Vec3::X,
+        Vec3::new(1., 1., 0.),
+        Vec3::new(0.3, -0.6, 1.),
+    ] {
+        let p = Vec3::new(-1., 2., 0.5);
+        let torus = Torus::new(d.normalize(), p, 3., 0.8, '*', false);
+        assert_hits_inside(&torus, &rays_around(p, 12., 4.5));
+    }
+}
+
+#[test]
+fn triangle_hits_are_inside_its_box() {
+    let a = Vec3::new(0., 0., 0.);
+    let b = Vec3::new(3., 1., -1.);
+    let c = Vec3::new(1., 4., 2.);
+    let tri = Triangle::new(a, b, c, '#');
+    // Triangles are one-sided, so shoot from both sides.
+    let center = (a + b + c) / 3.;
+    let n = (b - a).cross(c - a).normalize();
+    let mut all = rays(center + n * 8., center, 3., 25);
+    all.extend(rays(center - n * 8., center, 3., 25));
+    assert_hits_inside(&tri, &all);
+}
+
+#[test]
+fn object_box_follows_its_transform_and_movement() {
+    let mut obj = object(true, movement());
+    for t in [0., 0.5, 1.7, 4.] {
+        obj.update(t, 0., None);
+        let center = bounds_of(&obj).centroid();
+        assert_hits_inside(&obj, &rays_around(center, 30., 10.));
+    }
+}
+
+#[test]
+fn object_box_covers_nested_objects() {
+    let mut parent = object(true, movement());
+    let mut child = object(true, None);
+    child.set_transform(Transform {
+        translation: Vec3::new(0., 12., -3.),
+        ..Transform::identity()
+    });
+    parent.add_child(child);
+    for t in [0., 1., 2.5] {
+        parent.update(t, 0., None);
+        let aabb = bounds_of(&parent);
+        for obj in parent.subtree() {
+            let inner = bounds_of(obj);
+            assert!(contains(&aabb, inner.min()) && contains(&aabb, inner.max()));
+        }
+        assert_hits_inside(&parent, &rays_around(aabb.centroid(), 40., 15.));
+    }
+}
+
+#[test]
+fn bvh_gives_the_same_hits_as_the_linear_scan() {
+    let with_bvh = object(true, None);
+    let linear = object(false, None);
+    let center = bounds_of(&linear).centroid();
+    assert_same_hits(&with_bvh, &linear, &rays_around(center, 30., 10.));
+}
+
+#[test]
+fn bvh_gives_the_same_hits_as_the_linear_scan_while_moving() {
+    let mut with_bvh = object(true, movement());
+    let mut linear = object(false, movement());
+    for t in [0.3, 1.1, 3.] {
+        with_bvh.update(t, 0., None);
+        linear.update(t, 0., None);
+        let center = bounds_of(&linear).centroid();
+        assert_same_hits(&with_bvh, &linear, &rays_around(center, 30., 10.));
+    }
+}
+
+// Many moving objects, so the top level splits and has to be refitted.
+fn world(enable_aabb: bool) -> Tlas {
+    let objects: Vec<Box<dyn Thing>> = (0..12)
+        .map(|k| {
+            let m: Option<Box<dyn Movement>> = Some(Box::new(Translate {
+                v: Vec3::new((k % 3) as f32 - 1., (k % 4) as f32 * 0.5 - 0.75, 0.3),
+            }));
+            let mut obj = Object::new(mixed(), m, enable_aabb, false);
+            obj.set_transform(Transform {
+                rotation: Quat::from_rotation_z(k as f32),
+                translation: Vec3::new((k % 4) as f32 * 14., (k / 4) as f32 * 12., 0.),
+                scale: Vec3::splat(0.8),
+            });
+            Box::new(obj) as Box<dyn Thing>
+        })
+        .collect();
+    Tlas::new(objects, enable_aabb)
+}
+
+#[test]
+fn tlas_gives_the_same_hits_as_the_linear_scan_after_refitting() {
+    let mut with_bvh = world(true);
+    let mut linear = world(false);
+    for t in [0., 2., 5.] {
+        for w in [&mut with_bvh, &mut linear] {
+            for obj in w.objects_mut() {
+                obj.update(t, 0., None);
+            }
+            w.refit();
+        }
+        let center = Vec3::new(21. + t * 0.5, 12., t * 0.3);
+        let mut hits = 0;
+        for ray in rays_around(center, 60., 30.) {
+            match (with_bvh.intersect(&ray), linear.intersect(&ray)) {
+                (Some((p, _, _)), Some((q, _, _))) => {
+                    assert!(p.distance(q) < EPS, "hits differ: {} and {}", p, q);
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("one hit and one miss"),
+            }
+            assert_eq!(with_bvh.occluded(&ray), linear.occluded(&ray));
+        }
+        assert!(hits > 0, "no ray hit");
+    }
+}
@@ -0,0 +1,96 @@
+// BVH caches: a tree read back from one has to trace like the tree it was
+// written from, and a cache for some other mesh, builder version or just
+// damaged has to be turned down rather than traced through.
+
+use glam::Vec3;
+
+use cosmo::aabb::AABB;
+use cosmo::bvh::Bvh;
+use cosmo::cache::{content_hash, read_cache, write_cache, VERSION};
+use cosmo::engine::{Triangle, Visible};
+use cosmo::util::Ray;
+
+// A bumpy sheet of `2 * n * n` triangles, enough for a deep tree.
+fn sheet(n: usize) -> Vec<Triangle> {
+    let p = |i: usize, j: usize| {
+        let (x, y) = (i as f32, j as f32);
+        Vec3::new(x, y, (x * 0.7).sin() + (y * 0.4).cos())
+    };
+    let mut tris = vec![];
+    for i in 0..n {
+        for j in 0..n {
+            tris.push(Triangle::new(p(i, j), p(i + 1, j), p(i + 1, j + 1), '#'));
+            tris.push(Triangle::new(p(i, j), p(i + 1, j + 1), p(i, j + 1), '#'));
+        }
+    }
+    tris
+}
+
+fn bvh_of(tris: &[Triangle]) -> Bvh {
+    let aabbs: Vec<AABB> = tris
+        .iter()
+        .map(|t| {
+            let mut a = AABB::new();
+            t.update_aabb(&mut a);
+            a
+        })
+        .collect();
+    Bvh::from_bounds(&aabbs)
+}
+
+#[test]
+fn cached_tree_gives_the_same_hits() {
+    let tris = sheet(40);
+    let built = bvh_of(&tris);
+    let hash = content_hash(b"sheet");
+    let read = read_cache(&write_cache(&built, hash), hash, tris.len()).unwrap();
+    assert_eq!(built.to_bytes(), read.to_bytes());
+    let mut hits = 0;
+    for i in 0..30 {
+        for j in 0..30 {
+            let ray = Ray {
+                p: Vec3::new(20., 20., 30.),
+                d: (Vec3::new(i as f32 * 1.3, j as f32 * 1.3, 0.) - Vec3::new(20., 20., 30.))
+                    .normalize(),
+            };
+            let a = built.intersect_with(&ray, |k| tris[k].intersect(&ray));
+            let b = read.intersect_with(&ray, |k| tris[k].intersect(&ray));
+            assert_eq!(a.map(|h| h.0), b.map(|h| h.0));
+            hits += a.is_some() as usize;
+        }
+    }
+    assert!(hits > 0, "no ray hit");
+}
+
+#[test]
+fn stale_caches_are_turned_down() {
+    let tris = sheet(8);
+    let hash = content_hash(b"sheet");
+    let cache = write_cache(&bvh_of(&tris), hash);
+    // Another mesh file, or the same one edited.
+    assert!(read_cache(&cache, content_hash(b"sheet!"), tris.len()).is_err());
+    assert!(read_cache(&cache, hash, tris.len() - 1).is_err());
+    // Another builder.
+    let mut other = cache.clone();
+    other[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    assert!(read_cache(&other, hash, tris.len()).is_err());
+}
+
+#[test]
+fn damaged_caches_are_turned_down() {
+    let tris = sheet(8);
+    let hash = content_hash(b"sheet");
+    let cache = write_cache(&bvh_of(&tris), hash);
+    assert!(read_cache(&cache, hash, tris.len()).is_ok());
+    assert!(read_cache(&cache[..cache.len() - 4], hash, tris.len()).is_err());
+    assert!(read_cache(&cache[..10], hash, tris.len()).is_err());
+    assert!(read_cache(b"not a cache at all", hash, tris.len()).is_err());
+    // An index out of range, and one child in two leaves.
+    let mut bad = cache.clone();
+    let last = bad.len() - 4;
+    bad[last..].copy_from_slice(&(tris.len() as u32).to_le_bytes());
+    assert!(read_cache(&bad, hash, tris.len()).is_err());
+    let first = bad[last - 4..last].to_vec();
+    bad[last..].copy_from_slice(&first);
+    assert!(read_cache(&bad, hash, tris.len()).is_err());
+}
@@ -0,0 +1,342 @@
+// Cameras that move: carried by a movement or a track, turned to face a
+// point or a named object, shooting rays and projecting points from where
+// they are now. `C FOV` cameras, which frame a scene by their field of view
+// whatever the size of the screen, and `C AUTO` cameras, which are placed to
+// fit the scene.
+
+use glam::Vec3;
+
+use cosmo::camera::{auto_camera, Camera};
+use cosmo::player::Player;
+use cosmo::scene::{RenderSettings, Scene, SceneBuilder};
+
+mod common;
+use common::{load, load_in, near};
+
+// The scene's objects and its first camera brought to time `t`.
+fn at(scene: &mut Scene, t: f32) -> &dyn Camera {
+    for obj in &mut scene.objects {
+        obj.update(0., t, None);
+    }
+    scene.cameras[0].update(0., t, &scene.objects);
+    scene.cameras[0].as_ref()
+}
+
+// Rays leave through the cells `project` puts the points they pass through.
+fn consistent(camera: &dyn Camera) {
+    for (i, j) in [(3., 7.), (20., 40.), (35.5, 70.25)] {
+        let ray = camera.ray_at(i, j);
+        let (pj, pi, _) = camera.project(ray.p + ray.d * 12.).unwrap();
+        assert!(
+            (pi - i).abs() < 1e-2 && (pj - j).abs() < 1e-2,
+            "{} {}",
+            i,
+            j
+        );
+    }
+}
+
+#[test]
+fn movements_carry_the_camera() {
+    // Swung a quarter turn round the z axis, facing the way it turned.
+    for lens in ["P -1 0 0 30 0 0 60 2", "O -1 0 0 30 0 0 2"] {
+        let mut scene = load(&format!("C {} R 90 0 0 0 0 0 1", lens));
+        let camera = at(&mut scene, 1.);
+        assert!(near(camera.eye(), Vec3::new(0., 30., 0.)), "{}", lens);
+        assert!(near(camera.forward(), Vec3::NEG_Y), "{}", lens);
+        consistent(camera);
+    }
+}
+
+#[test]
+fn tracks_carry_the_camera() {
+    let mut scene = load("C P -1 0 0 30 0 0 60 2\nKEY 0 0 0 0 0 0 0 1 1\nKEY 2 0 0 8 0 0 0 1 1");
+    let camera = at(&mut scene, 1.);
+    assert!(near(camera.eye(), Vec3::new(30., 0., 4.)));
+    assert!(near(camera.forward(), Vec3::NEG_X));
+}
+
+#[test]
+fn looking_cameras_face_their_target() {
+    // Going straight past the origin while looking at it.
+    let mut scene = load("C P -1 0 0 30 0 0 60 2 T 0 10 0\nLOOK 0 0 0");
+    let camera = at(&mut scene, 3.);
+    assert!(near(camera.eye(), Vec3::new(30., 30., 0.)));
+    assert!(near(camera.forward(), Vec3::new(-1., -1., 0.).normalize()));
+    consistent(camera);
+
+    // Standing still while a named object goes by.
+    let mut scene = load(
+        "\
+C O -1 0 0 30 0 0 2
+LOOK OBJ ball
+P O 0 0 0
+OBJ
+NAME ball
+S O 1 #
+M T 0 0 5
+END_OBJ",
+    );
+    let camera = at(&mut scene, 6.);
+    assert!(near(camera.eye(), Vec3::new(30., 0., 0.)));
+    assert!(near(camera.forward(), Vec3::new(-1., 0., 1.).normalize()));
+    consistent(camera);
+}
+
+#[test]
+fn the_player_shoots_from_where_the_camera_is() {
+    let settings = RenderSettings {
+        w: 40,
+        h: 20,
+        disable_shade: true,
+        ..RenderSettings::default()
+    };
+    let ball = "P O 0 0 0\nOBJ\nS O 3 #\nEND_OBJ";
+    let frame = |camera: &str, t: f32| -> String {
+        let scene = load_in(&format!("{}\n{}", camera, ball), settings).ok();
+        let mut player = Player::new(scene.unwrap()).unwrap();
+        player.seek(t);
+        player
+            .a
+            .iter()
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect()
+    };
+    let moving = frame("C P -1 0 0 30 0 0 60 2 T 0 1 0", 2.);
+    assert_ne!(moving, frame("C P -1 0 0 30 0 0 60 2 T 0 1 0", 0.));
+    assert_eq!(moving, frame("C P -1 0 0 30 2 0 60 2", 2.));
+}
+
+#[test]
+fn looks_need_a_camera_and_a_known_object() {
+    let messages = |text: &str| -> Vec<(usize, usize, String)> {
+        load_in(text, RenderSettings::default())
+            .err()
+            .unwrap_or_else(|| panic!("loads:\n{}", text))
+            .into_iter()
+            .map(|e| (e.line, e.col, e.message))
+            .collect()
+    };
+    assert_eq!(
+        messages("C P -1 0 0 30 0 0 60 2\nLOOK OBJ ball"),
+        vec![(2, 10, "no object named `ball`".to_string())]
+    );
+    assert_eq!(
+        messages("C P -1 0 0 30 0 0 60 2\nP O 0 0 0\nOBJ\nS O 1 #\nEND_OBJ\nLOOK 0 0 0"),
+        vec![(6, 1, "LOOK has to follow a C line".to_string())]
+    );
+}
+
+// Where `p` lands on the screen of the first camera of `text` at w x h, as
+// fractions of the width and height.
+fn on_screen(text: &str, w: usize, h: usize, p: Vec3) -> (f32, f32) {
+    let settings = RenderSettings {
+        w,
+        h,
+        ..RenderSettings::default()
+    };
+    let scene = load_in(text, settings).ok().unwrap();
+    let (j, i, _) = scene.cameras[0].project(p).unwrap();
+    (j / w as f32, i / h as f32)
+}
+
+fn near2(a: (f32, f32), b: (f32, f32)) -> bool {
+    (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+}
+
+#[test]
+fn fov_cameras_frame_the_same_at_any_size() {
+    // 40 degrees from top to bottom, looking at the origin from 10 away.
+    let edge = 10. * 20_f32.to_radians().tan();
+    let camera = "C FOV 10 0 0 0 0 0 0 0 1 40";
+    for (w, h) in [(80, 40), (200, 100)] {
+        assert!(near2(on_screen(camera, w, h, Vec3::ZERO), (0.5, 0.5)));
+        assert!(near2(on_screen(camera, w, h, Vec3::Z * edge), (0.5, 0.)));
+        // As wide as it is high on a screen of cells twice as high as wide.
+        assert!(near2(on_screen(camera, w, h, Vec3::Y * edge), (1., 0.5)));
+    }
+    // Cells as high as wide fit twice as many columns in the same width.
+    let square = "C FOV 10 0 0 0 0 0 0 0 1 40 ASPECT 1";
+    assert!(near2(on_screen(square, 80, 40, Vec3::Z * edge), (0.5, 0.)));
+    assert!(near2(
+        on_screen(square, 80, 40, Vec3::Y * edge),
+        (0.75, 0.5)
+    ));
+}
+
+#[test]
+fn fov_cameras_roll_to_their_up_vector() {
+    let up_z = "C FOV 10 0 0 0 0 0 0 0 1 40";
+    let up_y = "C FOV 10 0 0 0 0 0 0 1 0 40";
+    let above = on_screen(up_z, 80, 40, Vec3::Z);
+    assert!(above.1 < 0.5 && (above.0 - 0.5).abs() < 1e-4);
+    let above = on_screen(up_y, 80, 40, Vec3::Y);
+    assert!(above.1 < 0.5 && (above.0 - 0.5).abs() < 1e-4);
+    // Tilted up a little, the way up goes with it.
+    let tilted = on_screen("C FOV 10 0 0 0 0 0 0 1 1 40", 80, 40, Vec3::new(0., 1., 1.));
+    assert!(tilted.1 < 0.5 && (tilted.0 - 0.5).abs() < 1e-4);
+
+    let scene = load(&format!("{}\n{} ASPECT 1.5", up_z, up_y));
+    assert_eq!(scene.cameras[0].to_cos(), up_z);
+    assert_eq!(scene.cameras[1].to_cos(), format!("{} ASPECT 1.5", up_y));
+}
+
+#[test]
+fn fov_cameras_need_somewhere_to_look() {
+    let messages = |text: &str| -> Vec<(usize, usize, String)> {
+        load_in(text, RenderSettings::default())
+            .err()
+            .unwrap_or_else(|| panic!("loads:\n{}", text))
+            .into_iter()
+            .map(|e| (e.line, e.col, e.message))
+            .collect()
+    };
+    let cases = [
+        (
+            "C FOV 10 0 0 10 0 0 0 0 1 40",
+            14,
+            "camera target is the same as its eye",
+        ),
+        (
+            "C FOV 10 0 0 0 0 0 1 0 0 40",
+            20,
+            "up vector is zero or along the view",
+        ),
+        (
+            "C FOV 10 0 0 0 0 0 0 0 1 180",
+            26,
+            "field of view must be between 0 and 180 degrees",
+        ),
+        (
+            "C FOV 10 0 0 0 0 0 0 0 1 40 ASPECT 0",
+            36,
+            "cell aspect must be positive",
+        ),
+    ];
+    for (text, col, message) in cases {
+        // Followed by the scene having no camera.
+        assert_eq!(messages(text)[0], (1, col, message.to_string()));
+    }
+}
+
+// Spheres, one of them in a block placed and stretched, and a torus.
+const SPREAD: &str = "\
+P A 3 0 0
+P O 0 0 0
+S A 1 #
+OBJ
+S O 1 @
+SCALE 2 1 1
+POS -2 4 1
+END_OBJ
+TRS 0 0 1 0 -3 -1 1.5 0.5 o";
+
+// Every corner of the scene's box is on the screen of its first camera
+// with `margin` of the screen left free, and some corner is right at the
+// edge of that.
+fn fits(scene: &Scene, margin: f32) {
+    let (w, h) = (scene.settings.w as f32, scene.settings.h as f32);
+    let camera = &scene.cameras[0];
+    let mut widest = 0f32;
+    for corner in scene.bounds().corners() {
+        let (j, i, _) = camera.project(corner).unwrap();
+        // How far out from the middle, as a fraction of half the screen.
+        let out = f32::max((j - w / 2.).abs() / (w / 2.), (i - h / 2.).abs() / (h / 2.));
+        widest = widest.max(out);
+    }
+    assert!(
+        (widest * (1. + margin) - 1.).abs() < 1e-3,
+        "out to {} of the screen",
+        widest
+    );
+}
+
+#[test]
+fn auto_cameras_fit_the_scene() {
+    for lens in ["O", "P"] {
+        for (margin, given) in [(0.1, ""), (0.3, " 0.3"), (0., " 0")] {
+            let auto = format!("C AUTO {} 0 0.6 -0.8{}", lens, given);
+            let scene = load(&format!("{}\n{}", auto, SPREAD));
+            fits(&scene, margin);
+            let forward = scene.cameras[0].forward();
+            assert!(near(forward, Vec3::new(0., 0.6, -0.8)), "{}", auto);
+            // Written as given, not as framed.
+            assert_eq!(scene.cameras[0].to_cos(), auto);
+        }
+    }
+    // Looking down the diagonal by default.
+    let scene = load(&format!("C AUTO\n{}", SPREAD));
+    fits(&scene, 0.1);
+    assert!(near(scene.cameras[0].forward(), Vec3::NEG_ONE.normalize()));
+    // A lone number is the margin, and a movement can follow it.
+    let scene = load(&format!("C AUTO P 0.3 T 1 0 0\n{}", SPREAD));
+    fits(&scene, 0.3);
+    assert!(near(scene.cameras[0].forward(), Vec3::NEG_ONE.normalize()));
+
+    // Everything is in the picture.
+    let settings = RenderSettings {
+        disable_shade: true,
+        ..RenderSettings::default()
+    };
+    let scene = load_in(&format!("C AUTO\n{}", SPREAD), settings).ok();
+    let mut player = Player::new(scene.unwrap()).unwrap();
+    player.seek(0.);
+    for color in ['#', '@', 'o'] {
+        assert!(
+            player.a.iter().flatten().any(|&c| c == color),
+            "no {}",
+            color
+        );
+    }
+}
+
+#[test]
+fn auto_cameras_can_be_built_in_rust() {
+    let settings = RenderSettings {
+        w: 60,
+        h: 20,
+        ..RenderSettings::default()
+    };
+    let mut builder = SceneBuilder::new(settings);
+    builder.sphere(Vec3::new(0., 5., 0.), 2., '#').end_object();
+    builder.torus(Vec3::X, Vec3::new(0., -4., 2.), 3., 1., '@');
+    builder.end_object();
+    builder.camera(auto_camera(false, Vec3::NEG_X, 0.2, 60, 20));
+    fits(&builder.build(), 0.2);
+}
+
+#[test]
+fn auto_numbers_are_a_margin_a_direction_or_both() {
+    let messages = |text: &str| -> Vec<(usize, usize, String)> {
+        load_in(text, RenderSettings::default())
+            .err()
+            .unwrap_or_else(|| panic!("loads:\n{}", text))
+            .into_iter()
+            .map(|e| (e.line, e.col, e.message))
+            .collect()
+    };
+    assert_eq!(
+        messages("C AUTO P 0 1")[0],
+        (
+            1,
+            10,
+            "expected a margin `m`, a direction `dx dy dz` or both".to_string()
+        )
+    );
+    assert_eq!(
+        messages("C AUTO 1 0 0 0.2 5")[0],
+        (
+            1,
+            8,
+            "expected a margin `m`, a direction `dx dy dz` or both".to_string()
+        )
+    );
+    assert_eq!(
+        messages("C AUTO O 1 0 0 -0.5")[0],
+        (1, 16, "margin must not be negative".to_string())
+    );
+    assert_eq!(
+        messages("C AUTO -0.5")[0],
+        (1, 8, "margin must not be negative".to_string())
+    );
+}
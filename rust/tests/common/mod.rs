@@ -0,0 +1,44 @@
+// Fixtures shared by the integration tests: a camera for scenes that do not
+// care where they are seen from, a mesh file for them to read, and loading a
+// scene from its text.
+
+// Each test file uses only some of these.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use cosmo::loader::{parse_scene, SceneError};
+use cosmo::scene::{RenderSettings, Scene};
+
+pub const CAMERA: &str = "C P -1 0 0 30 0 0 60 2";
+
+// A unit square in the z = 0 plane, facing +Z, read as `square.obj`.
+pub const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+
+pub fn near(a: Vec3, b: Vec3) -> bool {
+    a.abs_diff_eq(b, 1e-4)
+}
+
+// `text` loaded as it is, with `square.obj` there to be read.
+pub fn load_in(text: &str, settings: RenderSettings) -> Result<Scene, Vec<SceneError>> {
+    let files = HashMap::from([("square.obj".to_string(), SQUARE.as_bytes().to_vec())]);
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    parse_scene(lines, settings, None, files)
+}
+
+pub fn load(text: &str) -> Scene {
+    load_in(text, RenderSettings::default())
+        .unwrap_or_else(|e| panic!("`{}` does not load: {:?}", text, e))
+}
+
+// `text` loaded after CAMERA, so its own lines start at line 2.
+pub fn parse_in(text: &str, settings: RenderSettings) -> Result<Scene, Vec<SceneError>> {
+    load_in(&format!("{}\n{}", CAMERA, text), settings)
+}
+
+pub fn parse(text: &str) -> Scene {
+    parse_in(text, RenderSettings::default())
+        .unwrap_or_else(|e| panic!("`{}` does not load: {:?}", text, e))
+}
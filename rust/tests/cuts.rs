@@ -0,0 +1,136 @@
+// Cuts: the Player shoots through the first camera until the first cut, and
+// switches at each cut's time exactly, fading cell by cell over a dissolve.
+// A camera picked by name holds until the cuts are followed again.
+
+use std::collections::HashMap;
+
+use cosmo::loader::{parse_scene, SceneError};
+use cosmo::player::Player;
+use cosmo::scene::{RenderSettings, Scene};
+
+// Two cameras far apart, each seeing only its own ball.
+const SCENE: &str = "\
+C P -1 0 0 30 0 0 60 2
+NAME a
+C P -1 0 0 30 50 0 60 2
+NAME b
+P A 0 0 0
+P B 0 50 0
+S A 3 #
+S B 3 @";
+
+fn parse(text: &str) -> Result<Scene, Vec<SceneError>> {
+    let settings = RenderSettings {
+        w: 40,
+        h: 20,
+        disable_shade: true,
+        ..RenderSettings::default()
+    };
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    parse_scene(lines, settings, None, HashMap::new())
+}
+
+fn player(cuts: &str) -> Player {
+    let text = format!("{}\n{}", SCENE, cuts);
+    Player::new(parse(&text).unwrap_or_else(|e| panic!("{:?}", e))).unwrap()
+}
+
+// What the camera named `name` shows.
+fn shot(name: &str) -> Vec<Vec<char>> {
+    let mut player = player("");
+    player.seek(0.);
+    assert!(player.set_camera(name));
+    player.a.clone()
+}
+
+fn at(player: &mut Player, t: f32) -> Vec<Vec<char>> {
+    player.seek(t);
+    player.a.clone()
+}
+
+#[test]
+fn cuts_switch_at_their_time() {
+    let (a, b) = (shot("a"), shot("b"));
+    assert!(a.iter().flatten().any(|&c| c == '#'));
+    assert!(!a.iter().flatten().any(|&c| c == '@'));
+    assert!(b.iter().flatten().any(|&c| c == '@'));
+    let mut player = player("CUT 1 b\nCUT 2.5 a");
+    // The first camera until the first cut.
+    assert_eq!(at(&mut player, 0.), a);
+    assert_eq!(at(&mut player, 0.999), a);
+    assert_eq!(at(&mut player, 1.), b);
+    assert_eq!(at(&mut player, 2.499), b);
+    assert_eq!(at(&mut player, 2.5), a);
+    assert_eq!(at(&mut player, 100.), a);
+    // However the time was reached.
+    assert_eq!(at(&mut player, 1.5), b);
+}
+
+#[test]
+fn dissolves_fade_over_from_the_shot_before() {
+    let (a, b) = (shot("a"), shot("b"));
+    let mut player = player("CUT 2 b DISSOLVE 1");
+    assert_eq!(at(&mut player, 2.), a);
+    assert_eq!(at(&mut player, 3.), b);
+    // Part way, every cell is from one shot or the other, and more of them
+    // from the new one as the fade goes on.
+    let mut from_b = 0;
+    for t in [2.1, 2.4, 2.7, 2.99] {
+        let frame = at(&mut player, t);
+        let mut now = 0;
+        for i in 0..frame.len() {
+            for j in 0..frame[i].len() {
+                let c = frame[i][j];
+                assert!(c == a[i][j] || c == b[i][j], "{} at {}", c, t);
+                if c != a[i][j] {
+                    now += 1;
+                }
+            }
+        }
+        assert!(now > from_b, "{} cells switched at {}", now, t);
+        from_b = now;
+    }
+    assert_ne!(at(&mut player, 2.99), b);
+}
+
+#[test]
+fn a_picked_camera_holds_until_the_cuts_are_followed() {
+    let (a, b) = (shot("a"), shot("b"));
+    let mut player = player("CUT 1 b");
+    assert_eq!(player.camera_names(), ["a", "b"]);
+    player.seek(3.);
+    assert!(player.set_camera("a"));
+    assert_eq!(player.a, a);
+    assert_eq!(at(&mut player, 5.), a);
+    assert!(!player.set_camera("c"));
+    player.follow_cuts();
+    assert_eq!(player.a, b);
+}
+
+#[test]
+fn cuts_go_forwards_to_named_cameras() {
+    let messages = |cuts: &str| -> Vec<(usize, usize, String)> {
+        parse(&format!("{}\n{}", SCENE, cuts))
+            .err()
+            .unwrap_or_else(|| panic!("loads:\n{}", cuts))
+            .into_iter()
+            .map(|e| (e.line, e.col, e.message))
+            .collect()
+    };
+    assert_eq!(
+        messages("CUT 1 c"),
+        vec![(9, 7, "no camera named `c`".to_string())]
+    );
+    assert_eq!(
+        messages("CUT 1 b\nCUT 1 a"),
+        vec![(10, 5, "cut times must increase".to_string())]
+    );
+    assert_eq!(
+        messages("CUT 1 b DISSOLVE -1"),
+        vec![(9, 18, "dissolve time cannot be negative".to_string())]
+    );
+    assert_eq!(
+        messages("CUT 1 b FADE 1"),
+        vec![(9, 9, "expected DISSOLVE, found `FADE`".to_string())]
+    );
+}
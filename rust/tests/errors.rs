@@ -0,0 +1,136 @@
+// Scene parse errors: every bad line is reported at once, each with the file,
+// line and column it is about, instead of the loader panicking on the first.
+
+use cosmo::loader::{parse_file, parse_scene, SceneError};
+use cosmo::scene::{RenderSettings, Scene};
+
+mod common;
+use common::CAMERA;
+
+fn parse_with(text: &str, files: &[(&str, &str)]) -> Result<Scene, Vec<SceneError>> {
+    let files = files
+        .iter()
+        .map(|(name, text)| (name.to_string(), text.as_bytes().to_vec()))
+        .collect();
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    parse_scene(lines, RenderSettings::default(), None, files)
+}
+
+// (file, line, column, message) of each error, in the order reported.
+fn errors(text: &str, files: &[(&str, &str)]) -> Vec<(String, usize, usize, String)> {
+    match parse_with(text, files) {
+        Ok(_) => panic!("loads:\n{}", text),
+        Err(errors) => errors
+            .into_iter()
+            .map(|e| (e.file, e.line, e.col, e.message))
+            .collect(),
+    }
+}
+
+fn at(line: usize, col: usize, message: &str) -> (String, usize, usize, String) {
+    ("<scene>".to_string(), line, col, message.to_string())
+}
+
+#[test]
+fn every_bad_line_is_reported() {
+    let text = [
+        CAMERA,
+        "P A 0 0 x",
+        "",
+        "// S A 1 .",
+        "S B 1 .",
+        "  Q 1 2",
+        "P O 0 0 0",
+        "S O 1",
+        "S O 1 #",
+    ]
+    .join("\n");
+    assert_eq!(
+        errors(&text, &[]),
+        vec![
+            at(2, 9, "expected a number, found `x`"),
+            at(5, 3, "unknown point `B`"),
+            at(6, 3, "unknown line type `Q`"),
+            // Past the end of the line when the argument is missing.
+            at(8, 6, "unexpected end of line"),
+        ]
+    );
+}
+
+#[test]
+fn errors_read_like_a_compiler() {
+    let errors = parse_with(&format!("{}\nS A 1 .", CAMERA), &[])
+        .err()
+        .unwrap();
+    assert_eq!(errors[0].to_string(), "<scene>:2:3: unknown point `A`");
+    // The line is kept to print back under the message.
+    assert_eq!(errors[0].source, "S A 1 .");
+}
+
+#[test]
+fn whole_file_errors_have_no_line() {
+    let errors = parse_with("P A 0 0 0\nS A 1 .", &[]).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 0);
+    assert_eq!(
+        errors[0].to_string(),
+        "<scene>: scene has no camera (`C` line)"
+    );
+
+    let errors = parse_file("scenes/no_such_scene.cos", RenderSettings::default())
+        .err()
+        .unwrap();
+    assert_eq!(errors[0].line, 0);
+    assert!(
+        errors[0].message.starts_with("cannot open scene"),
+        "{}",
+        errors[0]
+    );
+}
+
+#[test]
+fn obj_blocks_have_to_be_closed() {
+    let stray = format!("{}\nEND_OBJ", CAMERA);
+    assert_eq!(
+        errors(&stray, &[]),
+        vec![at(2, 1, "END_OBJ without an open OBJ")]
+    );
+
+    // A block an included file leaves open is closed with it, so the lines
+    // after the INCLUDE are not taken into it.
+    let part = "P A 0 0 0\nOBJ\nS A 1 #";
+    let text = format!("{}\nINCLUDE part.cos\nEND_OBJ", CAMERA);
+    assert_eq!(
+        errors(&text, &[("part.cos", part)]),
+        vec![
+            at(2, 9, "`part.cos` ends inside an OBJ block"),
+            at(3, 1, "END_OBJ without an open OBJ"),
+        ]
+    );
+
+    // So is one the scene itself leaves open, nested or not.
+    for open in ["OBJ\nS A 1 #", "OBJ\nOBJ\nS A 1 #\nEND_OBJ"] {
+        let text = format!("{}\nP A 0 0 0\n{}", CAMERA, open);
+        let errors = parse_with(&text, &[]).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "<scene>: `<scene>` ends inside an OBJ block"
+        );
+    }
+}
+
+#[test]
+fn errors_in_included_files_point_into_them() {
+    let part = "P A 0 0 0\nS A 1 #\nS B 1 #";
+    let text = format!("{}\n\nINCLUDE part.cos", CAMERA);
+    assert_eq!(
+        errors(&text, &[("part.cos", part)]),
+        vec![(
+            "part.cos".to_string(),
+            3,
+            3,
+            "unknown point `B`".to_string()
+        )]
+    );
+}
@@ -0,0 +1,301 @@
+// The glTF reader: meshes from an embedded buffer, the node tree kept as
+// nested objects, and cameras and punctual lights turned from glTF's Y-up
+// into the Z-up the scene uses.
+
+use std::collections::HashMap;
+
+use base64::Engine;
+use glam::Vec3;
+
+use cosmo::engine::{find_object, Object};
+use cosmo::gltf::{read_gltf, GltfCamera, GltfLight, GltfScene};
+use cosmo::loader::{parse_scene, SceneError};
+use cosmo::scene::{RenderSettings, Scene, SceneBuilder};
+use cosmo::util::Transform;
+
+mod common;
+use common::{near, CAMERA};
+
+// One triangle, (0, 0, 0), (1, 0, 0) and (0, 1, 0), as a data URI.
+fn triangle_uri() -> String {
+    let mut bin = vec![];
+    for x in [0_f32, 0., 0., 1., 0., 0., 0., 1., 0.] {
+        bin.extend(x.to_le_bytes());
+    }
+    format!(
+        "data:application/octet-stream;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(&bin)
+    )
+}
+
+// One triangle in the glTF XY plane, a camera five units in front of it, a
+// sun pointing straight down and a lamp, with the camera and the sun on
+// nodes of their own under a parent that moves them.
+fn scene() -> GltfScene {
+    let json = format!(
+        r#"{{
+  "asset": {{ "version": "2.0" }},
+  "extensionsUsed": ["KHR_lights_punctual"],
+  "extensions": {{ "KHR_lights_punctual": {{ "lights": [
+    {{ "type": "directional", "intensity": 3 }},
+    {{ "type": "point", "intensity": 40 }}
+  ] }} }},
+  "scene": 0,
+  "scenes": [{{ "nodes": [0, 1, 4] }}],
+  "nodes": [
+    {{ "mesh": 0 }},
+    {{ "translation": [0, 0, 1], "children": [2, 3] }},
+    {{ "camera": 0, "translation": [0, 0, 4] }},
+    {{ "extensions": {{ "KHR_lights_punctual": {{ "light": 0 }} }},
+       "rotation": [-0.70710678, 0, 0, 0.70710678] }},
+    {{ "extensions": {{ "KHR_lights_punctual": {{ "light": 1 }} }},
+       "translation": [1, 2, 3] }}
+  ],
+  "cameras": [{{ "type": "perspective",
+    "perspective": {{ "yfov": 0.8, "znear": 0.1, "aspectRatio": 1.5 }} }}],
+  "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }} }}] }}],
+  "accessors": [{{ "bufferView": 0, "componentType": 5126, "count": 3,
+    "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }}],
+  "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+  "buffers": [{{ "byteLength": 36, "uri": "{}" }}]
+}}"#,
+        triangle_uri()
+    );
+    read_gltf(json.as_bytes(), |uri| Err(format!("no file `{}`", uri))).unwrap()
+}
+
+// A body two up from the origin with an arm one along from it and a hand
+// one further, all placing the one triangle, and a squashed node holding a
+// finger. Next to them, a lamp with no mesh.
+fn model() -> String {
+    format!(
+        r#"{{
+  "asset": {{ "version": "2.0" }},
+  "extensionsUsed": ["KHR_lights_punctual"],
+  "extensions": {{ "KHR_lights_punctual": {{ "lights": [
+    {{ "type": "point", "intensity": 40 }}
+  ] }} }},
+  "scenes": [{{ "nodes": [0, 5] }}],
+  "nodes": [
+    {{ "name": "body", "mesh": 0, "translation": [0, 0, 2], "children": [1, 3] }},
+    {{ "name": "arm", "mesh": 0, "translation": [1, 0, 0], "children": [2] }},
+    {{ "name": "hand", "mesh": 0, "translation": [1, 0, 0] }},
+    {{ "name": "squashed", "scale": [1, 2, 1], "children": [4] }},
+    {{ "name": "finger", "mesh": 0, "translation": [0, 1, 0] }},
+    {{ "name": "lamp", "extensions": {{ "KHR_lights_punctual": {{ "light": 0 }} }} }}
+  ],
+  "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }} }}] }}],
+  "accessors": [{{ "bufferView": 0, "componentType": 5126, "count": 3,
+    "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }}],
+  "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+  "buffers": [{{ "byteLength": 36, "uri": "{}" }}]
+}}"#,
+        triangle_uri()
+    )
+}
+
+fn load(text: &str) -> Result<Scene, Vec<SceneError>> {
+    let files = HashMap::from([("model.gltf".to_string(), model().into_bytes())]);
+    let text = format!("{}\n{}", CAMERA, text);
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    parse_scene(lines, RenderSettings::default(), None, files)
+}
+
+fn names(obj: &Object) -> Vec<Option<&str>> {
+    obj.subtree().iter().map(|o| o.name()).collect()
+}
+
+#[test]
+fn meshes_are_turned_to_z_up() {
+    let scene = scene();
+    assert_eq!(scene.meshes.len(), 1);
+    let t = scene.nodes[0].transform;
+    let [a, b, c] = scene.meshes[0].triangles[0].map(|p| t.object_to_world_point(p));
+    assert!(near(a, Vec3::ZERO) && near(b, Vec3::X) && near(c, Vec3::Z));
+}
+
+#[test]
+fn mesh_nodes_keep_their_tree() {
+    let gltf = read_gltf(model().as_bytes(), |uri| Err(format!("no file `{}`", uri))).unwrap();
+    // The lamp holds no mesh, so it is no node here.
+    assert_eq!(gltf.nodes.len(), 1);
+    let body = &gltf.nodes[0];
+    let arm = &body.children[0];
+    assert_eq!(body.name.as_deref(), Some("body"));
+    assert!(near(body.transform.translation, Vec3::new(0., -2., 0.)));
+    assert_eq!(arm.name.as_deref(), Some("arm"));
+    assert_eq!(arm.transform.translation, Vec3::X);
+    assert_eq!(arm.children[0].name.as_deref(), Some("hand"));
+    // All three place the one mesh.
+    assert_eq!(
+        (body.mesh, arm.mesh, arm.children[0].mesh),
+        (Some(0), Some(0), Some(0))
+    );
+
+    // Scaled per axis, the squashed node cannot carry its scale over to the
+    // finger, so the finger is baked into a mesh of its own.
+    let squashed = &body.children[1];
+    assert!(squashed.children.is_empty());
+    assert_eq!(squashed.transform.scale, Vec3::new(1., 2., 1.));
+    assert_eq!(
+        gltf.meshes[squashed.mesh.unwrap()].triangles,
+        vec![[Vec3::Y, Vec3::new(1., 1., 0.), Vec3::new(0., 2., 0.)]]
+    );
+}
+
+#[test]
+fn gltf_lines_add_one_object_holding_the_nodes() {
+    let text = "GLTF model.gltf POS 0 0 1 T 1 0 0\nNAME model\nTRACK LOOP\nC P -1 0 0 30 0 0 60 2\nLOOK OBJ hand";
+    let scene = load(text).unwrap_or_else(|e| panic!("does not load: {:?}", e));
+    assert_eq!(scene.objects.len(), 1);
+    let model = find_object(&scene.objects, "model").unwrap();
+    assert_eq!(
+        names(model),
+        [
+            Some("model"),
+            Some("body"),
+            Some("arm"),
+            Some("hand"),
+            Some("squashed")
+        ]
+    );
+    // Moved as a whole, the model carries the nodes with it.
+    assert_eq!(model.movement().unwrap().to_cos(), "T 1 0 0");
+    assert!(model.track().unwrap().looped);
+    let hand = find_object(&scene.objects, "hand").unwrap();
+    assert!(near(hand.transform().translation, Vec3::new(2., -2., 1.)));
+    assert_eq!(scene.lights.len(), 1);
+
+    // Names already taken are left off.
+    let scene = load("GLTF model.gltf\nGLTF model.gltf").unwrap();
+    let second = scene.objects[1].as_object().unwrap();
+    assert!(names(second).iter().all(|name| name.is_none()));
+
+    let errors = load("GLTF model.gltf SCALE 1 2 1").err().unwrap();
+    assert_eq!(errors[0].message, "GLTF scale must be uniform");
+}
+
+#[test]
+fn cameras_take_the_node_transforms() {
+    let scene = scene();
+    assert_eq!(scene.cameras.len(), 1);
+    match scene.cameras[0] {
+        GltfCamera::Perspective { p, d, yfov } => {
+            assert!(near(p, Vec3::new(0., -5., 0.)), "{}", p);
+            assert!(near(d, Vec3::Y), "{}", d);
+            assert_eq!(yfov, 0.8);
+        }
+        GltfCamera::Ortho { .. } => panic!("read as orthographic"),
+    }
+}
+
+#[test]
+fn punctual_lights_are_read() {
+    let scene = scene();
+    assert_eq!(scene.lights.len(), 2);
+    match scene.lights[0] {
+        GltfLight::Directional { d, intensity } => {
+            assert!(near(d, Vec3::NEG_Z), "{}", d);
+            assert_eq!(intensity, 3.);
+        }
+        GltfLight::Point { .. } => panic!("sun read as a point light"),
+    }
+    match scene.lights[1] {
+        GltfLight::Point { p, intensity } => {
+            assert!(near(p, Vec3::new(1., -3., 2.)), "{}", p);
+            assert_eq!(intensity, 40.);
+        }
+        GltfLight::Directional { .. } => panic!("lamp read as a sun"),
+    }
+}
+
+#[test]
+fn suns_are_clamped_to_full_brightness() {
+    let mut builder = SceneBuilder::new(RenderSettings::default());
+    let place = Transform::identity();
+    let sun = |intensity| GltfLight::Directional {
+        d: Vec3::NEG_Z,
+        intensity,
+    };
+    builder.gltf_light(&sun(0.5), &place);
+    builder.gltf_light(&sun(1000.), &place);
+    builder.gltf_light(
+        &GltfLight::Point {
+            p: Vec3::Z,
+            intensity: 40.,
+        },
+        &place,
+    );
+    let scene = builder.build();
+    let lights: Vec<_> = scene.lights.iter().map(|l| l.to_cos()).collect();
+    assert_eq!(lights, ["L D 0 0 -1 0.5", "L D 0 0 -1 1", "L P 0 0 1 40"]);
+}
+
+#[test]
+fn ortho_cameras_fit_their_view_height_to_the_frame() {
+    let settings = RenderSettings {
+        w: 80,
+        h: 40,
+        ..RenderSettings::default()
+    };
+    let mut builder = SceneBuilder::new(settings);
+    let camera = GltfCamera::Ortho {
+        p: Vec3::new(10., 0., 0.),
+        d: Vec3::NEG_X,
+        ymag: 3.,
+    };
+    builder.gltf_camera(&camera, &Transform::identity());
+    let scene = builder.build();
+    // `ymag` above the middle is the top of the first row, and as far below
+    // it the bottom of the last.
+    let (_, top, _) = scene.cameras[0].project(Vec3::new(0., 0., 3.)).unwrap();
+    let (_, bottom, _) = scene.cameras[0].project(Vec3::new(0., 0., -3.)).unwrap();
+    assert!(top.abs() < 1e-4, "top at row {}", top);
+    assert!((bottom - 40.).abs() < 1e-4, "bottom at row {}", bottom);
+}
+
+#[test]
+fn indices_out_of_range_are_errors() {
+    let mut bin = vec![];
+    for x in [0_f32, 0., 0., 1., 0., 0., 0., 1., 0.] {
+        bin.extend(x.to_le_bytes());
+    }
+    for i in [0_u16, 1, 5, 0] {
+        bin.extend(i.to_le_bytes());
+    }
+    let json = format!(
+        r#"{{
+  "asset": {{ "version": "2.0" }},
+  "scenes": [{{ "nodes": [0] }}],
+  "nodes": [{{ "mesh": 0 }}],
+  "meshes": [{{ "name": "roof", "primitives": [
+    {{ "attributes": {{ "POSITION": 0 }} }},
+    {{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}
+  ] }}],
+  "accessors": [
+    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+      "min": [0, 0, 0], "max": [1, 1, 0] }},
+    {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+  ],
+  "bufferViews": [
+    {{ "buffer": 0, "byteLength": 36 }},
+    {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+  ],
+  "buffers": [{{ "byteLength": 44, "uri": "data:application/octet-stream;base64,{}" }}]
+}}"#,
+        base64::engine::general_purpose::STANDARD.encode(&bin)
+    );
+    let err = read_gltf(json.as_bytes(), |uri| Err(format!("no file `{}`", uri)));
+    assert_eq!(
+        err.err().as_deref(),
+        Some("mesh `roof` primitive 1: vertex index 5 out of range (has 3 vertices)")
+    );
+}
+
+#[test]
+fn missing_buffers_are_errors() {
+    let json = r#"{ "asset": { "version": "2.0" },
+        "buffers": [{ "byteLength": 36, "uri": "mesh.bin" }] }"#;
+    let err = read_gltf(json.as_bytes(), |uri| Err(format!("no file `{}`", uri)));
+    assert_eq!(err.err(), Some("no file `mesh.bin`".to_string()));
+}
@@ -0,0 +1,162 @@
+// Nested OBJ blocks: a block inside another is a child of it, placed and
+// moved relative to it, and goes wherever it goes; it is hit, drawn, bounded
+// and found by name through its parent.
+
+use glam::Vec3;
+
+use cosmo::engine::{find_object, Object, Visible};
+use cosmo::player::Player;
+use cosmo::scene::{RenderSettings, Scene};
+use cosmo::util::Ray;
+
+mod common;
+use common::{near, parse, parse_in};
+
+// A planet orbiting the sun at the origin and turning as it goes, and a moon
+// orbiting the planet, a quarter turn a second each.
+const SOLAR: &str = "\
+P O 0 0 0
+OBJ
+NAME sun
+S O 2 #
+OBJ
+NAME planet
+S O 1 @
+POS 10 0 0
+M ORB 90 0 0 0 0 0 1
+M SPIN 90 0 0 1
+OBJ
+NAME moon
+S O 0.5 o
+POS 2 0 0
+M ORB 90 0 0 0 0 0 1
+END_OBJ
+END_OBJ
+END_OBJ";
+
+fn at(scene: &Scene, name: &str) -> Vec3 {
+    find_object(&scene.objects, name)
+        .unwrap()
+        .transform()
+        .translation
+}
+
+#[test]
+fn children_go_where_their_parents_go() {
+    let mut scene = parse(SOLAR);
+    // One object at the top, holding the rest.
+    assert_eq!(scene.objects.len(), 1);
+    let sun = scene.objects[0].as_object().unwrap();
+    let names: Vec<_> = sun.subtree().iter().map(|o| o.name().unwrap()).collect();
+    assert_eq!(names, ["sun", "planet", "moon"]);
+    assert!(near(at(&scene, "moon"), Vec3::new(12., 0., 0.)));
+    // Relative to the parent at rest.
+    let moon = find_object(&scene.objects, "moon").unwrap();
+    assert!(near(moon.rest().translation, Vec3::new(2., 0., 0.)));
+
+    // A quarter turn on, the planet has gone round to +y facing along it,
+    // and the moon has gone round the planet in the planet's turned frame.
+    scene.objects[0].update(0., 1., None);
+    assert!(near(at(&scene, "planet"), Vec3::new(0., 10., 0.)));
+    assert!(near(at(&scene, "moon"), Vec3::new(-2., 10., 0.)));
+}
+
+#[test]
+fn children_are_hit_and_bounded_through_their_parent() {
+    let mut scene = parse(SOLAR);
+    scene.objects[0].update(0., 1., None);
+    let sun: &Object = scene.objects[0].as_object().unwrap();
+    // Straight down onto the moon, past the planet.
+    let ray = Ray {
+        p: Vec3::new(-2., 10., 5.),
+        d: Vec3::NEG_Z,
+    };
+    let (p, _, color) = sun.intersect(&ray).unwrap();
+    assert_eq!(color, 'o');
+    assert!(near(p, Vec3::new(-2., 10., 0.5)));
+    // Through the planet and the sun, the planet comes first.
+    let ray = Ray {
+        p: Vec3::new(0., 20., 0.),
+        d: Vec3::NEG_Y,
+    };
+    assert_eq!(sun.intersect(&ray).unwrap().2, '@');
+
+    let bounds = scene.bounds();
+    let (min, max) = (bounds.min(), bounds.max());
+    assert!(near(min, Vec3::new(-2.5, -2., -2.)), "{} to {}", min, max);
+    assert!(near(max, Vec3::new(2., 11., 2.)), "{} to {}", min, max);
+}
+
+#[test]
+fn both_renderers_draw_children_where_they_are() {
+    // A square two levels down, and the same square written out where the
+    // blocks around it put it, its edges clear of the rays.
+    let nested = "\
+P A 0 -1 1
+P B 0 -1 -1
+P C 0 1 -1
+P D 0 1 1
+OBJ
+POS 0 -0.73 0.17
+OBJ
+POS 0 0.3 -0.4
+SCALE 2.1
+T A B C .
+T A C D .
+END_OBJ
+END_OBJ";
+    let flat = "\
+P A 0 -2.53 1.87
+P B 0 -2.53 -2.33
+P C 0 1.67 -2.33
+P D 0 1.67 1.87
+T A B C .
+T A C D .";
+    let frame = |text: &str, raster: bool| -> String {
+        let settings = RenderSettings {
+            w: 40,
+            h: 20,
+            disable_shade: true,
+            raster,
+            ..RenderSettings::default()
+        };
+        let mut player = Player::new(parse_in(text, settings).ok().unwrap()).unwrap();
+        player.seek(0.);
+        player
+            .a
+            .iter()
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect()
+    };
+    for raster in [false, true] {
+        let drawn = frame(nested, raster);
+        assert!(drawn.contains('.'), "nothing drawn (raster {})", raster);
+        assert_eq!(drawn, frame(flat, raster), "raster {}", raster);
+    }
+}
+
+#[test]
+fn blocks_holding_blocks_scale_evenly() {
+    let messages = |text: &str| -> Vec<(usize, usize, String)> {
+        parse_in(text, RenderSettings::default())
+            .err()
+            .unwrap_or_else(|| panic!("loads:\n{}", text))
+            .into_iter()
+            .map(|e| (e.line, e.col, e.message))
+            .collect()
+    };
+    let uneven = "scale of an OBJ block holding OBJ blocks must be uniform".to_string();
+    // Whichever comes first, the scale or the nested block. (The block
+    // turned down leaves its END_OBJ to close the outer one early.)
+    assert_eq!(
+        messages("OBJ\nSCALE 1 2 1\nOBJ\nEND_OBJ\nEND_OBJ")[0],
+        (4, 1, uneven.clone())
+    );
+    assert_eq!(
+        messages("OBJ\nOBJ\nEND_OBJ\nSCALE 1 2 1\nEND_OBJ"),
+        vec![(5, 1, uneven)]
+    );
+    // Either is fine on its own, and a nested block may scale unevenly.
+    parse("OBJ\nSCALE 2\nOBJ\nSCALE 1 2 1\nEND_OBJ\nEND_OBJ");
+    parse("OBJ\nSCALE 1 2 1\nEND_OBJ");
+}
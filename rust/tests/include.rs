@@ -0,0 +1,128 @@
+// INCLUDE: another scene file parsed in place, with point names of its own,
+// put where the placement on the INCLUDE line says, and turned down when it
+// ends up including itself.
+
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+
+use glam::{Quat, Vec3};
+
+use cosmo::engine::Object;
+use cosmo::loader::{parse_scene, SceneError};
+use cosmo::scene::{RenderSettings, Scene};
+
+mod common;
+use common::{near, CAMERA};
+
+fn parse_with(text: &str, files: &[(&str, &str)]) -> Result<Scene, Vec<SceneError>> {
+    let files = files
+        .iter()
+        .map(|(name, text)| (name.to_string(), text.as_bytes().to_vec()))
+        .collect::<HashMap<_, _>>();
+    let text = format!("{}\n{}", CAMERA, text);
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    parse_scene(lines, RenderSettings::default(), None, files)
+}
+
+// (file, line, message) of each error, in the order reported.
+fn errors(text: &str, files: &[(&str, &str)]) -> Vec<(String, usize, String)> {
+    parse_with(text, files)
+        .err()
+        .unwrap_or_else(|| panic!("loads:\n{}", text))
+        .into_iter()
+        .map(|e| (e.file, e.line, e.message))
+        .collect()
+}
+
+fn objects(scene: &Scene) -> Vec<&Object> {
+    scene.objects.iter().filter_map(|o| o.as_object()).collect()
+}
+
+#[test]
+fn include_cycles_are_errors() {
+    let a = "INCLUDE b.cos";
+    let b = "P A 0 0 0\nINCLUDE a.cos";
+    assert_eq!(
+        errors("INCLUDE a.cos", &[("a.cos", a), ("b.cos", b)]),
+        vec![(
+            "b.cos".to_string(),
+            2,
+            "include cycle: a.cos -> b.cos -> a.cos".to_string()
+        )]
+    );
+    assert_eq!(
+        errors("INCLUDE a.cos", &[("a.cos", "INCLUDE a.cos")]),
+        vec![(
+            "a.cos".to_string(),
+            1,
+            "include cycle: a.cos -> a.cos".to_string()
+        )]
+    );
+
+    // The same file included twice side by side is not a cycle.
+    let ball = "P O 0 0 0\nOBJ\nS O 1 #\nEND_OBJ";
+    let scene = parse_with(
+        "INCLUDE ball.cos\nINCLUDE ball.cos POS 5 0 0",
+        &[("ball.cos", ball)],
+    )
+    .unwrap();
+    assert_eq!(objects(&scene).len(), 2);
+}
+
+#[test]
+fn every_file_names_its_own_points() {
+    // The included file's A is its own, and the scene's A is still there
+    // after it.
+    let part = "P A 0 5 0\nOBJ\nS A 1 #\nEND_OBJ";
+    let text = "P A 0 0 0\nINCLUDE part.cos\nOBJ\nS A 1 @\nEND_OBJ";
+    let scene = parse_with(text, &[("part.cos", part)]).unwrap();
+    let spheres: Vec<_> = objects(&scene)
+        .iter()
+        .map(|o| {
+            o.transform()
+                .object_to_world_point(o.mesh().bounds().centroid())
+        })
+        .collect();
+    assert_eq!(spheres, [Vec3::new(0., 5., 0.), Vec3::ZERO]);
+
+    // Neither sees the points of the other.
+    assert_eq!(
+        errors("P A 0 0 0\nINCLUDE part.cos", &[("part.cos", "S A 1 #")]),
+        vec![("part.cos".to_string(), 1, "unknown point `A`".to_string())]
+    );
+    assert_eq!(
+        errors("INCLUDE part.cos\nS B 1 #", &[("part.cos", "P B 0 0 0")]),
+        vec![("<scene>".to_string(), 3, "unknown point `B`".to_string())]
+    );
+}
+
+#[test]
+fn included_files_are_placed_where_the_include_says() {
+    // Turned a quarter about Z, doubled and moved up, the block one along
+    // X in its file ends up two along Y from where the file is put.
+    let part = "P O 0 0 0\nOBJ\nS O 1 #\nPOS 1 0 0\nEND_OBJ";
+    let text = "INCLUDE part.cos POS 0 10 0 ROT 90 0 0 1 SCALE 2";
+    let scene = parse_with(text, &[("part.cos", part)]).unwrap();
+    let t = objects(&scene)[0].transform();
+    assert!(near(t.translation, Vec3::new(0., 12., 0.)));
+    assert!(t
+        .rotation
+        .abs_diff_eq(Quat::from_axis_angle(Vec3::Z, FRAC_PI_2), 1e-6));
+    assert_eq!(t.scale, Vec3::splat(2.));
+
+    // Includes inside includes add up their placements.
+    let outer = "INCLUDE part.cos POS 0 0 3";
+    let text = "INCLUDE outer.cos POS 0 10 0";
+    let scene = parse_with(text, &[("outer.cos", outer), ("part.cos", part)]).unwrap();
+    let t = objects(&scene)[0].transform();
+    assert!(near(t.translation, Vec3::new(1., 10., 3.)));
+
+    assert_eq!(
+        errors("INCLUDE part.cos SCALE 1 2 1", &[("part.cos", part)]),
+        vec![(
+            "<scene>".to_string(),
+            2,
+            "INCLUDE scale must be uniform".to_string()
+        )]
+    );
+}
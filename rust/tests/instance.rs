@@ -0,0 +1,100 @@
+// Mesh instancing: a mesh given a name with DEF is read once, and every INST
+// of it shares the one copy of its triangles, BVH and raster triangles, with
+// only the placement and movement of its own.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use glam::Vec3;
+
+use cosmo::engine::{Object, Visible};
+use cosmo::obj::read_obj;
+use cosmo::scene::{RenderSettings, Scene, SceneBuilder};
+use cosmo::util::{Ray, Transform};
+
+mod common;
+use common::{parse, parse_in, SQUARE};
+
+fn placed(scene: &Scene) -> Vec<&Object> {
+    scene.objects.iter().filter_map(|o| o.as_object()).collect()
+}
+
+// Where a ray straight down onto the z = 0 plane at (x, y) hits `obj`.
+fn hit(obj: &Object, x: f32, y: f32) -> Option<Vec3> {
+    let ray = Ray {
+        p: Vec3::new(x, y, 5.),
+        d: Vec3::NEG_Z,
+    };
+    obj.intersect(&ray).map(|(p, _, _)| p)
+}
+
+#[test]
+fn instances_share_one_mesh() {
+    let text = "DEF square OBJ_FILE square.obj\nINST square\nINST square T 0 0 1";
+    let scene = parse(text);
+    let objects = placed(&scene);
+    assert_eq!(objects.len(), 2);
+    assert!(Arc::ptr_eq(objects[0].mesh(), objects[1].mesh()));
+    assert_eq!(objects[0].mesh().name(), Some("square"));
+    // The rasterizer draws both from the same triangles.
+    assert_eq!(
+        objects[0].raster_tris().as_ptr(),
+        objects[1].raster_tris().as_ptr()
+    );
+    // Read once, however many times it is placed.
+    assert_eq!(scene.mesh_sizes.len(), 1);
+    // Only the second one moves.
+    assert!(objects[0].movement().is_none());
+    assert_eq!(objects[1].movement().unwrap().to_cos(), "T 0 0 1");
+
+    // A mesh line places a mesh of its own.
+    let scene = parse("OBJ_FILE square.obj\nOBJ_FILE square.obj");
+    let objects = placed(&scene);
+    assert!(!Arc::ptr_eq(objects[0].mesh(), objects[1].mesh()));
+}
+
+#[test]
+fn each_instance_is_hit_where_it_is_placed() {
+    let obj = read_obj(Cursor::new(SQUARE)).unwrap();
+    let mut builder = SceneBuilder::new(RenderSettings::default());
+    let mesh = builder.obj_file_mesh(&obj, None);
+    builder.define("square", mesh);
+    let mesh = builder.mesh("square").unwrap();
+    builder.instance(mesh.clone(), Transform::identity(), None);
+    let up = Transform {
+        translation: Vec3::new(0., 5., 0.),
+        ..Transform::identity()
+    };
+    builder.instance(mesh, up, None);
+    let scene = builder.build();
+    let objects = placed(&scene);
+
+    assert_eq!(hit(objects[0], 0.25, 0.5), Some(Vec3::new(0.25, 0.5, 0.)));
+    assert_eq!(hit(objects[0], 0.25, 5.5), None);
+    assert_eq!(hit(objects[1], 0.25, 0.5), None);
+    assert_eq!(hit(objects[1], 0.25, 5.5), Some(Vec3::new(0.25, 5.5, 0.)));
+}
+
+#[test]
+fn meshes_are_defined_once_and_placed_outside_obj_blocks() {
+    let messages = |text: &str| -> Vec<(usize, String)> {
+        parse_in(text, RenderSettings::default())
+            .err()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.line, e.message))
+            .collect()
+    };
+    assert_eq!(
+        messages("INST square"),
+        vec![(2, "unknown mesh `square`".to_string())]
+    );
+    assert_eq!(
+        messages("DEF square OBJ_FILE square.obj\nDEF square OBJ_FILE square.obj"),
+        vec![(3, "mesh `square` is already defined".to_string())]
+    );
+    assert_eq!(
+        messages("DEF square OBJ_FILE square.obj\nOBJ\nINST square\nEND_OBJ"),
+        vec![(4, "`INST` cannot go inside an OBJ block".to_string())]
+    );
+}
@@ -0,0 +1,269 @@
+// Movements are closed-form functions of time: where they put things at a
+// given time, that `*_from` undoes `*_at`, that stepping frame by frame ends
+// where the closed form says, that `to_cos` reads back as the same movement,
+// and that movements given one after another apply in that order.
+
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+
+use glam::Vec3;
+
+use cosmo::loader::parse_scene;
+use cosmo::movement::{stack, Movement, Orbit, Oscillate, Rotate, Scale, Shake, Spin, Translate};
+use cosmo::scene::RenderSettings;
+use cosmo::util::{Ray, Transform};
+use cosmo::writer::write_scene;
+
+mod common;
+use common::near;
+
+// A quarter turn a second about Z through (1, 0, 0).
+fn quarter_turn() -> Ray {
+    Ray {
+        p: Vec3::X,
+        d: Vec3::Z,
+    }
+}
+
+// One of each kind, none of them still at t = 1.3.
+fn all() -> Vec<Box<dyn Movement>> {
+    vec![
+        Box::new(Rotate {
+            rad: FRAC_PI_2,
+            axis: quarter_turn(),
+        }),
+        Box::new(Translate {
+            v: Vec3::new(1., -2., 0.5),
+        }),
+        Box::new(Oscillate {
+            a: Vec3::new(0., 0., 3.),
+            hz: 0.25,
+        }),
+        Box::new(Orbit {
+            rad: FRAC_PI_2,
+            axis: quarter_turn(),
+        }),
+        Box::new(Spin {
+            rad: FRAC_PI_2,
+            d: Vec3::Z,
+            center: Vec3::ZERO,
+        }),
+        Box::new(Shake {
+            amp: 0.6,
+            hz: 3.,
+            seed: 42,
+        }),
+        Box::new(Scale {
+            amp: 0.3,
+            hz: 0.25,
+            center: Vec3::ZERO,
+        }),
+    ]
+}
+
+#[test]
+fn closed_forms_put_things_where_they_should_be() {
+    let [rotate, translate, oscillate, orbit, spin, _, scale]: [Box<dyn Movement>; 7] =
+        all().try_into().ok().unwrap();
+    let p = Vec3::new(2., 0., 0.);
+
+    assert!(near(rotate.point_at(1., p), Vec3::new(1., 1., 0.)));
+    assert!(near(rotate.direction_at(1., Vec3::X), Vec3::Y));
+    assert!(near(translate.point_at(2., p), Vec3::new(4., -4., 1.)));
+    // A quarter of the way through a period is the far end of the swing,
+    // and a whole period is back where it started.
+    assert!(near(oscillate.point_at(1., p), Vec3::new(2., 0., 3.)));
+    assert!(near(oscillate.point_at(4., p), p));
+    // An orbit goes round like a rotation but does not turn.
+    assert!(near(orbit.point_at(1., p), Vec3::new(1., 1., 0.)));
+    assert!(near(orbit.direction_at(1., Vec3::X), Vec3::X));
+    assert!(near(spin.point_at(2., p), -p));
+    assert!(near(spin.direction_at(1., Vec3::X), Vec3::Y));
+    assert!(near(scale.point_at(1., p), p * 1.3));
+}
+
+#[test]
+fn from_undoes_at() {
+    let (p, d) = (Vec3::new(2., -1., 3.), Vec3::new(0., 0.6, 0.8));
+    for m in all() {
+        for t in [0., 0.4, 1.3, 7.] {
+            let moved = m.point_at(t, p);
+            assert!(near(m.point_from(t, moved), p), "{} at {}", m.to_cos(), t);
+            let turned = m.direction_at(t, d);
+            assert!(
+                near(m.direction_from(t, turned), d),
+                "{} at {}",
+                m.to_cos(),
+                t
+            );
+        }
+    }
+}
+
+#[test]
+fn everything_starts_where_it_was_placed() {
+    let p = Vec3::new(2., -1., 3.);
+    let rest = Transform::identity();
+    for m in all() {
+        assert!(near(m.point_at(0., p), p), "{}", m.to_cos());
+        let placed = m.transform_at(0., &rest);
+        assert!(near(placed.object_to_world_point(p), p), "{}", m.to_cos());
+    }
+}
+
+#[test]
+fn transforms_move_the_origin_like_points() {
+    let rest = Transform::identity();
+    for m in all() {
+        let moved = m.transform_at(1.3, &rest);
+        assert!(
+            near(moved.translation, m.point_at(1.3, Vec3::ZERO)),
+            "{}",
+            m.to_cos()
+        );
+    }
+}
+
+#[test]
+fn stepping_ends_where_the_closed_form_does() {
+    let p = Vec3::new(2., -1., 3.);
+    let dt = 1. / 60.;
+    for m in all() {
+        let mut stepped = p;
+        for frame in 0..120 {
+            m.update_point(frame as f32 * dt, dt, &mut stepped);
+        }
+        assert!(near(stepped, m.point_at(2., p)), "{}", m.to_cos());
+    }
+}
+
+#[test]
+fn shakes_repeat_for_the_same_seed() {
+    let shake = |seed| Shake {
+        amp: 0.6,
+        hz: 3.,
+        seed,
+    };
+    let (a, b, c) = (shake(7), shake(7), shake(8));
+    let mut differs = false;
+    for i in 0..50 {
+        let t = i as f32 * 0.13;
+        assert_eq!(a.point_at(t, Vec3::ZERO), b.point_at(t, Vec3::ZERO));
+        differs |= a.point_at(t, Vec3::ZERO) != c.point_at(t, Vec3::ZERO);
+        // Noise of up to amp, less where it was at time 0.
+        assert!(a.point_at(t, Vec3::ZERO).abs().max_element() <= 1.2);
+    }
+    assert!(differs);
+}
+
+#[test]
+fn stacks_apply_in_order() {
+    assert!(stack(vec![]).is_none());
+    let spin_then_move = stack(vec![
+        Box::new(Spin {
+            rad: FRAC_PI_2,
+            d: Vec3::Z,
+            center: Vec3::ZERO,
+        }),
+        Box::new(Translate { v: Vec3::X }),
+    ])
+    .unwrap();
+    let move_then_spin = stack(vec![
+        Box::new(Translate { v: Vec3::X }),
+        Box::new(Spin {
+            rad: FRAC_PI_2,
+            d: Vec3::Z,
+            center: Vec3::ZERO,
+        }),
+    ])
+    .unwrap();
+    assert!(near(
+        spin_then_move.point_at(1., Vec3::X),
+        Vec3::new(1., 1., 0.)
+    ));
+    assert!(near(
+        move_then_spin.point_at(1., Vec3::X),
+        Vec3::new(0., 2., 0.)
+    ));
+    assert_eq!(spin_then_move.to_cos(), "SPIN 90 0 0 1 T 1 0 0");
+}
+
+#[test]
+fn movements_are_written_as_they_were_read() {
+    let movements = [
+        "R 45 1 0 0 0 0 1",
+        "T 1 -2 0.5",
+        "OSC 0 0 3 0.25",
+        "ORB 36 0 0 0 0 0 1",
+        "SPIN 120 0 0 1",
+        "SHAKE 0.6 3 42",
+        "PULSE 0.3 0.25",
+        "SPIN 120 0 0 1 ORB 36 0 0 0 0 0 1",
+    ];
+    for m in movements {
+        let text = format!(
+            "C P -1 0 0 30 0 0 60 2\nP A 0 0 0\nOBJ\nS A 1 .\nM {}\nEND_OBJ\n",
+            m
+        );
+        let lines = text.lines().map(|l| l.to_string()).collect();
+        let scene = parse_scene(lines, RenderSettings::default(), None, HashMap::new())
+            .unwrap_or_else(|e| panic!("`{}` does not load: {:?}", m, e));
+        let written = write_scene(&scene);
+        assert!(
+            written.lines().any(|l| l == format!("M {}", m)),
+            "`{}` written as\n{}",
+            m,
+            written
+        );
+    }
+}
+
+#[test]
+fn movement_lines_stack_in_the_order_given() {
+    let load = |text: &str| {
+        let text = format!("C P -1 0 0 30 0 0 60 2\n{}", text);
+        let lines = text.lines().map(|l| l.to_string()).collect();
+        let mut scene = parse_scene(lines, RenderSettings::default(), None, HashMap::new())
+            .unwrap_or_else(|e| panic!("`{}` does not load: {:?}", text, e));
+        for obj in &mut scene.objects {
+            obj.update(0., 1., None);
+        }
+        for light in &mut scene.lights {
+            light.update(0., 1.);
+        }
+        scene
+    };
+    let body = |movements: &str| {
+        load(&format!(
+            "P O 0 0 0\nOBJ\nS O 1 #\nPOS 5 0 0\n{}\nEND_OBJ",
+            movements
+        ))
+    };
+    let orbit = "ORB 90 0 0 0 0 0 1";
+
+    // Moved along, then carried round; carried round, then moved along.
+    let scene = body(&format!("M T 1 0 0\nM {}", orbit));
+    let obj = scene.objects[0].as_object().unwrap();
+    assert!(near(obj.transform().translation, Vec3::new(0., 6., 0.)));
+    assert_eq!(
+        obj.movement().unwrap().to_cos(),
+        format!("T 1 0 0 {}", orbit)
+    );
+    let scene = body(&format!("M {}\nM T 1 0 0", orbit));
+    let obj = scene.objects[0].as_object().unwrap();
+    assert!(near(obj.transform().translation, Vec3::new(1., 5., 0.)));
+
+    // A spin turns the body about its own center as it goes round.
+    let scene = body(&format!("M SPIN 90 0 0 1\nM {}", orbit));
+    let obj = scene.objects[0].as_object().unwrap();
+    assert!(near(obj.transform().translation, Vec3::new(0., 5., 0.)));
+    assert!(near(obj.transform().rotation * Vec3::X, Vec3::Y));
+
+    // Movements following one another on one line stack the same way.
+    let scene = load(&format!("L P 5 0 0 800 T 1 0 0 {}", orbit));
+    assert!(near(scene.lights[0].get_ray(Vec3::ZERO).d, Vec3::Y));
+    assert_eq!(
+        scene.lights[0].to_cos(),
+        format!("L P 5 0 0 800 T 1 0 0 {}", orbit)
+    );
+}
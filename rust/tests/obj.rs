@@ -0,0 +1,56 @@
+// The Wavefront OBJ reader: indices counted from either end of the vertex
+// list, fan triangulation of polygons, groups, and errors for faces that
+// refer to vertices that are not there.
+
+use std::io::Cursor;
+
+use glam::Vec3;
+
+use cosmo::obj::read_obj;
+
+const SQUARE: &str = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+";
+
+#[test]
+fn negative_indices_count_back_from_the_last_vertex() {
+    let positive = read_obj(Cursor::new(format!("{}f 1 2 3 4\n", SQUARE))).unwrap();
+    let negative = read_obj(Cursor::new(format!("{}f -4 -3 -2 -1\n", SQUARE))).unwrap();
+    assert_eq!(positive.triangles(None), negative.triangles(None));
+    assert_eq!(negative.groups[0].faces[0].v, [0, 1, 2]);
+}
+
+#[test]
+fn negative_indices_are_relative_to_where_the_face_is() {
+    // `-1` is the last vertex read so far, not the last one in the file.
+    let text = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\nf -4 -3 -2\n";
+    let mesh = read_obj(Cursor::new(text)).unwrap();
+    let tris = mesh.triangles(None);
+    assert_eq!(tris.len(), 2);
+    assert_eq!(tris[0], tris[1]);
+    assert!(!tris[1].contains(&Vec3::splat(5.)));
+}
+
+#[test]
+fn polygons_are_fanned_and_groups_kept_apart() {
+    let text = format!(
+        "{}v 2 0 0\nvt 0 0\nvt 1 0\nvn 0 0 1\ng a\nf 1 2 3 4\ng b\nf 2/1/1 5/2/1 3//1\n",
+        SQUARE
+    );
+    let mesh = read_obj(Cursor::new(text)).unwrap();
+    assert_eq!(mesh.num_faces(), 3);
+    assert_eq!(mesh.triangles(Some("a")).len(), 2);
+    assert_eq!(mesh.triangles(Some("b")).len(), 1);
+    assert_eq!(mesh.triangles(None).len(), 3);
+}
+
+#[test]
+fn indices_out_of_range_are_errors() {
+    for face in ["f 1 2 5", "f 0 1 2", "f -5 1 2", "f 1 2 x"] {
+        let text = format!("{}{}\n", SQUARE, face);
+        assert!(read_obj(Cursor::new(text)).is_err(), "`{}` was read", face);
+    }
+}
@@ -0,0 +1,162 @@
+// Spline paths: through their control points at a steady speed, stopping at
+// the end of an open path and going round a closed one, and turning with the
+// path only when asked to.
+
+use glam::Vec3;
+
+use cosmo::movement::{Movement, SplineKind, SplinePath};
+
+fn near(a: Vec3, b: Vec3, eps: f32) -> bool {
+    a.abs_diff_eq(b, eps)
+}
+
+fn square() -> Vec<Vec3> {
+    vec![
+        Vec3::new(10., 0., 0.),
+        Vec3::new(0., 10., 2.),
+        Vec3::new(-10., 0., 0.),
+        Vec3::new(0., -10., -2.),
+    ]
+}
+
+// The closest the path comes to `p`, checked every tenth of a unit.
+fn closest(path: &SplinePath, p: Vec3) -> f32 {
+    let steps = (path.length() * 10.) as usize;
+    (0..=steps)
+        .map(|i| (path.at(i as f32 / 10.).0 - p).length())
+        .fold(f32::MAX, f32::min)
+}
+
+#[test]
+fn catmull_rom_goes_through_every_point() {
+    for closed in [false, true] {
+        let path = SplinePath::new(SplineKind::CatmullRom, square(), closed, 4., false).unwrap();
+        assert!(near(path.at(0.).0, square()[0], 1e-5));
+        for p in square() {
+            assert!(closest(&path, p) < 0.1, "misses {} (closed {})", p, closed);
+        }
+    }
+}
+
+#[test]
+fn bezier_goes_through_its_ends_and_not_its_handles() {
+    let points = vec![
+        Vec3::ZERO,
+        Vec3::new(0., 10., 0.),
+        Vec3::new(10., 10., 0.),
+        Vec3::new(10., 0., 0.),
+    ];
+    let path = SplinePath::new(SplineKind::Bezier, points.clone(), false, 1., false).unwrap();
+    assert!(near(path.at(0.).0, points[0], 1e-5));
+    assert!(near(path.at(path.length()).0, points[3], 1e-4));
+    assert!(closest(&path, points[1]) > 1.);
+    // Straight out of the first point towards its handle.
+    assert!(near(path.at(0.).1, Vec3::Y, 1e-2));
+}
+
+#[test]
+fn speed_is_steady_along_the_arc() {
+    // Unevenly spaced points, so that the spline parameter is not, but not
+    // so uneven that the curve loops back on itself.
+    let points = vec![
+        Vec3::ZERO,
+        Vec3::new(3., 0., 0.),
+        Vec3::new(12., 3., 0.),
+        Vec3::new(14., 7., 1.),
+    ];
+    let path = SplinePath::new(SplineKind::CatmullRom, points, false, 2., false).unwrap();
+    let ds = 0.5;
+    let mut s = 0.;
+    while s + ds < path.length() {
+        let step = (path.at(s + ds).0 - path.at(s).0).length();
+        assert!((step - ds).abs() < 0.02, "{} long at {}", step, s);
+        s += ds;
+    }
+}
+
+#[test]
+fn open_paths_stop_and_closed_ones_go_round() {
+    let open = SplinePath::new(SplineKind::CatmullRom, square(), false, 4., false).unwrap();
+    let end = open.at(open.length()).0;
+    assert!(near(end, square()[3], 1e-4));
+    assert!(near(open.at(open.length() + 20.).0, end, 1e-5));
+
+    let closed = SplinePath::new(SplineKind::CatmullRom, square(), true, 4., false).unwrap();
+    assert!(closed.length() > open.length());
+    for s in [0., 3., 17.5] {
+        let (a, b) = (closed.at(s).0, closed.at(s + closed.length()).0);
+        assert!(near(a, b, 1e-3), "{} against {} at {}", a, b, s);
+    }
+}
+
+#[test]
+fn bodies_ride_the_path() {
+    let speed = 4.;
+    let path = SplinePath::new(SplineKind::CatmullRom, square(), true, speed, true).unwrap();
+    let start = square()[0];
+    for t in [0., 1., 2.5, 6.] {
+        // A body placed on the first point is on the curve, and faces along
+        // it if it started facing along it.
+        let (p, d) = path.at(speed * t);
+        assert!(
+            near(path.point_at(t, start), p, 1e-4),
+            "off the path at {}",
+            t
+        );
+        assert!(near(path.direction_at(t, path.at(0.).1), d, 1e-3));
+        // Anything else moves with it.
+        let q = start + Vec3::Z;
+        assert!(near(path.point_at(t, q), p + Vec3::Z, 1e-4));
+    }
+
+    let level = SplinePath::new(SplineKind::CatmullRom, square(), true, speed, false).unwrap();
+    assert_eq!(level.direction_at(2.5, Vec3::X), Vec3::X);
+}
+
+#[test]
+fn point_counts_are_checked() {
+    let points = |n: usize| vec![Vec3::ZERO; n];
+    assert!(SplinePath::new(SplineKind::CatmullRom, points(1), false, 1., false).is_err());
+    assert!(SplinePath::new(SplineKind::Bezier, points(5), false, 1., false).is_err());
+    assert!(SplinePath::new(SplineKind::Bezier, points(4), true, 1., false).is_err());
+    assert!(SplinePath::new(SplineKind::Bezier, points(6), true, 1., false).is_ok());
+}
+
+#[test]
+fn paths_are_written_as_they_were_read() {
+    let path = SplinePath::new(SplineKind::CatmullRom, square(), true, 4., true).unwrap();
+    assert_eq!(
+        path.to_cos(),
+        "PATH CR 4 10 0 0 0 10 2 -10 0 0 0 -10 -2 4 ALIGN LOOP"
+    );
+}
+
+#[test]
+fn aligned_paths_keep_facing_the_way_they_went() {
+    // Nowhere to go, so no way to face: turned down.
+    let still = vec![Vec3::ONE; 4];
+    assert!(SplinePath::new(SplineKind::CatmullRom, still.clone(), false, 1., true).is_err());
+    assert!(SplinePath::new(SplineKind::CatmullRom, still, false, 1., false).is_ok());
+
+    // A Bezier segment with all four points the same stands the curve
+    // still: here at the end, where an open path stays once it is done.
+    let points = vec![
+        Vec3::ZERO,
+        Vec3::new(10., 0., 0.),
+        Vec3::new(10., 10., 0.),
+        Vec3::new(0., 10., 0.),
+        Vec3::new(0., 10., 0.),
+        Vec3::new(0., 10., 0.),
+        Vec3::new(0., 10., 0.),
+    ];
+    let speed = 2.;
+    let path = SplinePath::new(SplineKind::Bezier, points, false, speed, true).unwrap();
+    let start = path.at(0.).1;
+    let done = path.length() / speed;
+    let last = path.direction_at(done - 0.01, start);
+    assert!(near(last, Vec3::NEG_X, 1e-2), "ends facing {}", last);
+    for t in [done, done + 1., done + 10.] {
+        let d = path.direction_at(t, start);
+        assert!(near(d, last, 1e-2), "{} at {}", d, t);
+    }
+}
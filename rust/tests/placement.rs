@@ -0,0 +1,104 @@
+// Placement: POS, ROT and SCALE set where an OBJ block or a mesh line puts
+// its object before any movement, the movement carrying on from there, and
+// the ray tracer and the rasterizer both draw it there.
+
+use std::f32::consts::FRAC_PI_2;
+
+use glam::{Quat, Vec3};
+
+use cosmo::engine::Object;
+use cosmo::player::Player;
+use cosmo::scene::{RenderSettings, Scene};
+
+mod common;
+use common::{parse, parse_in};
+
+fn first(scene: &Scene) -> &Object {
+    scene.objects[0].as_object().unwrap()
+}
+
+fn frame(text: &str, raster: bool) -> String {
+    let settings = RenderSettings {
+        w: 40,
+        h: 20,
+        disable_shade: true,
+        raster,
+        ..RenderSettings::default()
+    };
+    let mut player = Player::new(parse_in(text, settings).ok().unwrap()).unwrap();
+    player.seek(0.);
+    player
+        .a
+        .iter()
+        .map(|row| row.iter().collect::<String>() + "\n")
+        .collect()
+}
+
+#[test]
+fn obj_blocks_are_placed_wherever_the_lines_are() {
+    let before = parse("P O 0 0 0\nOBJ\nPOS 1 2 3\nROT 90 0 0 1\nS O 1 #\nSCALE 2\nEND_OBJ");
+    let after = parse("P O 0 0 0\nOBJ\nS O 1 #\nSCALE 2\nROT 90 0 0 1\nPOS 1 2 3\nEND_OBJ");
+    for scene in [&before, &after] {
+        let rest = first(scene).rest();
+        assert_eq!(rest.translation, Vec3::new(1., 2., 3.));
+        assert!(rest
+            .rotation
+            .abs_diff_eq(Quat::from_axis_angle(Vec3::Z, FRAC_PI_2), 1e-6));
+        assert_eq!(rest.scale, Vec3::splat(2.));
+    }
+}
+
+#[test]
+fn movements_carry_on_from_the_placement() {
+    let mut scene = parse("P O 0 0 0\nOBJ\nS O 1 #\nPOS 5 0 0\nM T 0 1 0\nEND_OBJ");
+    assert_eq!(first(&scene).transform().translation, Vec3::new(5., 0., 0.));
+    scene.objects[0].update(0., 2., None);
+    assert_eq!(first(&scene).transform().translation, Vec3::new(5., 2., 0.));
+    // At rest it is where it was placed.
+    assert_eq!(first(&scene).rest().translation, Vec3::new(5., 0., 0.));
+}
+
+#[test]
+fn mesh_lines_take_a_placement_before_their_movement() {
+    let scene = parse("OBJ_FILE square.obj POS 0 0 1 SCALE 1 2 3 T 1 0 0");
+    let obj = first(&scene);
+    assert_eq!(obj.rest().translation, Vec3::new(0., 0., 1.));
+    assert_eq!(obj.rest().scale, Vec3::new(1., 2., 3.));
+    assert_eq!(obj.movement().unwrap().to_cos(), "T 1 0 0");
+}
+
+#[test]
+fn both_renderers_draw_it_where_it_was_placed() {
+    // The unit square turned to face the camera and spread over
+    // y in [-2.7, 2.6], z in [-2.2, 3.1], and the same square written out
+    // where that puts it. Its edges fall between the rays, so that rounding
+    // cannot put one on either side.
+    let placed = "OBJ_FILE square.obj POS 0 -2.7 3.1 ROT 90 0 1 0 SCALE 5.3";
+    let written =
+        "P A 0 -2.7 3.1\nP B 0 -2.7 -2.2\nP C 0 2.6 -2.2\nP D 0 2.6 3.1\nT A B C .\nT A C D .";
+    for raster in [false, true] {
+        let drawn = frame(placed, raster);
+        assert!(drawn.contains('.'), "nothing drawn (raster {})", raster);
+        assert_eq!(drawn, frame(written, raster), "raster {}", raster);
+    }
+}
+
+#[test]
+fn scales_are_positive_and_placements_complete() {
+    let messages = |text: &str| -> Vec<(usize, usize, String)> {
+        parse_in(text, RenderSettings::default())
+            .err()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.line, e.col, e.message))
+            .collect()
+    };
+    assert_eq!(
+        messages("OBJ_FILE square.obj SCALE 1 0 1"),
+        vec![(2, 29, "scale must be positive".to_string())]
+    );
+    assert_eq!(
+        messages("OBJ\nPOS 1 2 3 4\nEND_OBJ"),
+        vec![(3, 11, "unexpected `4`".to_string())]
+    );
+}
@@ -0,0 +1,127 @@
+// The PLY reader: the same mesh in ASCII and in both binary byte orders,
+// brightness from colors, and errors rather than panics for list counts and
+// indices a broken file makes up. Point clouds with no spacing between their
+// points still show.
+
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use cosmo::loader::parse_scene;
+use cosmo::ply::{read_ply, PlyMesh};
+use cosmo::scene::{RenderSettings, POINT_SIZE};
+use cosmo::util::Ray;
+
+const VERTICES: [[f32; 3]; 4] = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 2.]];
+
+fn header(format: &str, faces: usize) -> String {
+    format!(
+        "ply\nformat {} 1.0\ncomment made up\nelement vertex 4\nproperty float x\n\
+         property float y\nproperty float z\nproperty uchar red\nproperty uchar green\n\
+         property uchar blue\nelement face {}\nproperty list uchar int vertex_indices\n\
+         end_header\n",
+        format, faces
+    )
+}
+
+// A square with one grey and three white corners, as a quad, in binary.
+fn binary(big_endian: bool) -> Vec<u8> {
+    let format = if big_endian {
+        "binary_big_endian"
+    } else {
+        "binary_little_endian"
+    };
+    let mut data = header(format, 1).into_bytes();
+    for (i, v) in VERTICES.iter().enumerate() {
+        for x in v {
+            data.extend(if big_endian {
+                x.to_be_bytes()
+            } else {
+                x.to_le_bytes()
+            });
+        }
+        let c = if i == 0 { 51 } else { 255 };
+        data.extend([c, c, c]);
+    }
+    data.push(4);
+    for i in [0_i32, 1, 2, 3] {
+        data.extend(if big_endian {
+            i.to_be_bytes()
+        } else {
+            i.to_le_bytes()
+        });
+    }
+    data
+}
+
+fn ascii(face: &str) -> Vec<u8> {
+    let mut text = header("ascii", 1);
+    for (i, v) in VERTICES.iter().enumerate() {
+        let c = if i == 0 { 51 } else { 255 };
+        text.push_str(&format!("{} {} {} {} {} {}\n", v[0], v[1], v[2], c, c, c));
+    }
+    text.push_str(face);
+    text.push('\n');
+    text.into_bytes()
+}
+
+fn assert_square(mesh: &PlyMesh) {
+    let expected: Vec<Vec3> = VERTICES.iter().map(|v| Vec3::from_array(*v)).collect();
+    assert_eq!(mesh.vertices, expected);
+    assert_eq!(mesh.faces, vec![vec![0, 1, 2, 3]]);
+    let b = mesh.brightness.as_ref().unwrap();
+    assert!((b[0] - 0.2).abs() < 1e-6 && b[1..].iter().all(|&l| l == 1.));
+    assert_eq!(mesh.triangles().len(), 2);
+}
+
+#[test]
+fn binary_big_endian_reads_like_ascii() {
+    assert_square(&read_ply(&ascii("4 0 1 2 3")).unwrap());
+    assert_square(&read_ply(&binary(true)).unwrap());
+    assert_square(&read_ply(&binary(false)).unwrap());
+}
+
+#[test]
+fn bad_list_counts_are_errors() {
+    // Far more items than the file could hold, in ASCII and in binary.
+    for face in ["1e19 0 1 2", "200 0 1 2", "-3 0 1 2", "2.5 0 1 2"] {
+        assert!(read_ply(&ascii(face)).is_err(), "`{}` was read", face);
+    }
+    let mut data = binary(true);
+    let count = data.len() - 17;
+    data[count] = 200;
+    assert!(read_ply(&data).is_err());
+}
+
+#[test]
+fn bad_vertex_indices_are_errors() {
+    for face in ["3 0 -1 2", "3 0 1.5 2", "3 0 1 4"] {
+        assert!(read_ply(&ascii(face)).is_err(), "`{}` was read", face);
+    }
+}
+
+#[test]
+fn truncated_files_are_errors() {
+    let data = binary(false);
+    for len in [10, data.len() / 2, data.len() - 1] {
+        assert!(read_ply(&data[..len]).is_err(), "read {} bytes", len);
+    }
+}
+
+#[test]
+fn lone_points_are_drawn() {
+    let ply = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+               property float y\nproperty float z\nend_header\n1 2 3\n";
+    let files = HashMap::from([("point.ply".to_string(), ply.as_bytes().to_vec())]);
+    let text = "C P -1 0 0 30 0 0 60 2\nPLY point.ply";
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    let scene = parse_scene(lines, RenderSettings::default(), None, files).unwrap();
+    let ray = Ray {
+        p: Vec3::new(1., 2., 10.),
+        d: Vec3::NEG_Z,
+    };
+    let (p, _, _) = scene.objects[0]
+        .intersect(&ray)
+        .expect("ray misses the point");
+    assert!((p.z - (3. + POINT_SIZE)).abs() < 1e-4, "hit at {}", p);
+}
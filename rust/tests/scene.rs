@@ -0,0 +1,129 @@
+// Scenes built from Rust code: a Player takes any scene with a camera, and
+// turns down one without instead of aborting the program. Mesh sizes are
+// handed back with the scene for the program to print. Meshes from files are
+// placed as objects of their own, leaving what was given for the object
+// being built to it.
+
+use std::collections::HashMap;
+use std::fs::File;
+
+use glam::Vec3;
+
+use cosmo::camera::OrthoCamera;
+use cosmo::engine::Object;
+use cosmo::loader::parse_scene;
+use cosmo::movement::Translate;
+use cosmo::player::Player;
+use cosmo::scene::{MeshSize, RenderSettings, SceneBuilder};
+use cosmo::stl::read_stl;
+use cosmo::util::Transform;
+
+const TORUS: &str = "scenes/torus/simplify_torus.stl";
+
+fn settings() -> RenderSettings {
+    RenderSettings {
+        w: 20,
+        h: 10,
+        ..RenderSettings::default()
+    }
+}
+
+#[test]
+fn scenes_without_a_camera_are_errors() {
+    let mut builder = SceneBuilder::new(settings());
+    builder.sphere(Vec3::ZERO, 1., '#').end_object();
+    let err = Player::new(builder.build()).err();
+    assert_eq!(err.as_deref(), Some("scene has no camera"));
+}
+
+#[test]
+fn built_scenes_play() {
+    let mut builder = SceneBuilder::new(settings());
+    builder.sphere(Vec3::ZERO, 1., '#').end_object();
+    builder.camera(Box::new(OrthoCamera::new(
+        Vec3::NEG_X,
+        Vec3::new(5., 0., 0.),
+        4.,
+        20,
+        10,
+    )));
+    let mut player = Player::new(builder.build()).unwrap();
+    player.seek(0.);
+    assert_eq!(player.a[5][10], '#');
+}
+
+#[test]
+fn mesh_sizes_come_back_with_the_scene() {
+    let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+    let text = "C P -1 0 0 30 0 0 60 2\nOBJ_FILE square.obj -\nOBJ_FILE square.obj -\n";
+    let files = HashMap::from([("square.obj".to_string(), obj.as_bytes().to_vec())]);
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    let scene = parse_scene(lines, settings(), None, files).unwrap();
+    // The quad counts as the two triangles it is drawn with.
+    let square = MeshSize {
+        vertices: Some(4),
+        faces: 2,
+    };
+    assert_eq!(scene.mesh_sizes, vec![square, square]);
+}
+
+// The faces of the small torus the scenes use.
+fn torus() -> Vec<[Vec3; 3]> {
+    let mut file = File::open(TORUS).unwrap();
+    read_stl(&mut file, |_, _| {}).unwrap()
+}
+
+#[test]
+fn stl_meshes_keep_their_name() {
+    let mut builder = SceneBuilder::new(settings());
+    builder.stl(TORUS, torus(), None);
+    let scene = builder.build();
+    let object = scene.objects[0].as_object().unwrap();
+    assert_eq!(
+        object.mesh().source(),
+        Some("STL scenes/torus/simplify_torus.stl")
+    );
+    // The corners the faces share are counted once.
+    let size = scene.mesh_sizes[0];
+    assert_eq!(size.faces, object.raster_tris().len());
+    let vertices = size.vertices.unwrap();
+    assert!(vertices > 0 && vertices < 3 * size.faces, "{:?}", size);
+}
+
+#[test]
+fn mesh_files_do_not_take_what_was_given_before_them() {
+    let up = Transform {
+        translation: Vec3::Z,
+        ..Transform::identity()
+    };
+    let mut builder = SceneBuilder::new(settings());
+    builder
+        .sphere(Vec3::ZERO, 1., '#')
+        .movement(Box::new(Translate { v: Vec3::X }))
+        .transform(up)
+        .name("ball");
+    builder.stl(TORUS, torus(), None);
+    builder.end_object();
+    let scene = builder.build();
+    let objects: Vec<&Object> = scene.objects.iter().filter_map(|o| o.as_object()).collect();
+    let (torus, ball) = (objects[0], objects[1]);
+    assert!(torus.movement().is_none() && torus.name().is_none());
+    assert!(torus.transform().is_identity());
+    assert_eq!(ball.movement().unwrap().to_cos(), "T 1 0 0");
+    assert_eq!(
+        (ball.name(), ball.transform().translation),
+        (Some("ball"), Vec3::Z)
+    );
+
+    // The same for a movement line above a mesh line: it moves the
+    // primitives at the top level of the file, not the mesh.
+    let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+    let text = "C P -1 0 0 30 0 0 60 2\nP A 0 0 0\nM T 1 0 0\nOBJ_FILE square.obj\nS A 1 #";
+    let files = HashMap::from([("square.obj".to_string(), obj.as_bytes().to_vec())]);
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    let scene = parse_scene(lines, settings(), None, files).unwrap();
+    let square = scene.objects[0].as_object().unwrap();
+    assert!(square.movement().is_none());
+    let ball = scene.objects[1].as_object().unwrap();
+    assert_eq!(ball.movement().unwrap().to_cos(), "T 1 0 0");
+}
@@ -0,0 +1,84 @@
+// Seeking: a frame reached by jumping straight to its time is the frame
+// played up to one step at a time, in either direction, for scenes with
+// movements, paths, keyframes and cuts.
+
+use cosmo::loader::parse_file;
+use cosmo::player::Player;
+use cosmo::scene::RenderSettings;
+
+const SCENES: [&str; 4] = [
+    "scenes/movements.cos",
+    "scenes/path.cos",
+    "scenes/keyframes.cos",
+    "scenes/cuts.cos",
+];
+
+fn load(file: &str) -> Player {
+    let settings = RenderSettings {
+        w: 60,
+        h: 30,
+        fr: 12,
+        ..RenderSettings::default()
+    };
+    let scene = parse_file(file, settings).unwrap_or_else(|e| panic!("{}: {:?}", file, e));
+    Player::new(scene).unwrap()
+}
+
+fn frame(player: &Player) -> String {
+    player
+        .a
+        .iter()
+        .map(|row| row.iter().collect::<String>() + "\n")
+        .collect()
+}
+
+#[test]
+fn seeking_draws_the_frame_stepping_reaches() {
+    for file in SCENES {
+        let mut stepped = load(file);
+        let mut seeked = load(file);
+        stepped.seek(0.);
+        let first = frame(&stepped);
+        let mut moved = false;
+        for _ in 0..40 {
+            stepped.update();
+            moved |= frame(&stepped) != first;
+        }
+        assert!(moved, "{} stands still", file);
+        seeked.seek(stepped.time());
+        assert_eq!(
+            frame(&seeked),
+            frame(&stepped),
+            "{} at {}",
+            file,
+            stepped.time()
+        );
+    }
+}
+
+#[test]
+fn stepping_back_retraces_the_frames() {
+    for file in SCENES {
+        let mut player = load(file);
+        let mut times = vec![];
+        player.seek(0.);
+        for _ in 0..25 {
+            times.push(player.time());
+            player.step(1);
+        }
+        // Jumping away and back again leaves nothing behind.
+        let (end, last) = (player.time(), frame(&player));
+        player.seek(100.);
+        player.seek(end);
+        assert_eq!(frame(&player), last, "{}", file);
+        // Time runs back to within rounding of where it was, and a player
+        // that only ever seeks there draws the same.
+        let mut fresh = load(file);
+        for &t in times.iter().rev() {
+            player.step(-1);
+            assert!((player.time() - t).abs() < 1e-5, "{} at {}", file, t);
+            fresh.seek(player.time());
+            assert_eq!(frame(&player), frame(&fresh), "{} at {}", file, t);
+        }
+    }
+}
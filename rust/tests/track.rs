@@ -0,0 +1,226 @@
+// Keyframe tracks: the eases between keys, holding still or playing over
+// again outside them, turning by slerp, and the KEY and TRACK lines that
+// give objects and lights their tracks.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use glam::{Quat, Vec3};
+
+use cosmo::loader::{parse_scene, SceneError};
+use cosmo::scene::{RenderSettings, Scene};
+use cosmo::track::{Ease, Key, Track};
+use cosmo::util::Transform;
+
+mod common;
+use common::near;
+
+fn key(t: f32, pos: Vec3, ease: Ease) -> Key {
+    Key {
+        t,
+        pos,
+        rot: Quat::IDENTITY,
+        scale: Vec3::ONE,
+        ease,
+    }
+}
+
+// From the origin at t = 1 to (4, 0, 0) at t = 3.
+fn slide(ease: Ease, looped: bool) -> Track {
+    Track {
+        keys: vec![
+            key(1., Vec3::ZERO, ease),
+            key(3., Vec3::new(4., 0., 0.), Ease::Linear),
+        ],
+        looped,
+    }
+}
+
+// The camera goes last, so that it is not what a KEY at the start animates.
+fn parse(text: &str) -> Result<Scene, Vec<SceneError>> {
+    let text = format!("{}\nC P -1 0 0 30 0 0 60 2", text);
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    parse_scene(lines, RenderSettings::default(), None, HashMap::new())
+}
+
+#[test]
+fn eases_run_from_zero_to_one() {
+    let eases = [
+        Ease::Linear,
+        Ease::Smooth,
+        Ease::Bezier(0.42, 0., 0.58, 1.),
+        Ease::Bezier(0.25, 0.1, 0.25, 1.),
+    ];
+    for ease in eases {
+        assert!(ease.apply(0.).abs() < 1e-5, "{:?}", ease);
+        assert!((ease.apply(1.) - 1.).abs() < 1e-5, "{:?}", ease);
+    }
+    assert_eq!(Ease::Linear.apply(0.25), 0.25);
+    assert_eq!(Ease::Smooth.apply(0.25), 0.15625);
+    // Slow out and in, symmetric about the middle.
+    let ease_in_out = Ease::Bezier(0.42, 0., 0.58, 1.);
+    assert!(ease_in_out.apply(0.2) < 0.2);
+    assert!((ease_in_out.apply(0.5) - 0.5).abs() < 1e-4);
+    assert!((ease_in_out.apply(0.3) + ease_in_out.apply(0.7) - 1.).abs() < 1e-4);
+    // The x control points at 0 and 1 make it linear.
+    assert!((Ease::Bezier(0., 0., 1., 1.).apply(0.3) - 0.3).abs() < 1e-4);
+}
+
+#[test]
+fn tracks_hold_or_loop_outside_their_keys() {
+    let held = slide(Ease::Linear, false);
+    assert!(near(held.point_at(0., Vec3::ONE), Vec3::ONE));
+    assert!(near(held.point_at(2.5, Vec3::ZERO), Vec3::new(3., 0., 0.)));
+    assert!(near(held.point_at(10., Vec3::ZERO), Vec3::new(4., 0., 0.)));
+
+    let looped = slide(Ease::Linear, true);
+    assert!(near(
+        looped.point_at(5.5, Vec3::ZERO),
+        Vec3::new(1., 0., 0.)
+    ));
+    assert!(near(
+        looped.point_at(0.5, Vec3::ZERO),
+        Vec3::new(3., 0., 0.)
+    ));
+
+    // A step holds the first key right up to the second.
+    let step = slide(Ease::Step, false);
+    assert!(near(step.point_at(2.99, Vec3::ZERO), Vec3::ZERO));
+    assert!(near(step.point_at(3., Vec3::ZERO), Vec3::new(4., 0., 0.)));
+
+    assert!(Track::default().offset(1.).is_identity());
+}
+
+#[test]
+fn turns_go_the_short_way_at_an_even_rate() {
+    let mut track = slide(Ease::Linear, false);
+    track.keys[1].rot = Quat::from_axis_angle(Vec3::Z, 0.75 * PI);
+    track.keys[1].scale = Vec3::new(3., 1., 1.);
+    for (t, angle) in [(1.5, 0.1875 * PI), (2., 0.375 * PI), (2.5, 0.5625 * PI)] {
+        let d = track.direction_at(t, Vec3::X);
+        assert!(
+            near(d, Vec3::new(angle.cos(), angle.sin(), 0.)),
+            "{} at {}",
+            d,
+            t
+        );
+    }
+    let rest = Transform::identity();
+    assert!(near(
+        track.transform_at(2., &rest).scale,
+        Vec3::new(2., 1., 1.)
+    ));
+}
+
+#[test]
+fn key_lines_animate_what_they_follow() {
+    let scene = parse(
+        "\
+P O 0 0 0
+OBJ
+S O 1 #
+KEY 0 0 0 0 0 0 0 1 1
+KEY 2 0 4 0 90 0 0 1 2 SMOOTH
+TRACK LOOP
+END_OBJ
+L P 5 0 0 800
+KEY 0 0 0 0 0 0 0 1 1 STEP
+KEY 1 0 0 1 0 0 0 1 1",
+    )
+    .ok()
+    .unwrap();
+    let obj = scene.objects[0].as_object().unwrap();
+    let track = obj.track().unwrap();
+    assert!(track.looped);
+    assert_eq!(track.keys[1].ease, Ease::Smooth);
+    assert!(near(track.point_at(1., Vec3::ZERO), Vec3::new(0., 2., 0.)));
+    assert_eq!(
+        track.to_cos(),
+        vec![
+            "KEY 0 0 0 0 0 1 0 0 1 LINEAR",
+            "KEY 2 0 4 0 90 0 0 1 2 SMOOTH",
+            "TRACK LOOP"
+        ]
+    );
+    // The light's track is its own.
+    let written = scene.lights[0].to_cos();
+    assert!(written.contains("KEY 1 0 0 1"), "{}", written);
+}
+
+#[test]
+fn bad_keys_are_errors() {
+    let messages = |text: &str| -> Vec<(usize, usize, String)> {
+        parse(text)
+            .err()
+            .unwrap_or_else(|| panic!("loads:\n{}", text))
+            .into_iter()
+            .map(|e| (e.line, e.col, e.message))
+            .collect()
+    };
+    let obj = |keys: &str| format!("P O 0 0 0\nOBJ\nS O 1 #\n{}\nEND_OBJ", keys);
+    assert_eq!(
+        messages(&obj("KEY 1 0 0 0 0 0 0 1 1\nKEY 1 0 0 0 0 0 0 1 1")),
+        vec![(5, 5, "key times must increase".to_string())]
+    );
+    assert_eq!(
+        messages(&obj("KEY 1 0 0 0 90 0 0 0 1")),
+        vec![(4, 16, "rotation axis is zero".to_string())]
+    );
+    assert_eq!(
+        messages(&obj("KEY 1 0 0 0 0 0 0 1 1 BEZIER 2 0 1 1")),
+        vec![(4, 30, "Bezier x must be in [0, 1]".to_string())]
+    );
+    assert_eq!(
+        messages("KEY 1 0 0 0 0 0 0 1 1"),
+        vec![(
+            1,
+            1,
+            "nothing to animate here; put it in an OBJ block or after an L, C, mesh or INST line"
+                .to_string()
+        )]
+    );
+}
+
+#[test]
+fn keys_only_follow_what_they_animate() {
+    let messages = |text: &str| -> Vec<(usize, String)> {
+        parse(text)
+            .err()
+            .unwrap_or_else(|| panic!("loads:\n{}", text))
+            .into_iter()
+            .map(|e| (e.line, e.message))
+            .collect()
+    };
+    let nothing =
+        "nothing to animate here; put it in an OBJ block or after an L, C, mesh or INST line";
+    // A bare primitive, point or movement line between a camera and a KEY
+    // leaves the KEY with nothing to animate, rather than the camera.
+    let camera = "C P -1 0 0 30 0 0 60 2\nP A 0 0 0\nP B 1 0 0\nP C 0 1 0";
+    for line in [
+        "T A B C #",
+        "S A 1 #",
+        "TRS 0 0 1 0 0 0 2 1 o",
+        "M T 1 0 0",
+        "P D 0 0 1",
+    ] {
+        let text = format!("{}\n{}\nKEY 1 0 0 0 0 0 0 1 1", camera, line);
+        assert_eq!(messages(&text), vec![(6, nothing.to_string())], "{}", line);
+        let text = format!("{}\n{}\nTRACK LOOP", camera, line);
+        assert_eq!(messages(&text), vec![(6, nothing.to_string())], "{}", line);
+    }
+    // The same goes for LOOK and NAME.
+    let text = format!("{}\nT A B C #\nLOOK A", camera);
+    assert_eq!(
+        messages(&text),
+        vec![(6, "LOOK has to follow a C line".to_string())]
+    );
+    let text = format!("{}\nT A B C #\nNAME cam", camera);
+    assert_eq!(
+        messages(&text),
+        vec![(
+            6,
+            "nothing to name here; put it in an OBJ block or after a C, mesh or INST line"
+                .to_string()
+        )]
+    );
+}
@@ -0,0 +1,123 @@
+// Scaled transforms: points map back and forth exactly under a per-axis
+// scale, normals stay perpendicular to the surfaces they belong to, and a
+// scaled object is hit, and drawn in front of or behind others, where its
+// world-space surface is.
+
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+
+use glam::{Quat, Vec3};
+
+use cosmo::engine::{Object, Sphere, Thing, Visible};
+use cosmo::loader::parse_scene;
+use cosmo::player::Player;
+use cosmo::scene::RenderSettings;
+use cosmo::util::{Ray, Transform};
+
+mod common;
+use common::near;
+
+fn squash() -> Transform {
+    Transform {
+        rotation: Quat::from_axis_angle(Vec3::new(1., 1., 0.).normalize(), 0.7),
+        translation: Vec3::new(1., -2., 3.),
+        scale: Vec3::new(3., 1., 0.5),
+    }
+}
+
+#[test]
+fn points_go_back_where_they_came_from() {
+    let t = squash();
+    for p in [Vec3::ZERO, Vec3::ONE, Vec3::new(-2., 5., 0.25)] {
+        assert!(near(t.world_to_object_point(t.object_to_world_point(p)), p));
+    }
+    // Each axis is stretched by its own factor before turning.
+    let plain = Transform {
+        scale: Vec3::new(3., 1., 0.5),
+        ..Transform::identity()
+    };
+    assert_eq!(
+        plain.object_to_world_point(Vec3::ONE),
+        Vec3::new(3., 1., 0.5)
+    );
+}
+
+#[test]
+fn normals_stay_perpendicular_to_their_surface() {
+    let t = squash();
+    // A slanted plane, given by its normal and two directions along it.
+    let n = Vec3::new(1., 1., 1.).normalize();
+    let along = [Vec3::new(1., -1., 0.), Vec3::new(0., 1., -1.)];
+    let world_n = t.object_to_world_normal(n);
+    assert!(world_n.is_normalized());
+    for v in along {
+        let world_v = t.object_to_world_point(v) - t.object_to_world_point(Vec3::ZERO);
+        assert!(
+            world_n.dot(world_v).abs() < 1e-4,
+            "{} against {}",
+            world_n,
+            world_v
+        );
+        // Carried like a direction, the normal would lean over.
+        assert!(t.object_to_world_dir(n).dot(world_v).abs() > 0.1);
+    }
+}
+
+#[test]
+fn scaled_spheres_are_hit_on_their_ellipsoid() {
+    let sphere: Box<dyn Thing> = Box::new(Sphere {
+        o: Vec3::ZERO,
+        r: 1.,
+        color: '#',
+    });
+    let mut obj = Object::new(vec![sphere], None, false, false);
+    obj.set_transform(Transform {
+        rotation: Quat::from_axis_angle(Vec3::Z, FRAC_PI_2),
+        scale: Vec3::new(1., 3., 1.),
+        ..Transform::identity()
+    });
+    // Stretched along y, then turned so that it lies along -x.
+    let shoot = |p: Vec3| obj.intersect(&Ray { p, d: Vec3::NEG_X });
+    let (p, n, _) = shoot(Vec3::new(10., 0., 0.)).unwrap();
+    assert!(near(p, Vec3::new(3., 0., 0.)), "{}", p);
+    assert!(near(n, Vec3::X), "{}", n);
+
+    // Off centre, on x^2 / 9 + y^2 = 1, facing along its gradient.
+    let (p, n, _) = shoot(Vec3::new(10., 0.5, 0.)).unwrap();
+    assert!(near(p, Vec3::new(3. * 0.75_f32.sqrt(), 0.5, 0.)), "{}", p);
+    let gradient = Vec3::new(p.x / 9., p.y, 0.).normalize();
+    assert!(near(n, gradient), "{} against {}", n, gradient);
+}
+
+#[test]
+fn the_nearest_surface_wins_whatever_the_scale() {
+    // A sphere shrunk to radius 1 in front of a bigger one. Its ray
+    // parameter in object space is twice the world one, which would put it
+    // behind the other were hits compared by it. (Ray traced: the
+    // rasterizer draws triangles only.)
+    let text = "\
+C P -1 0 0 30 0 0 60 2
+P O 0 0 0
+P F -10 0 0
+OBJ
+S O 2 #
+SCALE 0.5
+END_OBJ
+OBJ
+S F 4 @
+END_OBJ";
+    let settings = RenderSettings {
+        w: 40,
+        h: 20,
+        disable_shade: true,
+        ..RenderSettings::default()
+    };
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    let scene = parse_scene(lines, settings, None, HashMap::new())
+        .ok()
+        .unwrap();
+    let mut player = Player::new(scene).unwrap();
+    player.seek(0.);
+    assert_eq!(player.a[10][20], '#');
+    assert_eq!(player.a[10][12], '@');
+}
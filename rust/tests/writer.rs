@@ -0,0 +1,122 @@
+// The scene writer: what it writes has to load back into the same scene, so
+// writing that scene again gives the same text, with no digits lost on the
+// way.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use cosmo::loader::{parse_file, parse_scene};
+use cosmo::player::Player;
+use cosmo::scene::{RenderSettings, Scene};
+use cosmo::writer::write_scene;
+
+fn reparse(text: &str, filename: &str) -> Scene {
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    parse_scene(
+        lines,
+        RenderSettings::default(),
+        Some(filename),
+        HashMap::new(),
+    )
+    .unwrap_or_else(|e| panic!("{} does not load back: {:?}\n{}", filename, e, text))
+}
+
+fn scenes(dir: &Path, out: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scenes(&path, out);
+        } else if path.extension().is_some_and(|e| e == "cos") {
+            out.push(path.to_string_lossy().to_string());
+        }
+    }
+}
+
+#[test]
+fn written_scenes_load_back_the_same() {
+    let mut files = vec![];
+    scenes(Path::new("scenes"), &mut files);
+    files.sort();
+    let mut written = 0;
+    for file in &files {
+        // Scenes meant for INCLUDE have no camera of their own.
+        let Ok(scene) = parse_file(file, RenderSettings::default()) else {
+            continue;
+        };
+        // Read back as if it were the original, so that mesh files are found
+        // next to it.
+        let first = write_scene(&scene);
+        let second = write_scene(&reparse(&first, file));
+        assert_eq!(first, second, "{} changes when written again", file);
+        written += 1;
+    }
+    assert!(written > 10, "only {} scenes written", written);
+}
+
+#[test]
+fn numbers_are_written_without_loss() {
+    let text = "\
+C P -1 0 0 30 0 0 60 2
+P A 0 0 8.660254
+P B 1 0 0
+P C 0 1 0
+OBJ
+POS 0.1 0 0
+ROT 33.3 0 0 1
+SCALE 0.000001
+T A B C #
+M SPIN 120 0 0 1
+END_OBJ
+";
+    let first = write_scene(&reparse(text, "<test>"));
+    for number in [
+        "0 0 8.660254",
+        "POS 0.1 0 0",
+        "ROT 33.3 0 0 1",
+        "SCALE 0.000001",
+        "SPIN 120 0 0 1",
+    ] {
+        assert!(first.contains(number), "`{}` lost in\n{}", number, first);
+    }
+    assert_eq!(write_scene(&reparse(&first, "<test>")), first);
+}
+
+#[test]
+fn spinning_lights_in_placed_includes_keep_their_center() {
+    // The lamp turns about the origin of its own file, which the INCLUDE
+    // puts at (0, 10, 0) in the scene.
+    let lamp = "L P 6 0 0 800 SPIN 90 0 0 1\n";
+    let text = "\
+C P -1 0 0 30 0 0 60 2
+INCLUDE lamp.cos POS 0 10 0
+P A 0 0 0
+OBJ
+S A 6 .
+END_OBJ
+";
+    let files = HashMap::from([("lamp.cos".to_string(), lamp.as_bytes().to_vec())]);
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    let settings = RenderSettings {
+        w: 40,
+        h: 20,
+        ..RenderSettings::default()
+    };
+    let scene = parse_scene(lines, settings, None, files).unwrap();
+    let written = write_scene(&scene);
+    assert!(
+        written.contains("L P 6 10 0 800 SPIN 90 0 0 1 AT 0 10 0"),
+        "{}",
+        written
+    );
+
+    let lines = written.lines().map(|l| l.to_string()).collect();
+    let read_back = parse_scene(lines, settings, None, HashMap::new()).unwrap();
+    assert_eq!(write_scene(&read_back), written);
+    let (mut before, mut after) = (Player::new(scene).unwrap(), Player::new(read_back).unwrap());
+    for t in [0.5, 1., 2.5] {
+        before.seek(t);
+        after.seek(t);
+        assert_eq!(before.a, after.a, "at {}", t);
+    }
+}